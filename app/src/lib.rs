@@ -20,6 +20,7 @@ mod dsp;
 mod messages;
 mod settings;
 mod tasks;
+mod transport;
 
 #[derive(Debug, Error)]
 pub enum AppError {
@@ -24,6 +24,8 @@ pub enum ButtonEvent {
 pub enum SipCommand {
     // From button task:
     Button(ButtonEvent),
+    /// Turn do-not-disturb on or off (long press while idle).
+    ToggleDnd,
 }
 
 pub type SipCommandSender = Sender<SipCommand>;
@@ -34,6 +36,38 @@ pub enum AudioCodec {
     Pcmu8k,
 }
 
+/// How an incoming call rings, picked from its Alert-Info through
+/// `sip_ring_patterns`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RingPattern {
+    #[default]
+    Normal,
+    Double,
+    Triple,
+    Urgent,
+}
+
+impl RingPattern {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "normal" => Some(Self::Normal),
+            "double" => Some(Self::Double),
+            "triple" => Some(Self::Triple),
+            "urgent" => Some(Self::Urgent),
+            _ => None,
+        }
+    }
+}
+
+/// Media flowing before a call is answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EarlyMedia {
+    /// Play what the far end sends with its 183 (ringback, announcements).
+    Receive,
+    /// Send the caller our own ringback tone.
+    Ringback,
+}
+
 #[derive(Debug)]
 pub enum AudioCommand {
     /// High-level mode change: Idle/Listen/Talk
@@ -43,6 +77,17 @@ pub enum AudioCommand {
     /// (e.g. play ringback tone vs remote audio)
     SetDialogState(PhoneState),
 
+    /// Early media for the ringing call, if any.
+    EarlyMedia(Option<EarlyMedia>),
+
+    /// Warn with a short beep before playing the call, e.g. when an
+    /// intercom call opened by itself.
+    Beep,
+
+    /// Ring the speaker with this cadence while an incoming call rings,
+    /// or stop (`None`).
+    Ring(Option<RingPattern>),
+
     // TODO: For things like comfort noise generation, tones, etc.,
     // PlayTone(ToneKind)
 }
@@ -59,6 +104,12 @@ pub enum RtpCommand {
         local_ssrc: Option<u32>,
         payload_type: u8,
     },
+    /// Pause or resume either half of the running stream, as the SDP
+    /// directions ask (hold). `StartStream` turns both back on.
+    SetDirection {
+        send: bool,
+        receive: bool,
+    },
     StopStream,
 }
 
@@ -76,6 +127,18 @@ pub enum PhoneState {
 pub enum UiCommand {
     DialogStateChanged(PhoneState),
     RegistrationStateChanged(bool),
+    /// Qualify probes lost (`false`) or regained (`true`) the server.
+    ServerReachabilityChanged(bool),
+    /// A second call is ringing while we're in a call (`true`), or it was
+    /// answered, rejected or gave up (`false`).
+    CallWaiting(bool),
+    /// Every answered call is on hold by us (`true`), or one was picked
+    /// back up or ended (`false`).
+    CallHeld(bool),
+    /// Do-not-disturb went on (`true`), by hand or by schedule, or off.
+    Dnd(bool),
+    /// How the ringing call rings on the LED.
+    RingPattern(RingPattern),
     SetLed(LedState),
 }
 
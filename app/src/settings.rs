@@ -11,10 +11,42 @@ pub struct Settings {
     pub wifi_username: Option<&'static str>,
     pub sip_registrar: &'static str,
     pub sip_contact: &'static str,
+    pub sip_aliases: &'static str,
     pub sip_username: &'static str,
     pub sip_password: &'static str,
     pub sip_target: &'static str,
+    pub sip_transfer_target: &'static str,
+    pub sip_transfer_attended: bool,
+    pub sip_transport: &'static str,
+    pub sip_tls_server_name: &'static str,
+    pub sip_tls_ca: &'static str,
     pub ring_timeout: i64,
+    pub ring_sound: bool,
+    pub sip_ring_patterns: &'static str,
+    pub sip_forward_always: &'static str,
+    pub sip_forward_busy: &'static str,
+    pub sip_forward_no_answer: &'static str,
+    pub sip_forward_no_answer_after: i64,
+    pub sip_forward_callers: &'static str,
+    pub sip_dnd_response: i64,
+    pub sip_dnd_forward: &'static str,
+    pub sip_dnd_allow: &'static str,
+    pub sip_dnd_quiet_hours: &'static str,
+    pub utc_offset_minutes: i64,
+    pub sip_auto_answer_from: &'static str,
+    pub sip_auto_answer_beep: bool,
+    pub sip_anonymous: bool,
+    pub sip_reject_anonymous: bool,
+    pub sip_trusted_proxies: &'static str,
+    pub sip_allow_callers: &'static str,
+    pub sip_deny_callers: &'static str,
+    pub sip_allow_sources: &'static str,
+    pub sip_deny_sources: &'static str,
+    pub sip_deny_user_agents: &'static str,
+    pub sip_rate_limit: i64,
+    pub sip_ban_seconds: i64,
+    pub sip_early_media: bool,
+    pub sip_qualify_interval: i64,
     pub task_stats: bool,
 }
 
@@ -28,9 +60,41 @@ pub const SETTINGS: Settings = Settings {
     },
     sip_registrar: CONFIG.app.sip_registrar,
     sip_contact: CONFIG.app.sip_contact,
+    sip_aliases: CONFIG.app.sip_aliases,
     sip_username: CONFIG.app.sip_username,
     sip_password: CONFIG.app.sip_password,
     sip_target: CONFIG.app.sip_target,
+    sip_transfer_target: CONFIG.app.sip_transfer_target,
+    sip_transfer_attended: CONFIG.app.sip_transfer_attended,
+    sip_transport: CONFIG.app.sip_transport,
+    sip_tls_server_name: CONFIG.app.sip_tls_server_name,
+    sip_tls_ca: CONFIG.app.sip_tls_ca,
     ring_timeout: CONFIG.app.ring_timeout,
+    ring_sound: CONFIG.app.ring_sound,
+    sip_ring_patterns: CONFIG.app.sip_ring_patterns,
+    sip_forward_always: CONFIG.app.sip_forward_always,
+    sip_forward_busy: CONFIG.app.sip_forward_busy,
+    sip_forward_no_answer: CONFIG.app.sip_forward_no_answer,
+    sip_forward_no_answer_after: CONFIG.app.sip_forward_no_answer_after,
+    sip_forward_callers: CONFIG.app.sip_forward_callers,
+    sip_dnd_response: CONFIG.app.sip_dnd_response,
+    sip_dnd_forward: CONFIG.app.sip_dnd_forward,
+    sip_dnd_allow: CONFIG.app.sip_dnd_allow,
+    sip_dnd_quiet_hours: CONFIG.app.sip_dnd_quiet_hours,
+    utc_offset_minutes: CONFIG.app.utc_offset_minutes,
+    sip_auto_answer_from: CONFIG.app.sip_auto_answer_from,
+    sip_auto_answer_beep: CONFIG.app.sip_auto_answer_beep,
+    sip_anonymous: CONFIG.app.sip_anonymous,
+    sip_reject_anonymous: CONFIG.app.sip_reject_anonymous,
+    sip_trusted_proxies: CONFIG.app.sip_trusted_proxies,
+    sip_allow_callers: CONFIG.app.sip_allow_callers,
+    sip_deny_callers: CONFIG.app.sip_deny_callers,
+    sip_allow_sources: CONFIG.app.sip_allow_sources,
+    sip_deny_sources: CONFIG.app.sip_deny_sources,
+    sip_deny_user_agents: CONFIG.app.sip_deny_user_agents,
+    sip_rate_limit: CONFIG.app.sip_rate_limit,
+    sip_ban_seconds: CONFIG.app.sip_ban_seconds,
+    sip_early_media: CONFIG.app.sip_early_media,
+    sip_qualify_interval: CONFIG.app.sip_qualify_interval,
     task_stats: CONFIG.app.task_stats,
 };
@@ -11,8 +11,8 @@ use crate::agc::Agc;
 use crate::dsp::Up6Polyphase;
 use crate::{
     messages::{
-        AudioCommand, AudioCommandReceiver, AudioMode,
-        MediaIn, MediaInReceiver, PhoneState, RxRtpPacket
+        AudioCommand, AudioCommandReceiver, AudioMode, EarlyMedia,
+        MediaIn, MediaInReceiver, PhoneState, RingPattern, RxRtpPacket
     },
     tasks::task::{AppTask, TaskMeta}
 };
@@ -22,6 +22,26 @@ const FRAME_SAMPLES_8K: usize = 160; // 20ms at 8kHz
 const FRAME_SAMPLES_48K: usize = 960; // 20ms at 48kHz
 const FRAME_DURATION: Duration = Duration::from_millis(20);
 
+// Ringback cadence sent as early media: 1 s tone, 4 s silence.
+const RINGBACK_ON_FRAMES: u32 = 50;
+const RINGBACK_CYCLE_FRAMES: u32 = 250;
+/// Length of the warning beep, 300 ms.
+const BEEP_FRAMES: u32 = 15;
+
+/// Ring cadences as (tone, silence) frame pairs.
+fn ring_cadence(pattern: RingPattern) -> &'static [(u32, u32)] {
+    match pattern {
+        // 2 s ring, 4 s silence.
+        RingPattern::Normal => &[(100, 200)],
+        // Two short rings: 0.4 s, 0.2 s gap, 0.4 s, 2 s silence.
+        RingPattern::Double => &[(20, 10), (20, 100)],
+        // Three rings of 0.3 s, 0.2 s apart, 2 s silence.
+        RingPattern::Triple => &[(15, 10), (15, 10), (15, 100)],
+        // 0.2 s on, 0.2 s off without a pause.
+        RingPattern::Urgent => &[(10, 10)],
+    }
+}
+
 type Jb = JitterBuffer<10, FRAME_SAMPLES_8K>;
 
 #[derive(Debug, Clone, Copy)]
@@ -54,6 +74,7 @@ pub struct AudioTask {
     media_rx: MediaInReceiver,
     media_tx: MediaOutSender,
     call_state: PhoneState,
+    early_media: Option<EarlyMedia>,
     mode: AudioMode,
     engine: Engine,
 
@@ -67,6 +88,12 @@ pub struct AudioTask {
     
     // Tone generator
     tone_phase: f32,
+    ringback_frame: u32,
+    /// Frames of warning beep still to play before the call's audio.
+    beep_frames: u32,
+    /// Ring the speaker while a call rings.
+    ring: Option<RingPattern>,
+    ring_frame: u32,
 }
 
 impl AppTask for AudioTask {
@@ -97,6 +124,7 @@ impl AudioTask {
             media_rx,
             media_tx,
             call_state: PhoneState::Idle,
+            early_media: None,
             mode: AudioMode::Listen,
             engine: Engine::Off,
 
@@ -107,6 +135,10 @@ impl AudioTask {
             agc: Agc::new(),
 
             tone_phase: 0.0,
+            ringback_frame: 0,
+            beep_frames: 0,
+            ring: None,
+            ring_frame: 0,
         }
     }
 
@@ -162,9 +194,29 @@ impl AudioTask {
             AudioCommand::SetDialogState(st) => {
                 self.call_state = st;
                 // If the call ends, clear jitter
-                if !matches!(self.call_state, PhoneState::Established) {
+                if !matches!(self.call_state, PhoneState::Established)
+                    && self.early_media != Some(EarlyMedia::Receive)
+                {
                     self.jitter.reset();
                 }
+                if self.call_state == PhoneState::Idle {
+                    self.beep_frames = 0;
+                }
+            }
+            AudioCommand::Beep => {
+                self.beep_frames = BEEP_FRAMES;
+            }
+            AudioCommand::Ring(ring) => {
+                if ring != self.ring {
+                    self.ring = ring;
+                    self.ring_frame = 0;
+                }
+            }
+            AudioCommand::EarlyMedia(early_media) => {
+                if early_media != self.early_media {
+                    self.early_media = early_media;
+                    self.ringback_frame = 0;
+                }
             }
             AudioCommand::SetMode(m) => {
                 self.mode = m;
@@ -192,9 +244,12 @@ impl AudioTask {
     }
 
     fn update_engine(&mut self) {
-        let want = match (self.call_state, self.mode) {
-            (PhoneState::Established, AudioMode::Listen) => EngineKind::Listen,
-            (PhoneState::Established, AudioMode::Talk) => EngineKind::Talk,
+        let want = match (self.call_state, self.mode, self.early_media) {
+            (PhoneState::Established, AudioMode::Listen, _) => EngineKind::Listen,
+            (PhoneState::Established, AudioMode::Talk, _) => EngineKind::Talk,
+            (PhoneState::Ringing, _, Some(EarlyMedia::Receive)) => EngineKind::Listen,
+            (PhoneState::Ringing, _, Some(EarlyMedia::Ringback)) => EngineKind::Talk,
+            (PhoneState::Ringing, _, None) if self.ring.is_some() => EngineKind::Listen,
             _ => EngineKind::Off,
         };
 
@@ -300,7 +355,15 @@ impl AudioTask {
         }
         self.engine = Engine::Listen{ next: Some(deadline + FRAME_DURATION) };
 
-        let (frame, had_real) = self.jitter.pop_frame();
+        let (mut frame, had_real) = self.jitter.pop_frame();
+        let ringing = (self.call_state, self.ring, self.early_media);
+        if let (PhoneState::Ringing, Some(pattern), None) = ringing {
+            frame = self.gen_ring_frame_8k(pattern);
+        }
+        if self.beep_frames > 0 {
+            self.beep_frames -= 1;
+            frame = self.gen_tone_frame_8k();
+        }
         log::debug!(
             "playout frame, real={}, first_sample={}",
             had_real,
@@ -393,6 +456,14 @@ impl AudioTask {
         }
         self.engine = Engine::Talk { next: Some(deadline + FRAME_DURATION) };
 
+        if self.call_state == PhoneState::Ringing
+            && self.early_media == Some(EarlyMedia::Ringback)
+        {
+            let frame = self.gen_ringback_frame_8k();
+            let _ = self.media_tx.send(MediaOut::PcmFrame(frame));
+            return;
+        }
+
         let mut frame = if self.inject_tone_as_mic {
             self.gen_tone_frame_8k()
         } else {
@@ -429,6 +500,44 @@ impl AudioTask {
         out8
     }
 
+    fn gen_ringback_frame_8k(&mut self) -> HVec<i16, FRAME_SAMPLES_8K> {
+        let on = self.ringback_frame < RINGBACK_ON_FRAMES;
+        self.ringback_frame = (self.ringback_frame + 1) % RINGBACK_CYCLE_FRAMES;
+        if on {
+            return self.gen_tone_frame_8k();
+        }
+
+        let mut pcm = HVec::new();
+        let _ = pcm.resize_default(FRAME_SAMPLES_8K);
+        pcm
+    }
+
+    fn gen_ring_frame_8k(&mut self, pattern: RingPattern) -> HVec<i16, FRAME_SAMPLES_8K> {
+        let cadence = ring_cadence(pattern);
+        let cycle: u32 = cadence.iter().map(|(on, off)| on + off).sum();
+        let mut at = self.ring_frame % cycle;
+        self.ring_frame = (self.ring_frame + 1) % cycle;
+
+        let mut on = false;
+        for &(tone, silence) in cadence {
+            if at < tone {
+                on = true;
+                break;
+            }
+            if at < tone + silence {
+                break;
+            }
+            at -= tone + silence;
+        }
+        if on {
+            return self.gen_tone_frame_8k();
+        }
+
+        let mut pcm = HVec::new();
+        let _ = pcm.resize_default(FRAME_SAMPLES_8K);
+        pcm
+    }
+
     fn gen_tone_frame_8k(&mut self) -> HVec<i16, FRAME_SAMPLES_8K> {
         use std::f32::consts::PI;
         const AMP: f32 = 8_000.0;
@@ -23,6 +23,8 @@ pub struct RtpTask {
     buf: [u8; RX_BUF_SIZE],
 
     active: bool,
+    sending: bool,
+    receiving: bool,
 
     // Peer selection
     signaled_peer: Option<SocketAddr>,
@@ -75,6 +77,8 @@ impl RtpTask {
             buf: [0u8; RX_BUF_SIZE],
 
             active: false,
+            sending: true,
+            receiving: true,
 
             signaled_peer: None,
             observed_peer: None,
@@ -167,6 +171,8 @@ impl RtpTask {
                         self.ts = 0;
 
                         self.active = true;
+                        self.sending = true;
+                        self.receiving = true;
                         self.next_tick = Instant::now() + self.tick;
 
                         log::info!(
@@ -179,6 +185,11 @@ impl RtpTask {
                     }
                 }
             }
+            RtpCommand::SetDirection { send, receive } => {
+                self.sending = send;
+                self.receiving = receive;
+                log::info!("RTP direction: send={}, receive={}", send, receive);
+            }
             RtpCommand::StopStream => {
                 self.active = false;
 
@@ -208,7 +219,7 @@ impl RtpTask {
     }
 
     fn handle_rx_packet(&mut self, len: usize, addr: SocketAddr) {
-        if len < 12 {
+        if len < 12 || !self.receiving {
             return;
         }
 
@@ -265,6 +276,13 @@ impl RtpTask {
         // Pull a frame from media_out, or generate a tone for testing.
         let payload = self.build_payload();
 
+        // Paused: the frame is dropped, but the clock keeps running so the
+        // far end sees a gap rather than a jump back in time on resume.
+        if !self.sending {
+            self.ts = self.ts.wrapping_add(self.frame_samples);
+            return;
+        }
+
         let header = RtpHeader {
             version: 2,
             padding: false,
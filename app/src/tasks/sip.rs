@@ -1,32 +1,92 @@
-use std::io::ErrorKind::WouldBlock;
-use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use hardware::ButtonState;
 use heapless::String as HString;
-use sdp::{MediaDescription, SessionDescription};
+use sdp::{Direction, MediaDescription, SessionDescription};
 use sip_core::{
-    CoreDialogEvent, CoreEvent, CoreRegistrationEvent, DigestCredentials,
-    InviteKind, RegistrationResult, RegistrationState, SipStack,
-    authorization_header,
+    AdmissionPolicy, AdmissionStats, CallerIdentity, CoreDialogEvent, CoreEvent,
+    CoreRegistrationEvent, DialogHandle, DigestCredentials, ForwardReason, InviteKind,
+    RegistrationResult, RegistrationState, SipStack, Transport, TransportAddr, TransportKind,
+    add_forwarding, alert_info, authorization_header, auto_answer_after,
 };
 
 use crate::tasks::task::{AppTask, TaskMeta};
+use crate::transport::{TcpTransport, TlsTransport, UdpTransport};
 use crate::messages::{
-    AudioCommand, AudioCommandSender, AudioMode, ButtonEvent, PhoneState,
-    RtpCommand, RtpCommandSender,
+    AudioCommand, AudioCommandSender, AudioMode, ButtonEvent, EarlyMedia, PhoneState,
+    RingPattern, RtpCommand, RtpCommandSender,
     SipCommand, SipCommandReceiver,
     UiCommand, UiCommandSender,
 };
 
+/// One call in progress plus one waiting or on hold.
+const MAX_CALLS: usize = 2;
+
+/// Window of `sip_rate_limit`.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+
+/// How often to log what admission turned away, if anything.
+const ADMISSION_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 struct CallContext {
+    handle: DialogHandle,
     invite: sip_core::Request,
-    remote_sdp: SessionDescription,
+    /// Who called us; `None` for calls we placed.
+    caller: Option<CallerIdentity>,
+    remote_sdp: Option<SessionDescription>,
     local_sdp: SessionDescription,
     ring_deadline: Option<Instant>, // Some(...) while ringing, None otherwise
-    remote_addr: SocketAddr,
+    /// When a trusted intercom call answers itself.
+    auto_answer_at: Option<Instant>,
+    /// How the call rings, from its Alert-Info.
+    ring_pattern: RingPattern,
+    remote_addr: TransportAddr,
+    /// On hold by us: the peer was offered `sendonly` and we send nothing.
+    /// The RTP stream may have gone to another call meanwhile.
+    held: bool,
+    /// Other early dialogs of this call when our INVITE was forked. The
+    /// first one answered becomes `handle`.
+    forks: Vec<DialogHandle>,
+    /// RTP runs before the call is answered.
+    early_media: Option<EarlyMedia>,
+    /// We placed the call for this dialog's REFER; its final response is
+    /// NOTIFYed there.
+    referred_by: Option<DialogHandle>,
+    /// Consultation call of an attended transfer of this held call.
+    consult_for: Option<DialogHandle>,
+}
+
+impl CallContext {
+    /// The direction we ask for: everything, unless we hold the call.
+    fn wanted_direction(&self) -> Direction {
+        if self.held {
+            Direction::SendOnly
+        } else {
+            Direction::SendRecv
+        }
+    }
+}
+
+/// The stream the RTP task was last told to run.
+#[derive(Debug, Clone, PartialEq)]
+struct RtpStream {
+    handle: DialogHandle,
+    remote_ip: String,
+    remote_port: u16,
+    payload_type: u8,
+    send: bool,
+    receive: bool,
+}
+
+impl RtpStream {
+    fn same_target(&self, other: &RtpStream) -> bool {
+        self.remote_ip == other.remote_ip
+            && self.remote_port == other.remote_port
+            && self.payload_type == other.payload_type
+    }
 }
 
 pub struct SipTask {
@@ -39,19 +99,31 @@ pub struct SipTask {
 
     // Core SIP logic
     core: SipStack,
-    call_ctx: Option<CallContext>,
+    calls: Vec<CallContext>,
+    call_waiting: bool,
+    call_held: bool,
+    /// Do-not-disturb switched on by hand; quiet hours add to it.
+    dnd: bool,
+    /// Whether do-not-disturb is in effect, as last shown on the LED.
+    dnd_active: bool,
     ring_timeout: Duration,
+    rtp_stream: Option<RtpStream>,
+    /// Who may reach us, and what it turned away.
+    admission: AdmissionPolicy,
+    logged_admission: AdmissionStats,
+    next_admission_log: Instant,
 
     // Networking
-    rx_buf: [u8; 1500],
-    sip_socket: UdpSocket,
-    registrar: String,
+    transports: Vec<Box<dyn Transport + Send>>,
+    transport: TransportKind,
+    registrar_flow: Option<TransportAddr>,
     local_ip: String,
     local_sip_port: u16,
     local_rtp_port: u16,
 
     // Timers
     next_register: Instant,
+    server_unreachable: bool,
 
     // Local mirror of reg state so we can log transitions
     last_reg_state: RegistrationState,
@@ -82,21 +154,63 @@ impl SipTask {
         audio_tx: AudioCommandSender,
         rtp_tx: RtpCommandSender,
     ) -> Self {
-        let core = SipStack::default();
+        let mut core = SipStack::default();
 
-        let registrar = parse_uri(settings.sip_registrar);
+        let transport = TransportKind::parse(settings.sip_transport).unwrap_or_else(|| {
+            log::warn!("unknown sip_transport {:?}; using UDP", settings.sip_transport);
+            TransportKind::Udp
+        });
 
-        // SIP socket
-        let sip_socket = UdpSocket::bind((addr, 0)).expect("create SIP socket");
-        sip_socket
-            .set_nonblocking(true)
-            .expect("set SIP socket non-blocking");
+        // SIP sockets: UDP on an ephemeral port, TCP listening on the same
+        // port so Via/Contact are valid for either transport.
+        let udp = UdpTransport::bind(addr, 0).expect("create SIP socket");
+        let (local_ip, local_sip_port) = local_ip_port(&udp);
+        let tcp = TcpTransport::bind(addr, local_sip_port);
+        let mut transports: Vec<Box<dyn Transport + Send>> = vec![Box::new(udp), Box::new(tcp)];
+        let tls = tls_transport(settings);
+        // Don't advertise sips: and TLS in Via when we can't send on it.
+        let transport = if transport == TransportKind::Tls && tls.is_none() {
+            log::warn!("no SIP TLS transport; using TCP");
+            TransportKind::Tcp
+        } else {
+            transport
+        };
+        transports.extend(tls);
 
-        if let Ok(addr) = registrar.parse::<SocketAddr>() {
-            let _ = sip_socket.connect(addr);
+        let contact_uri =
+            build_contact_uri(settings.sip_contact, &local_ip, local_sip_port, transport);
+        core.set_local_endpoint(transport, &local_ip, local_sip_port, &contact_uri);
+        core.set_random_seed(hardware::random_u32());
+        core.set_anonymous(settings.sip_anonymous);
+        let aliases: Vec<&str> = settings.sip_aliases.split(',').collect();
+        core.set_local_identities(settings.sip_contact, &aliases);
+        if let Err(e) = core.set_trusted_sources(settings.sip_trusted_proxies) {
+            log::warn!("bad sip_trusted_proxies: {:?}", e);
         }
 
-        let (local_ip, local_sip_port) = local_ip_port(&sip_socket);
+        let qualify_interval = u64::try_from(settings.sip_qualify_interval)
+            .ok()
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        core.set_qualify(
+            settings.sip_registrar,
+            settings.sip_contact,
+            qualify_interval,
+            Instant::now(),
+        );
+
+        let mut admission = AdmissionPolicy::default();
+        let sources = admission.set_sources(settings.sip_allow_sources, settings.sip_deny_sources);
+        if let Err(e) = sources {
+            log::warn!("bad sip_allow_sources / sip_deny_sources: {:?}", e);
+        }
+        admission.set_user_agent_denylist(settings.sip_deny_user_agents);
+        admission.set_callers(settings.sip_allow_callers, settings.sip_deny_callers);
+        admission.set_rate_limit(
+            u32::try_from(settings.sip_rate_limit).unwrap_or(0),
+            RATE_LIMIT_WINDOW,
+            Duration::from_secs(u64::try_from(settings.sip_ban_seconds).unwrap_or(0)),
+        );
 
         Self {
             settings,
@@ -106,17 +220,26 @@ impl SipTask {
             rtp_tx,
 
             core,
-            call_ctx: None,
+            calls: Vec::new(),
+            call_waiting: false,
+            call_held: false,
+            dnd: false,
+            dnd_active: false,
             ring_timeout: Duration::from_secs(settings.ring_timeout as u64),
-
-            rx_buf: [0u8; 1500],
-            sip_socket,
-            registrar,
+            rtp_stream: None,
+            admission,
+            logged_admission: AdmissionStats::default(),
+            next_admission_log: Instant::now() + ADMISSION_LOG_INTERVAL,
+
+            transports,
+            transport,
+            registrar_flow: None,
             local_ip,
             local_sip_port,
             local_rtp_port,
 
             next_register: Instant::now(),
+            server_unreachable: false,
             last_reg_state: RegistrationState::Unregistered,
         }
     }
@@ -133,13 +256,15 @@ impl SipTask {
             let now = Instant::now();
 
             self.maybe_send_register(now);
-            self.poll_sip_socket();
+            self.poll_transports();
             if !self.poll_commands() {
                 log::info!("SIP task exiting: command channel closed");
                 break;
             }
             self.check_call_timeouts(now);
+            self.update_dnd();
             self.process_core_timers(now);
+            self.log_admission_stats(now);
 
             thread::sleep(Duration::from_millis(10));
         }
@@ -187,13 +312,12 @@ impl SipTask {
                 self.settings.sip_contact,
                 &self.local_ip,
                 self.local_sip_port,
+                self.transport,
             );
 
         let req = match self.core.build_register(
             self.settings.sip_registrar,
             &contact_uri,
-            &self.local_ip,
-            self.local_sip_port,
             expires,
             auth_header,
         ) {
@@ -205,17 +329,13 @@ impl SipTask {
             }
         };
 
-        let rendered = match req.render() {
-            Ok(s) => s,
-            Err(e) => {
-                log::warn!("failed to render REGISTER: {:?}", e);
-                self.next_register = now + Duration::from_secs(30);
-                return;
-            }
-        };
-
-        log::info!("sending REGISTER" /*\n{}", rendered*/ );
-        send_sip(&self.sip_socket, &self.registrar, &rendered);
+        log::info!("sending REGISTER");
+        self.registrar_flow = self.send_request(&req);
+        self.core.set_proxy_flow(self.registrar_flow);
+        if self.registrar_flow.is_none() {
+            self.next_register = now + Duration::from_secs(30);
+            return;
+        }
 
         // Give a short window for the first response
         self.next_register = now + REGISTER_TIMEOUT;
@@ -266,60 +386,102 @@ impl SipTask {
 
     // --- Network receive -----------------------------------------------------
 
-    fn poll_sip_socket(&mut self) {
-        loop {
-            match self.sip_socket.recv_from(&mut self.rx_buf) {
-                Ok((len, addr)) => {
-                    if let Ok(text) = core::str::from_utf8(&self.rx_buf[..len]) {
-                        //log::debug!("parse_message:\r\n{}", text); switching to logging `Message`
-                        match sip_core::parse_message(text) {
-                            Ok(msg) => {
-                                log::debug!("parse_message ->\r\n{:?}", &msg);
-                                let now = Instant::now();
-                                let events = self.core.on_message(msg, addr, now);
-                                for ev in events {
-                                    self.handle_core_event(ev, addr);
-                                }
-                            }
-                            Err(e) => {
-                                log::error!("parse_message: {:?}\r\n{}", e, text);
-                            }
-                        }
+    fn poll_transports(&mut self) {
+        for idx in 0..self.transports.len() {
+            let kind = self.transports[idx].kind();
+            while let Some((data, addr)) = self.transports[idx].poll_recv() {
+                self.handle_received(&data, TransportAddr::new(kind, addr));
+            }
+
+            // Losing the connection we registered over means the registrar
+            // can no longer reach us; register again to open a new one.
+            for addr in self.transports[idx].take_closed() {
+                if self.registrar_flow == Some(TransportAddr::new(kind, addr)) {
+                    log::info!("SIP {} connection to registrar lost; re-registering", kind);
+                    self.registrar_flow = None;
+                    self.core.set_proxy_flow(None);
+                    self.core.registration.reset_to_unregistered();
+                    self.next_register = Instant::now();
+                }
+            }
+        }
+    }
 
+    fn handle_received(&mut self, data: &[u8], addr: TransportAddr) {
+        let Ok(text) = core::str::from_utf8(data) else {
+            return;
+        };
+
+        //log::debug!("parse_message:\r\n{}", text); switching to logging `Message`
+        match sip_core::parse_message(text) {
+            Ok(msg) => {
+                log::debug!("parse_message ->\r\n{:?}", &msg);
+                let now = Instant::now();
+                // Requests over the flow we registered on are our
+                // registrar's; anyone else's may be dropped without an answer.
+                let from_registrar = self.registrar_flow == Some(addr);
+                if let sip_core::Message::Request(req) = &msg {
+                    let known = self.core.is_known_request(req);
+                    if !from_registrar
+                        && !self.admission.admit_request(req, addr.addr.ip(), known, now)
+                    {
+                        log::debug!("Dropping {} from {}", req.method, addr);
+                        return;
                     }
                 }
-                Err(ref e) if e.kind() == WouldBlock => break,
-                Err(e) => {
-                    log::warn!("SIP recv error: {:?}", e);
-                    break;
+                let events = self.core.on_message(msg, addr, now);
+                for ev in events {
+                    self.handle_core_event(ev);
                 }
             }
+            Err(e) => {
+                log::error!("parse_message: {:?}\r\n{}", e, text);
+            }
         }
     }
 
-    fn handle_core_event(&mut self, ev: CoreEvent, remote_addr: SocketAddr) {
+    fn handle_core_event(&mut self, ev: CoreEvent) {
         match ev {
             CoreEvent::Registration(reg_ev) => self.handle_reg_event(reg_ev),
-            CoreEvent::Dialog(dialog_ev) => {
-                self.handle_dialog_event(dialog_ev, remote_addr)
-            }
-            CoreEvent::SendResponse(resp) => {
-                if let Ok(text) = resp.render() {
-                    log::debug!("Sending response");
-                    send_sip_addr(&self.sip_socket, remote_addr, &text);
+            CoreEvent::Dialog(dialog_ev) => self.handle_dialog_event(dialog_ev),
+            CoreEvent::SendResponse { response, target } => {
+                if let Ok(text) = response.render() {
+                    log::debug!("Sending response to {}", target);
+                    self.send_to(target, None, &text);
                 } else {
                     log::warn!("Failed to render response");
                 }
             }
-            CoreEvent::SendResponseTo { response, target } => {
-                if let Ok(text) = response.render() {
-                    log::debug!("Sending response (timer)");
-                    send_sip_addr(&self.sip_socket, target, &text);
-                } else {
-                    log::warn!("Failed to render response from timer");
-                }
+            CoreEvent::SendRequest { request, target } => {
+                log::debug!("Sending {} from core", request.method);
+                self.send_request_to(&request, &target);
+            }
+            CoreEvent::Reachability { state, rtt } => self.handle_reachability(state, rtt),
+        }
+    }
+
+    fn handle_reachability(&mut self, state: sip_core::Reachability, rtt: Option<Duration>) {
+        let reachable = match state {
+            sip_core::Reachability::Reachable => {
+                log::info!("SIP server reachable (rtt {:?})", rtt);
+                true
+            }
+            sip_core::Reachability::Unreachable => {
+                log::warn!("SIP server unreachable");
+                false
             }
+            sip_core::Reachability::Unknown => return,
+        };
+        let _ = self.ui_tx.send(UiCommand::ServerReachabilityChanged(reachable));
+
+        // The server may have restarted, or we may reach it through a new NAT
+        // mapping: re-register now instead of waiting for the refresh timer.
+        if reachable && self.server_unreachable {
+            log::info!("SIP server is back; re-registering");
+            self.core.registration.reset_to_unregistered();
+            self.next_register = Instant::now();
         }
+        self.server_unreachable = !reachable;
     }
 
     fn handle_reg_event(&mut self, ev: CoreRegistrationEvent) {
@@ -340,56 +502,214 @@ impl SipTask {
         }
     }
 
-    fn handle_dialog_event(
-        &mut self,
-        ev: CoreDialogEvent,
-        remote_addr: SocketAddr,
-    ) {
+    fn handle_dialog_event(&mut self, ev: CoreDialogEvent) {
         match ev {
-            CoreDialogEvent::IncomingInvite { request, kind: InviteKind::Initial } => {
-                log::info!("Incoming INVITE from {}", remote_addr);
-                self.on_incoming_initial_invite(request, remote_addr);
+            CoreDialogEvent::IncomingInvite { handle, kind: InviteKind::Initial, request, caller, source: remote_addr } => {
+                log::info!("Incoming INVITE {} from {} ({})", handle, caller, remote_addr);
+                self.on_incoming_initial_invite(handle, request, caller, remote_addr);
+            }
+            CoreDialogEvent::IncomingInvite { handle, kind: InviteKind::Reinvite, request, source: remote_addr, .. } => {
+                log::info!("Incoming re-INVITE {} from {}", handle, remote_addr);
+                self.on_incoming_reinvite(handle, request, remote_addr);
+            }
+            CoreDialogEvent::IncomingInvite { handle, kind: InviteKind::Replacing(replaced), request, caller, source: remote_addr } => {
+                log::info!("Incoming INVITE {} from {} ({}) replacing {}", handle, caller, remote_addr, replaced);
+                self.on_incoming_replacing_invite(handle, request, caller, remote_addr, replaced);
+            }
+            CoreDialogEvent::Forked { original, handle } => {
+                log::info!("INVITE forked: {} alongside {}", handle, original);
+                if let Some(ctx) = self.call_mut(original) {
+                    ctx.forks.push(handle);
+                }
+            }
+            CoreDialogEvent::InviteResponse { handle, response } => {
+                log::info!("INVITE response {}: {} {}", handle, response.status_code, response.reason);
+                self.on_invite_response(handle, &response);
+            }
+            CoreDialogEvent::Redirected { handle, status_code, target } => {
+                log::info!("Call {} redirected ({}) to {}", handle, status_code, target);
+                self.on_redirected(handle);
+            }
+            CoreDialogEvent::IncomingUpdate { handle, request, source: remote_addr } => {
+                log::info!("Incoming UPDATE {} from {}", handle, remote_addr);
+                self.on_incoming_update(handle, request, remote_addr);
             }
-            CoreDialogEvent::IncomingInvite { request, kind: InviteKind::Reinvite } => {
-                log::info!("Incoming re-INVITE from {}", remote_addr);
-                self.on_incoming_reinvite(request, remote_addr);
+            CoreDialogEvent::UpdateResponse { handle, response } => {
+                log::info!("UPDATE response {}: {} {}", handle, response.status_code, response.reason);
+                self.on_update_response(handle, &response);
             }
-            CoreDialogEvent::IncomingInvite { request, kind: InviteKind::InitialWhileBusy } => {
-                log::info!("Incoming INVITE while busy from {}, sending 486", remote_addr);
-                self.on_incoming_initial_while_busy(request, remote_addr);
+            CoreDialogEvent::IncomingRefer { handle, refer_to } => {
+                log::info!("REFER {} to {}", handle, refer_to);
+                self.on_incoming_refer(handle, &refer_to);
             }
-            CoreDialogEvent::DialogStateChanged(state) => {
-                log::info!("Dialog state -> {}", state);
-                self.on_dialog_state_changed(&state);
+            CoreDialogEvent::TransferProgress { handle, status_code } => {
+                log::info!("Transfer of {}: {}", handle, status_code);
+                self.on_transfer_progress(handle, status_code);
+            }
+            CoreDialogEvent::IncomingAnswer { handle, sdp } => {
+                log::info!("SDP answer in ACK for {}", handle);
+                self.on_incoming_answer(handle, &sdp);
+            }
+            CoreDialogEvent::DialogStateChanged { handle, state } => {
+                log::info!("Dialog {} state -> {}", handle, state);
+                self.on_dialog_state_changed(handle, &state);
             }
         }
     }
 
-    fn on_dialog_state_changed(&mut self, state: &sip_core::DialogState) {
-        self.broadcast_phone_state();
+    fn on_invite_response(&mut self, handle: DialogHandle, resp: &sip_core::Response) {
+        if resp.status_code >= 200 {
+            if let Some(referrer) = self.call_mut(handle).and_then(|ctx| ctx.referred_by.take()) {
+                self.notify_refer(referrer, resp.status_code, &resp.reason);
+            }
+        }
+        if resp.status_code <= 100 || resp.status_code >= 300 || resp.body.is_empty() {
+            return;
+        }
+
+        let sdp = match sdp::parse(resp.body.as_str()) {
+            Ok(sdp) => sdp,
+            Err(e) => {
+                log::warn!("failed to parse SDP answer: {:?}", e);
+                return;
+            }
+        };
+
+        let early = resp.status_code < 200
+            && self.core.dialogs.get(handle).is_some_and(|d| d.early_media());
+        let Some(ctx) = self.call_mut(handle) else {
+            return;
+        };
+        // Reliable 18x are retransmitted until PRACKed; same SDP, same stream.
+        let changed = ctx.remote_sdp.as_ref() != Some(&sdp);
+        let reinvite = resp.status_code >= 200 && ctx.remote_sdp.is_some();
+        ctx.remote_sdp = Some(sdp);
+
+        // 183 Session Progress: play the far end's ringback or announcement.
+        if early && changed {
+            log::info!("Early media on {}", handle);
+            ctx.early_media = Some(EarlyMedia::Receive);
+            self.start_rtp_streams(handle);
+            self.broadcast_phone_state();
+        } else if reinvite && self.is_established(handle) && self.has_media(handle) {
+            // Answer to our hold or resume offer.
+            self.start_rtp_streams(handle);
+        }
+    }
+
+    /// The call went on to a new target, which answers with SDP of its
+    /// own: drop the early media of the last one.
+    fn on_redirected(&mut self, handle: DialogHandle) {
+        if let Some(ctx) = self.call_mut(handle) {
+            ctx.remote_sdp = None;
+            ctx.early_media = None;
+        }
+        if self.rtp_stream.as_ref().is_some_and(|s| s.handle == handle) {
+            self.stop_rtp_streams();
+        }
+    }
+
+    /// The peer transfers us (RFC 3515): call the target and NOTIFY the
+    /// peer how it goes. The peer hangs up on us once it is answered.
+    fn on_incoming_refer(&mut self, handle: DialogHandle, refer_to: &str) {
+        let placed = if self.calls.len() < MAX_CALLS {
+            self.place_call(refer_to)
+        } else {
+            None
+        };
+        match placed.and_then(|new| self.call_mut(new)) {
+            Some(ctx) => ctx.referred_by = Some(handle),
+            None => self.notify_refer(handle, 503, "Service Unavailable"),
+        }
+    }
+
+    fn notify_refer(&mut self, handle: DialogHandle, status: u16, reason: &str) {
+        match self.core.build_refer_notify(handle, status, reason) {
+            Ok(notify) => {
+                self.send_request(&notify);
+            }
+            Err(e) => log::warn!("failed to build NOTIFY: {:?}", e),
+        }
+    }
+
+    /// How the transfer we asked for goes. Once the target answered our
+    /// part is over; if it failed, the call stays on hold to be resumed.
+    fn on_transfer_progress(&mut self, handle: DialogHandle, status_code: u16) {
+        match status_code {
+            100..=199 => {}
+            200..=299 => {
+                log::info!("Transfer of {} done; hanging up", handle);
+                self.hang_up_call(handle);
+            }
+            _ => log::info!("Transfer of {} failed; call stays on hold", handle),
+        }
+    }
+
+    /// The caller answered the offer in our 2xx (delayed offer). RTP
+    /// starts on the state change that follows.
+    fn on_incoming_answer(&mut self, handle: DialogHandle, body: &str) {
+        match sdp::parse(body) {
+            Ok(sdp) => {
+                if let Some(ctx) = self.call_mut(handle) {
+                    ctx.remote_sdp = Some(sdp);
+                }
+            }
+            Err(e) => log::warn!("failed to parse SDP answer in ACK: {:?}", e),
+        }
+    }
 
+    fn on_dialog_state_changed(&mut self, handle: DialogHandle, state: &sip_core::DialogState) {
         match state {
             sip_core::DialogState::Established { .. } => {
-                self.start_rtp_streams_from_ctx();
+                // The fork that answered carries the call from now on.
+                if let Some(ctx) = self.call_mut(handle) {
+                    if ctx.handle != handle {
+                        ctx.forks.retain(|h| *h != handle);
+                        ctx.forks.push(ctx.handle);
+                        ctx.handle = handle;
+                    }
+                    ctx.early_media = None;
+                }
+                // Our offer went out in the 2xx and the ACK brought no
+                // usable answer: there is no media to talk over.
+                if self.call(handle).is_some_and(|ctx| ctx.remote_sdp.is_none()) {
+                    log::warn!("Call {} established without an SDP answer; hanging up", handle);
+                    self.hang_up_call(handle);
+                    return;
+                }
+                self.broadcast_phone_state();
+                if self.has_media(handle) {
+                    self.start_rtp_streams(handle);
+                }
             }
             sip_core::DialogState::Terminated | sip_core::DialogState::Idle => {
-                self.stop_rtp_streams();
-                self.call_ctx = None;
+                self.forget_call(handle);
             }
-            _ => {}
+            _ => self.broadcast_phone_state(),
         }
     }
 
-    fn start_rtp_streams_from_ctx(&mut self) {
-        let ctx = match &self.call_ctx {
+    /// Point the RTP task at the call's remote media, with each direction
+    /// on only if both SDPs allow it. The stream keeps running, merely
+    /// paused or resumed, if only the directions changed.
+    fn start_rtp_streams(&mut self, handle: DialogHandle) {
+        let ctx = match self.call(handle) {
             Some(c) => c,
             None => {
-                log::warn!("start_rtp_streams_from_ctx: no call context");
+                log::warn!("start_rtp_streams: no call context for {}", handle);
+                return;
+            }
+        };
+
+        let remote_sdp = match &ctx.remote_sdp {
+            Some(s) => s,
+            None => {
+                log::warn!("start_rtp_streams: no remote SDP");
                 return;
             }
         };
 
-        if ctx.remote_sdp.media.port == 0 {
+        if remote_sdp.media.port == 0 {
             log::info!("remote RTP port is 0 (hold); stopping RTP");
             self.stop_rtp_streams();
             return;
@@ -397,239 +717,955 @@ impl SipTask {
 
         let mut remote_ip: HString<48> = HString::new();
         if remote_ip
-            .push_str(ctx.remote_sdp.connection_address.as_str())
+            .push_str(remote_sdp.connection_address.as_str())
             .is_err()
         {
             log::warn!(
-                "start_rtp_streams_from_ctx: remote IP too long: {}",
-                ctx.remote_sdp.connection_address
+                "start_rtp_streams: remote IP too long: {}",
+                remote_sdp.connection_address
             );
             return;
         }
 
-        let cmd = RtpCommand::StartStream {
-            remote_ip: remote_ip.clone(),
-            remote_port: ctx.remote_sdp.media.port,
-            expected_remote_ssrc: None,
-            local_ssrc: None,
-            payload_type: ctx.remote_sdp.media.payload_type,
+        // We have no music on hold, so a held call sends nothing even
+        // though it offered sendonly.
+        let local = ctx.local_sdp.media.direction;
+        let remote = remote_sdp.media.direction;
+        let stream = RtpStream {
+            handle: ctx.handle,
+            remote_ip: remote_sdp.connection_address.clone(),
+            remote_port: remote_sdp.media.port,
+            payload_type: remote_sdp.media.payload_type,
+            send: !ctx.held && local.sends() && remote.receives(),
+            receive: local.receives() && remote.sends(),
         };
+        if self.rtp_stream.as_ref() == Some(&stream) {
+            return;
+        }
+
+        let running = self
+            .rtp_stream
+            .as_ref()
+            .is_some_and(|current| current.same_target(&stream));
+        if !running {
+            let cmd = RtpCommand::StartStream {
+                remote_ip: remote_ip.clone(),
+                remote_port: stream.remote_port,
+                expected_remote_ssrc: None,
+                local_ssrc: None,
+                payload_type: stream.payload_type,
+            };
 
-        if let Err(e) = self.rtp_tx.send(cmd) {
-            log::warn!("Failed to start RTP: {:?}", e);
+            if let Err(e) = self.rtp_tx.send(cmd) {
+                log::warn!("Failed to start RTP: {:?}", e);
+                return;
+            }
+        }
+        if running || !(stream.send && stream.receive) {
+            let cmd = RtpCommand::SetDirection { send: stream.send, receive: stream.receive };
+            if let Err(e) = self.rtp_tx.send(cmd) {
+                log::warn!("Failed to set RTP direction: {:?}", e);
+            }
         }
+        self.rtp_stream = Some(stream);
     }
 
     fn stop_rtp_streams(&mut self) {
+        self.rtp_stream = None;
         if let Err(e) = self.rtp_tx.send(RtpCommand::StopStream) {
             log::debug!("stop_rtp_streams: receiver dropped? {:?}", e);
         }
     }
 
-    fn on_incoming_initial_invite(&mut self, req: sip_core::Request, remote_addr: SocketAddr) {
-        if req.body.is_empty() {
-            log::warn!("INVITE had no SDP body; ignoring");
+    fn on_incoming_initial_invite(
+        &mut self,
+        handle: DialogHandle,
+        req: sip_core::Request,
+        caller: CallerIdentity,
+        remote_addr: TransportAddr,
+    ) {
+        if !self.admission.admit_caller(&caller) {
+            log::info!("Refusing INVITE {} from {}", handle, caller);
+            if let Err(e) = self.send_response_403_forbidden(handle, &req, remote_addr) {
+                log::warn!("failed to send 403: {:?}", e);
+            }
+            self.end_call(handle);
+            return;
+        }
+
+        if self.settings.sip_reject_anonymous && caller.is_anonymous() {
+            log::info!("Rejecting anonymous INVITE {}", handle);
+            if let Err(e) = self.send_response_433_anonymity_disallowed(handle, &req, remote_addr) {
+                log::warn!("failed to send 433: {:?}", e);
+            }
+            self.end_call(handle);
+            return;
+        }
+
+        if let Some(target) = self.forward_target(Some(&caller), ForwardReason::Unconditional) {
+            log::info!("Forwarding incoming INVITE {} to {}", handle, target);
+            if let Err(e) = self.send_response_302_moved_temporarily(
+                handle,
+                &req,
+                remote_addr,
+                target,
+                ForwardReason::Unconditional,
+            ) {
+                log::warn!("failed to send 302: {:?}", e);
+            }
+            self.end_call(handle);
+            return;
+        }
+
+        if self.dnd_active && !self.rings_through_dnd(&caller) {
+            log::info!("Incoming INVITE {} during do-not-disturb", handle);
+            self.reject_dnd(handle, &req, Some(&caller), remote_addr);
+            self.end_call(handle);
+            return;
+        }
+
+        // A second call can only wait while every other call is answered.
+        let busy = self.calls.len() >= MAX_CALLS
+            || self.calls.iter().any(|c| !self.is_established(c.handle));
+        if busy {
+            log::info!("Incoming INVITE {} while busy", handle);
+            self.on_incoming_initial_while_busy(handle, req, &caller, remote_addr);
             return;
         }
 
-        let sdp = match sdp::parse(req.body.as_str()) {
+        // No offer: ours goes in the 200 and the answer comes in the ACK.
+        let offer = (!req.body.is_empty()).then(|| sdp::parse(req.body.as_str()));
+        let sdp = match offer.transpose() {
             Ok(s) => s,
             Err(e) => {
                 log::warn!("failed to parse SDP: {:?}", e);
                 if let Err(e) =
-                    self.send_response_488_not_acceptable_here(&req, remote_addr)
+                    self.send_response_488_not_acceptable_here(handle, &req, remote_addr)
                 {
                     log::warn!("Failed to send 488 Not Acceptable Here: {:?}", e);
                 }
+                self.end_call(handle);
                 return;
             }
         };
 
         let now = Instant::now();
-        let ring_deadline = now + self.ring_timeout;
-
-        log::info!(
-            "Incoming INVITE: remote RTP {}:{}, ring timeout {:?}",
-            sdp.connection_address,
-            sdp.media.port,
-            self.ring_timeout,
-        );
+        let ring_deadline = now + self.no_answer_timeout();
+        // Only a call we can take right away opens by itself.
+        let auto_answer_at = auto_answer_after(&req.headers)
+            .filter(|_| self.calls.is_empty() && self.may_auto_answer(&caller, remote_addr))
+            .map(|secs| now + Duration::from_secs(secs as u64));
+        if let Some(at) = auto_answer_at {
+            log::info!("Incoming INVITE {} answers itself in {:?}", handle, at - now);
+        }
+        let ring_pattern = self.ring_pattern(&req);
+
+        match &sdp {
+            Some(sdp) => log::info!(
+                "Incoming INVITE: remote RTP {}:{}, ring timeout {:?}",
+                sdp.connection_address,
+                sdp.media.port,
+                self.ring_timeout,
+            ),
+            None => log::info!("Incoming INVITE without offer, ring timeout {:?}", self.ring_timeout),
+        }
 
-        // Send 180 Ringing
-        if let Err(e) = self.send_response_180_ringing(&req, remote_addr) {
-            log::warn!("failed to send 180: {:?}", e);
+        // A waiting call only rings: RTP belongs to the call we're on.
+        // Without an offer a 183 would have to make one, so just ring.
+        let mut local_sdp = self.build_local_sdp();
+        if let Some(offer) = &sdp {
+            local_sdp.media.direction = offer.media.direction.answer(Direction::SendRecv);
+        }
+        let early_media = if self.settings.sip_early_media
+            && self.calls.is_empty()
+            && sdp.is_some()
+        {
+            match self.send_response_183_session_progress(handle, &req, remote_addr, &local_sdp) {
+                Ok(()) => Some(EarlyMedia::Ringback),
+                Err(e) => {
+                    log::warn!("failed to send 183: {:?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        if early_media.is_none() {
+            if let Err(e) = self.send_response_180_ringing(handle, &req, remote_addr) {
+                log::warn!("failed to send 180: {:?}", e);
+            }
         }
 
         // Store state
-        self.call_ctx = Some(CallContext {
+        self.calls.push(CallContext {
+            handle,
             invite: req,
+            caller: Some(caller),
             remote_sdp: sdp,
-            local_sdp: self.build_local_sdp(),
+            local_sdp,
             ring_deadline: Some(ring_deadline),
+            auto_answer_at,
+            ring_pattern,
             remote_addr,
+            held: false,
+            forks: Vec::new(),
+            early_media,
+            referred_by: None,
+            consult_for: None,
         });
+        if early_media.is_some() {
+            self.start_rtp_streams(handle);
+        }
 
-        // UI and audio
-        let _ = self.ui_tx.send(UiCommand::DialogStateChanged(PhoneState::Ringing));
-        let _ = self.audio_tx.send(AudioCommand::SetDialogState(PhoneState::Ringing));
+        // UI and audio: ringing, or call waiting if we're already talking.
+        self.broadcast_phone_state();
+        self.update_call_waiting();
     }
 
-    fn on_incoming_reinvite(&mut self, req: sip_core::Request, remote_addr: SocketAddr) {
-        // Check for an SDP
-        if req.body.is_empty() {
-            // offerless INVITE
-            log::warn!("received offerless re-INVITE");
-            if let Err(e) = self.send_response_488_not_acceptable_here(&req, remote_addr) {
-                log::warn!("failed to send 488: {:?}", e);
+    fn on_incoming_reinvite(
+        &mut self,
+        handle: DialogHandle,
+        req: sip_core::Request,
+        remote_addr: TransportAddr,
+    ) {
+        // An offerless re-INVITE (often a session refresh) gets our offer
+        // in the 200; media changes once the ACK brings the answer.
+        let offer = (!req.body.is_empty()).then(|| sdp::parse(req.body.as_str()));
+        let sdp = match offer.transpose() {
+            Ok(s) => s,
+            Err(e) => {
+                log::warn!("failed to parse SDP on re-INVITE: {:?}", e);
+                if let Err(e) = self.send_response_488_not_acceptable_here(handle, &req, remote_addr) {
+                    log::warn!("failed to send 488: {:?}", e);
+                }
+                return;
+            }
+        };
+
+        let Some(ctx) = self.call_mut(handle) else {
+            log::warn!("re-INVITE received but no call context; sending 481");
+            if let Err(e) = self.send_response_481_call_does_not_exist(handle, &req, remote_addr) {
+                log::warn!("failed to send 481: {:?}", e);
             }
             return;
+        };
+
+        // A peer putting us on hold offers sendonly or inactive; our
+        // answer mirrors it, and our own hold still holds.
+        let direction = match &sdp {
+            Some(offer) => offer.media.direction.answer(ctx.wanted_direction()),
+            None => ctx.wanted_direction(),
+        };
+        ctx.local_sdp.set_direction(direction);
+        let offered = sdp.is_some();
+        if offered {
+            ctx.remote_sdp = sdp;
+        }
+        let local_sdp = ctx.local_sdp.clone();
+
+        if offered && self.has_media(handle) {
+            self.start_rtp_streams(handle);
+        }
+        if let Err(e) = self.send_response_200_ok_with_sdp(handle, &req, remote_addr, &local_sdp) {
+            log::warn!("failed to respond to re-INVITE: {:?}", e);
         }
+    }
+
+    /// An INVITE taking over one of our calls (attended transfer, call
+    /// pickup). It is answered at once and steps into the old call's
+    /// place, RTP stream included, before the old call is hung up.
+    fn on_incoming_replacing_invite(
+        &mut self,
+        handle: DialogHandle,
+        req: sip_core::Request,
+        caller: CallerIdentity,
+        remote_addr: TransportAddr,
+        replaced: DialogHandle,
+    ) {
+        let Some(pos) = self
+            .calls
+            .iter()
+            .position(|c| c.handle == replaced || c.forks.contains(&replaced))
+        else {
+            log::warn!("INVITE replaces {} but there is no such call; sending 481", replaced);
+            if let Err(e) = self.send_response_481_call_does_not_exist(handle, &req, remote_addr) {
+                log::warn!("failed to send 481: {:?}", e);
+            }
+            self.end_call(handle);
+            return;
+        };
 
-        // Update remote SDP
-        let sdp = match sdp::parse(req.body.as_str()) {
+        let offer = (!req.body.is_empty()).then(|| sdp::parse(req.body.as_str()));
+        let sdp = match offer.transpose() {
             Ok(s) => s,
             Err(e) => {
-                log::warn!("failed to parse SDP on re-INVITE: {:?}", e);
-                if let Err(e) = self.send_response_488_not_acceptable_here(&req, remote_addr) {
+                log::warn!("failed to parse SDP: {:?}", e);
+                if let Err(e) = self.send_response_488_not_acceptable_here(handle, &req, remote_addr) {
                     log::warn!("failed to send 488: {:?}", e);
                 }
+                self.end_call(handle);
                 return;
             }
         };
 
-        if let Some(ctx) = &mut self.call_ctx {
-            ctx.remote_sdp = sdp;
-            self.start_rtp_streams_from_ctx();
+        let mut local_sdp = self.calls[pos].local_sdp.clone();
+        let direction = sdp
+            .as_ref()
+            .map_or(Direction::SendRecv, |offer| offer.media.direction.answer(Direction::SendRecv));
+        local_sdp.set_direction(direction);
+        let offered = sdp.is_some();
+        let old = std::mem::replace(
+            &mut self.calls[pos],
+            CallContext {
+                handle,
+                invite: req.clone(),
+                caller: Some(caller),
+                remote_sdp: sdp,
+                local_sdp: local_sdp.clone(),
+                ring_deadline: None,
+                auto_answer_at: None,
+                ring_pattern: RingPattern::Normal,
+                remote_addr,
+                held: false,
+                forks: Vec::new(),
+                early_media: None,
+                referred_by: None,
+                consult_for: None,
+            },
+        );
+
+        if let Err(e) = self.send_response_200_ok_with_sdp(handle, &req, remote_addr, &local_sdp) {
+            log::warn!("Failed to send 200 OK: {:?}", e);
+        }
+        if offered {
+            self.start_rtp_streams(handle);
         }
 
-        // For now, just acknowledge with our current local SDP
-        if let Some(ctx) = &self.call_ctx {
-            let local_sdp = ctx.local_sdp.clone();
-            if let Err(e) = self.send_response_200_ok_with_sdp(&req, remote_addr, &local_sdp) {
-                log::warn!("failed to respond to re-INVITE: {:?}", e);
+        for fork in &old.forks {
+            if let Some(dialog) = self.core.dialogs.get_mut(*fork) {
+                dialog.terminate_local();
             }
+        }
+        self.hang_up_call(old.handle);
+    }
+
+    /// UPDATE changes the session like a re-INVITE, but is answered at
+    /// once and may also come before the call is answered.
+    fn on_incoming_update(
+        &mut self,
+        handle: DialogHandle,
+        req: sip_core::Request,
+        remote_addr: TransportAddr,
+    ) {
+        let offer = (!req.body.is_empty()).then(|| sdp::parse(req.body.as_str()));
+        let sdp = match offer.transpose() {
+            Ok(s) => s,
+            Err(e) => {
+                log::warn!("failed to parse SDP on UPDATE: {:?}", e);
+                if let Err(e) = self.send_response_488_not_acceptable_here(handle, &req, remote_addr) {
+                    log::warn!("failed to send 488: {:?}", e);
+                }
+                return;
+            }
+        };
+
+        let media_running = if self.is_established(handle) {
+            self.has_media(handle)
         } else {
-            log::warn!("re-INVITE received but no call context; sending 481");
-            if let Err(e) = self.send_response_481_call_does_not_exist(&req, remote_addr) {
+            self.call(handle).is_some_and(|ctx| ctx.early_media.is_some())
+        };
+        let Some(ctx) = self.call_mut(handle) else {
+            log::warn!("UPDATE received but no call context; sending 481");
+            if let Err(e) = self.send_response_481_call_does_not_exist(handle, &req, remote_addr) {
                 log::warn!("failed to send 481: {:?}", e);
             }
+            return;
+        };
+
+        // Without an offer the UPDATE only refreshes the session.
+        let offered = sdp.is_some();
+        if let Some(offer) = &sdp {
+            let direction = offer.media.direction.answer(ctx.wanted_direction());
+            ctx.local_sdp.set_direction(direction);
+        }
+        if offered {
+            ctx.remote_sdp = sdp;
+        }
+        let local_sdp = ctx.local_sdp.clone();
+
+        if offered && media_running {
+            self.start_rtp_streams(handle);
+        }
+        let answer = offered.then_some(&local_sdp);
+        if let Err(e) = self.send_response_200_ok_to_update(handle, &req, remote_addr, answer) {
+            log::warn!("failed to respond to UPDATE: {:?}", e);
+        }
+    }
+
+    fn on_update_response(&mut self, handle: DialogHandle, resp: &sip_core::Response) {
+        if !(200..300).contains(&resp.status_code) || resp.body.is_empty() {
+            return;
+        }
+
+        match sdp::parse(resp.body.as_str()) {
+            Ok(sdp) => {
+                let Some(ctx) = self.call_mut(handle) else {
+                    return;
+                };
+                ctx.remote_sdp = Some(sdp);
+                if self.is_established(handle) && self.has_media(handle) {
+                    self.start_rtp_streams(handle);
+                }
+            }
+            Err(e) => log::warn!("failed to parse SDP answer to UPDATE: {:?}", e),
         }
     }
 
-    fn on_incoming_initial_while_busy(&mut self, req: sip_core::Request, remote_addr: SocketAddr) {
-        if let Err(e) = self.send_response_486_busy_here(&req, remote_addr) {
+    fn on_incoming_initial_while_busy(
+        &mut self,
+        handle: DialogHandle,
+        req: sip_core::Request,
+        caller: &CallerIdentity,
+        remote_addr: TransportAddr,
+    ) {
+        self.reject_busy(handle, &req, Some(caller), remote_addr);
+        self.end_call(handle);
+    }
+
+    /// Turn a caller away because we're busy: forwarded if we forward on
+    /// busy, 486 otherwise.
+    fn reject_busy(
+        &mut self,
+        handle: DialogHandle,
+        invite: &sip_core::Request,
+        caller: Option<&CallerIdentity>,
+        remote_addr: TransportAddr,
+    ) {
+        let reason = ForwardReason::Busy;
+        let sent = match self.forward_target(caller, reason) {
+            Some(target) => {
+                log::info!("Forwarding {} to {} on busy", handle, target);
+                self.send_response_302_moved_temporarily(handle, invite, remote_addr, target, reason)
+            }
+            None => self.send_response_486_busy_here(handle, invite, remote_addr),
+        };
+        if let Err(e) = sent {
             log::warn!("failed to respond to INVITE: {:?}", e);
         }
     }
 
+    /// Turn a caller away during do-not-disturb: forwarded if we forward
+    /// then, otherwise with the configured status.
+    fn reject_dnd(
+        &mut self,
+        handle: DialogHandle,
+        invite: &sip_core::Request,
+        caller: Option<&CallerIdentity>,
+        remote_addr: TransportAddr,
+    ) {
+        let reason = ForwardReason::DoNotDisturb;
+        let sent = match self.forward_target(caller, reason) {
+            Some(target) => {
+                log::info!("Forwarding {} to {} for do-not-disturb", handle, target);
+                self.send_response_302_moved_temporarily(handle, invite, remote_addr, target, reason)
+            }
+            None => match self.settings.sip_dnd_response {
+                480 => self.send_response_480_temporarily_unavailable(handle, invite, remote_addr),
+                603 => self.send_response_603_decline(handle, invite, remote_addr),
+                _ => self.send_response_486_busy_here(handle, invite, remote_addr),
+            },
+        };
+        if let Err(e) = sent {
+            log::warn!("failed to respond to INVITE: {:?}", e);
+        }
+    }
+
+    /// Whether the caller is on the do-not-disturb allow list.
+    fn rings_through_dnd(&self, caller: &CallerIdentity) -> bool {
+        self.settings
+            .sip_dnd_allow
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .any(|pattern| caller.matches(pattern))
+    }
+
+    /// The ring `sip_ring_patterns` picks for the INVITE's Alert-Info: that
+    /// of the first rule naming one of its URIs or info values.
+    fn ring_pattern(&self, invite: &sip_core::Request) -> RingPattern {
+        let alerts = alert_info(&invite.headers);
+        self.settings
+            .sip_ring_patterns
+            .split(',')
+            .filter_map(|rule| rule.rsplit_once('='))
+            .find(|(alert, _)| {
+                let alert = alert.trim().trim_start_matches('<').trim_end_matches('>');
+                alerts.iter().any(|a| a.eq_ignore_ascii_case(alert))
+            })
+            .and_then(|(_, pattern)| RingPattern::parse(pattern))
+            .unwrap_or_default()
+    }
+
+    /// Whether the caller of an intercom call is trusted to open it by
+    /// itself: its source IP, or the caller, is in `sip_auto_answer_from`.
+    fn may_auto_answer(&self, caller: &CallerIdentity, remote_addr: TransportAddr) -> bool {
+        self.settings
+            .sip_auto_answer_from
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .any(|pattern| match pattern.parse::<IpAddr>() {
+                Ok(ip) => ip == remote_addr.addr.ip(),
+                Err(_) => caller.matches(pattern),
+            })
+    }
+
+    /// Log what admission turned away since last time, if anything.
+    fn log_admission_stats(&mut self, now: Instant) {
+        if now < self.next_admission_log {
+            return;
+        }
+        self.next_admission_log = now + ADMISSION_LOG_INTERVAL;
+        let stats = self.admission.stats();
+        if stats != self.logged_admission {
+            log::info!(
+                "admission: dropped {} from denied sources, {} from scanners, {} rate limited \
+                 ({} bans); refused {} callers",
+                stats.denied_sources,
+                stats.scanners,
+                stats.rate_limited,
+                stats.bans,
+                stats.denied_callers,
+            );
+            self.logged_admission = stats;
+        }
+    }
+
+    /// Follow the quiet hours and the manual switch, and show the result.
+    fn update_dnd(&mut self) {
+        let quiet = local_minute_of_day(self.settings.utc_offset_minutes)
+            .is_some_and(|minute| in_quiet_hours(self.settings.sip_dnd_quiet_hours, minute));
+        let active = self.dnd || quiet;
+        if active != self.dnd_active {
+            self.dnd_active = active;
+            log::info!("do-not-disturb -> {}", active);
+            let _ = self.ui_tx.send(UiCommand::Dnd(active));
+        }
+    }
+
+    /// Where to forward a call for `reason`, if anywhere. A rule for the
+    /// caller forwards it whatever the reason.
+    fn forward_target(
+        &self,
+        caller: Option<&CallerIdentity>,
+        reason: ForwardReason,
+    ) -> Option<&'static str> {
+        let rule = caller.and_then(|caller| caller_rule(self.settings.sip_forward_callers, caller));
+        if rule.is_some() {
+            return rule;
+        }
+        let target = match reason {
+            ForwardReason::Unconditional => self.settings.sip_forward_always,
+            ForwardReason::Busy => self.settings.sip_forward_busy,
+            ForwardReason::NoAnswer => self.settings.sip_forward_no_answer,
+            ForwardReason::DoNotDisturb => self.settings.sip_dnd_forward,
+        };
+        (!target.is_empty()).then_some(target)
+    }
+
+    /// How long an incoming call rings before we give up on it, or forward
+    /// it if we forward on no answer.
+    fn no_answer_timeout(&self) -> Duration {
+        let after = self.settings.sip_forward_no_answer_after;
+        if self.settings.sip_forward_no_answer.is_empty() || after <= 0 {
+            return self.ring_timeout;
+        }
+        Duration::from_secs(after as u64)
+    }
+
+    // --- Calls ---------------------------------------------------------------
+
+    fn call(&self, handle: DialogHandle) -> Option<&CallContext> {
+        self.calls
+            .iter()
+            .find(|c| c.handle == handle || c.forks.contains(&handle))
+    }
+
+    fn call_mut(&mut self, handle: DialogHandle) -> Option<&mut CallContext> {
+        self.calls
+            .iter_mut()
+            .find(|c| c.handle == handle || c.forks.contains(&handle))
+    }
+
+    fn is_established(&self, handle: DialogHandle) -> bool {
+        self.core
+            .dialogs
+            .get(handle)
+            .is_some_and(|d| matches!(d.state, sip_core::DialogState::Established { .. }))
+    }
+
+    /// The incoming call that is ringing, if any.
+    fn ringing_call(&self) -> Option<DialogHandle> {
+        self.calls.iter().find(|c| c.ring_deadline.is_some()).map(|c| c.handle)
+    }
+
+    /// A ringing call that arrived while we were already in a call.
+    fn waiting_call(&self) -> Option<DialogHandle> {
+        self.ringing_call().filter(|_| self.calls.len() > 1)
+    }
+
+    /// The call we're talking on (or dialing out on).
+    fn active_call(&self) -> Option<DialogHandle> {
+        self.calls
+            .iter()
+            .find(|c| c.ring_deadline.is_none() && !c.held)
+            .map(|c| c.handle)
+    }
+
+    fn held_call(&self) -> Option<DialogHandle> {
+        self.calls.iter().find(|c| c.held).map(|c| c.handle)
+    }
+
+    /// Whether the call's media should run: it isn't on hold, or it is
+    /// but still has the (paused) RTP stream.
+    fn has_media(&self, handle: DialogHandle) -> bool {
+        self.call(handle).is_some_and(|ctx| {
+            !ctx.held || self.rtp_stream.as_ref().is_some_and(|s| s.handle == ctx.handle)
+        })
+    }
+
+    /// Put a call on hold: the peer gets a sendonly offer and our
+    /// transmit pauses. The dialog stays up.
+    fn hold_call(&mut self, handle: DialogHandle) {
+        log::info!("Call {} on hold", handle);
+        self.set_held(handle, true);
+    }
+
+    fn resume_call(&mut self, handle: DialogHandle) {
+        log::info!("Resuming call {}", handle);
+        self.set_held(handle, false);
+    }
+
+    /// Re-offer the call's media (RFC 3264 §8.4) with the direction for
+    /// `held`, and apply it to the RTP stream right away.
+    fn set_held(&mut self, handle: DialogHandle, held: bool) {
+        let Some(ctx) = self.call_mut(handle) else {
+            return;
+        };
+        ctx.held = held;
+        let direction = ctx.wanted_direction();
+        ctx.local_sdp.set_direction(direction);
+        let body = ctx.local_sdp.render().unwrap_or_default();
+
+        match self
+            .core
+            .build_reinvite(handle, Some(("application/sdp", &body)), Instant::now())
+        {
+            Ok(reinvite) => {
+                log::info!("Sending re-INVITE ({:?}) for {}", direction, handle);
+                self.send_request(&reinvite);
+            }
+            Err(e) => log::warn!("failed to build re-INVITE: {:?}", e),
+        }
+
+        if self.has_media(handle) {
+            self.start_rtp_streams(handle);
+        }
+        self.update_call_held();
+    }
+
+    /// End a call locally (no signalling) and forget it.
+    fn end_call(&mut self, handle: DialogHandle) {
+        if let Some(dialog) = self.core.dialogs.get_mut(handle) {
+            dialog.terminate_local();
+        }
+        self.forget_call(handle);
+    }
+
+    /// Drop the context of a call that ended, stopping RTP if the call had
+    /// the stream. A call whose INVITE forked lives on while any of its
+    /// early dialogs does.
+    fn forget_call(&mut self, handle: DialogHandle) {
+        if let Some(ctx) = self.call_mut(handle) {
+            if ctx.handle != handle {
+                ctx.forks.retain(|h| *h != handle);
+                return;
+            }
+            if let Some(next) = ctx.forks.pop() {
+                ctx.handle = next;
+                return;
+            }
+        }
+
+        if let Some(pos) = self.calls.iter().position(|c| c.handle == handle) {
+            let ctx = self.calls.remove(pos);
+            if let Some(referrer) = ctx.referred_by {
+                self.notify_refer(referrer, 503, "Service Unavailable");
+            }
+            if self.rtp_stream.as_ref().is_some_and(|s| self.call(s.handle).is_none()) {
+                self.stop_rtp_streams();
+            }
+            // A call placed for a REFER may have taken the stream from
+            // the call we're still on.
+            if let Some(active) = self.active_call().filter(|h| self.is_established(*h)) {
+                if self.rtp_stream.is_none() {
+                    self.start_rtp_streams(active);
+                }
+            }
+        }
+        self.broadcast_phone_state();
+        self.update_call_waiting();
+        self.update_call_held();
+    }
+
+    fn update_call_waiting(&mut self) {
+        let waiting = self.waiting_call().is_some();
+        if waiting != self.call_waiting {
+            self.call_waiting = waiting;
+            log::info!("call waiting -> {}", waiting);
+            let _ = self.ui_tx.send(UiCommand::CallWaiting(waiting));
+        }
+    }
+
+    fn update_call_held(&mut self) {
+        let held = self.active_call().is_none() && self.held_call().is_some();
+        if held != self.call_held {
+            self.call_held = held;
+            log::info!("call held -> {}", held);
+            let _ = self.ui_tx.send(UiCommand::CallHeld(held));
+        }
+    }
+
     // --- Network responses ---------------------------------------------------
 
-    fn send_response_180_ringing(&mut self, invite: &sip_core::Request, remote_addr: SocketAddr) -> Result<(), sip_core::SipError> {
+    /// Send a response to a request that arrived from `source`, routed by
+    /// its top Via, and track it for retransmission.
+    fn send_response(
+        &mut self,
+        resp: &sip_core::Response,
+        source: TransportAddr,
+    ) -> Result<(), sip_core::SipError> {
+        let target = sip_core::response_target(resp, source)?;
+        let text = resp.render()?;
+        self.core.record_outgoing_response(resp, target, Instant::now());
+        self.send_to(target, None, &text);
+        Ok(())
+    }
+
+    fn send_response_180_ringing(
+        &mut self,
+        handle: DialogHandle,
+        invite: &sip_core::Request,
+        remote_addr: TransportAddr,
+    ) -> Result<(), sip_core::SipError> {
         let resp = self
             .core
-            .dialog
-            .build_response_for_request(invite, 180, "Ringing", None)?;
+            .dialogs
+            .build_response(Some(handle), invite, 180, "Ringing", None)?;
 
-        let text = resp.render()?;
-        self.core.record_outgoing_response(&resp, remote_addr, Instant::now());
         log::debug!("Sending 180 Ringing");
-        send_sip_addr(&self.sip_socket, remote_addr, &text);
-        Ok(())
+        self.send_response(&resp, remote_addr)
+    }
+
+    /// 183 with the same SDP the 200 will carry, so RTP can start while
+    /// the phone rings.
+    fn send_response_183_session_progress(
+        &mut self,
+        handle: DialogHandle,
+        invite: &sip_core::Request,
+        remote_addr: TransportAddr,
+        local_sdp: &SessionDescription,
+    ) -> Result<(), sip_core::SipError> {
+        let body = local_sdp.render().unwrap_or_default();
+        let resp = self.core.dialogs.build_response(
+            Some(handle),
+            invite,
+            183,
+            "Session Progress",
+            Some(("application/sdp", &body)),
+        )?;
+
+        log::debug!("Sending 183 Session Progress");
+        self.send_response(&resp, remote_addr)
     }
 
     fn send_response_200_ok_with_sdp(
         &mut self,
+        handle: DialogHandle,
         invite: &sip_core::Request,
-        remote_addr: SocketAddr,
+        remote_addr: TransportAddr,
         local_sdp: &SessionDescription,
     ) -> Result<(), sip_core::SipError> {
         let body = local_sdp.render().unwrap_or_default();
         let mut resp = self
             .core
-            .dialog
-            .build_response_for_request(invite, 200, "OK", Some(("application/sdp", &body)))?;
+            .dialogs
+            .build_response(Some(handle), invite, 200, "OK", Some(("application/sdp", &body)))?;
 
         let contact_uri = build_contact_uri(
             self.settings.sip_contact,
             &self.local_ip,
             self.local_sip_port,
+            self.transport,
         );
         let contact_value = format!("<{}>", contact_uri);
         resp.add_header(sip_core::Header::new("Contact", &contact_value)?);
 
-        let text = resp.render()?;
-        self.core.record_outgoing_response(&resp, remote_addr, Instant::now());
         log::debug!("Sending 200 OK");
-        send_sip_addr(&self.sip_socket, remote_addr, &text);
-        Ok(())
+        self.send_response(&resp, remote_addr)
+    }
+
+    /// 200 to an UPDATE: carries our answer if it made an offer.
+    fn send_response_200_ok_to_update(
+        &mut self,
+        handle: DialogHandle,
+        update: &sip_core::Request,
+        remote_addr: TransportAddr,
+        local_sdp: Option<&SessionDescription>,
+    ) -> Result<(), sip_core::SipError> {
+        if let Some(local_sdp) = local_sdp {
+            return self.send_response_200_ok_with_sdp(handle, update, remote_addr, local_sdp);
+        }
+
+        let mut resp = self
+            .core
+            .dialogs
+            .build_response(Some(handle), update, 200, "OK", None)?;
+
+        let contact_uri = build_contact_uri(
+            self.settings.sip_contact,
+            &self.local_ip,
+            self.local_sip_port,
+            self.transport,
+        );
+        resp.add_header(sip_core::Header::new("Contact", &format!("<{}>", contact_uri))?);
+
+        log::debug!("Sending 200 OK to UPDATE");
+        self.send_response(&resp, remote_addr)
+    }
+
+    /// Send the caller to `target` instead (call forwarding).
+    fn send_response_302_moved_temporarily(
+        &mut self,
+        handle: DialogHandle,
+        invite: &sip_core::Request,
+        remote_addr: TransportAddr,
+        target: &str,
+        reason: ForwardReason,
+    ) -> Result<(), sip_core::SipError> {
+        let mut resp = self
+            .core
+            .dialogs
+            .build_response(Some(handle), invite, 302, "Moved Temporarily", None)?;
+        add_forwarding(&mut resp, invite, target, reason)?;
+
+        log::debug!("Sending 302 Moved Temporarily");
+        self.send_response(&resp, remote_addr)
+    }
+
+    fn send_response_403_forbidden(
+        &mut self,
+        handle: DialogHandle,
+        invite: &sip_core::Request,
+        remote_addr: TransportAddr
+    ) -> Result<(), sip_core::SipError> {
+        let resp = self
+            .core
+            .dialogs
+            .build_response(Some(handle), invite, 403, "Forbidden", None)?;
+
+        log::debug!("Sending 403 Forbidden");
+        self.send_response(&resp, remote_addr)
     }
 
     fn send_response_480_temporarily_unavailable(
         &mut self,
+        handle: DialogHandle,
         invite: &sip_core::Request,
-        remote_addr: SocketAddr
+        remote_addr: TransportAddr
     ) -> Result<(), sip_core::SipError> {
         let resp = self
             .core
-            .dialog
-            .build_response_for_request(invite, 480, "Temporarily Unavailable", None)?;
+            .dialogs
+            .build_response(Some(handle), invite, 480, "Temporarily Unavailable", None)?;
 
-        let text = resp.render()?;
-        self.core.record_outgoing_response(&resp, remote_addr, Instant::now());
         log::debug!("Sending 480 Temporarily Unavailable");
-        send_sip_addr(&self.sip_socket, remote_addr, &text);
-        Ok(())
+        self.send_response(&resp, remote_addr)
     }
 
     fn send_response_481_call_does_not_exist(
         &mut self,
+        handle: DialogHandle,
         invite: &sip_core::Request,
-        remote_addr: SocketAddr
+        remote_addr: TransportAddr
     ) -> Result<(), sip_core::SipError> {
         let resp = self
             .core
-            .dialog
-            .build_response_for_request(invite, 481, "Call/Transaction Does Not Exist", None)?;
+            .dialogs
+            .build_response(Some(handle), invite, 481, "Call/Transaction Does Not Exist", None)?;
 
-        let text = resp.render()?;
-        self.core.record_outgoing_response(&resp, remote_addr, Instant::now());
         log::debug!("Sending 481 Call/Transaction Does Not Exist");
-        send_sip_addr(&self.sip_socket, remote_addr, &text);
-        Ok(())
+        self.send_response(&resp, remote_addr)
     }
 
     fn send_response_486_busy_here(
         &mut self,
+        handle: DialogHandle,
         invite: &sip_core::Request,
-        remote_addr: SocketAddr
+        remote_addr: TransportAddr
     ) -> Result<(), sip_core::SipError> {
         let resp = self
             .core
-            .dialog
-            .build_response_for_request(invite, 486, "Busy Here", None)?;
+            .dialogs
+            .build_response(Some(handle), invite, 486, "Busy Here", None)?;
 
-        let text = resp.render()?;
-        self.core.record_outgoing_response(&resp, remote_addr, Instant::now());
         log::debug!("Sending 486 Busy Here");
-        send_sip_addr(&self.sip_socket, remote_addr, &text);
-        Ok(())
+        self.send_response(&resp, remote_addr)
+    }
+
+    fn send_response_433_anonymity_disallowed(
+        &mut self,
+        handle: DialogHandle,
+        invite: &sip_core::Request,
+        remote_addr: TransportAddr
+    ) -> Result<(), sip_core::SipError> {
+        let resp = self
+            .core
+            .dialogs
+            .build_response(Some(handle), invite, 433, "Anonymity Disallowed", None)?;
+
+        log::debug!("Sending 433 Anonymity Disallowed");
+        self.send_response(&resp, remote_addr)
+    }
+
+    fn send_response_603_decline(
+        &mut self,
+        handle: DialogHandle,
+        invite: &sip_core::Request,
+        remote_addr: TransportAddr
+    ) -> Result<(), sip_core::SipError> {
+        let resp = self
+            .core
+            .dialogs
+            .build_response(Some(handle), invite, 603, "Decline", None)?;
+
+        log::debug!("Sending 603 Decline");
+        self.send_response(&resp, remote_addr)
     }
 
     fn send_response_488_not_acceptable_here(
         &mut self,
+        handle: DialogHandle,
         invite: &sip_core::Request,
-        remote_addr: SocketAddr
+        remote_addr: TransportAddr
     ) -> Result<(), sip_core::SipError> {
         let resp = self
             .core
-            .dialog
-            .build_response_for_request(invite, 488, "Not Acceptable Here", None)?;
+            .dialogs
+            .build_response(Some(handle), invite, 488, "Not Acceptable Here", None)?;
 
-        let text = resp.render()?;
-        self.core.record_outgoing_response(&resp, remote_addr, Instant::now());
         log::debug!("Sending 488 Not Acceptable Here");
-        send_sip_addr(&self.sip_socket, remote_addr, &text);
-        Ok(())
+        self.send_response(&resp, remote_addr)
     }
 
     // --- Commands from UI / other tasks --------------------------------------
@@ -652,6 +1688,11 @@ impl SipTask {
             SipCommand::Button(event) => {
                 self.handle_button_event(event);
             }
+            SipCommand::ToggleDnd => {
+                self.dnd = !self.dnd;
+                log::info!("do-not-disturb {}", if self.dnd { "on" } else { "off" });
+                self.update_dnd();
+            }
         }
     }
 
@@ -666,55 +1707,270 @@ impl SipTask {
     }
 
     fn handle_answer(&mut self) {
-        match (&self.core.dialog.state, &self.call_ctx) {
-
-            // Incoming call, ringing: answer
-            (
-                sip_core::DialogState::Ringing { role, .. },
-                Some(ctx),
-            ) if *role == sip_core::DialogRole::Uas => {
-                // Clone what we need
-                let invite = ctx.invite.clone();
-                let local_sdp = ctx.local_sdp.clone();
-                let remote_addr = ctx.remote_addr;
-
-                // Build and send 200 OK + SDP, start RTP
-                if let Err(e) = self.send_response_200_ok_with_sdp(&invite, remote_addr, &local_sdp) {
-                    log::warn!("Failed to send 200 OK: {:?}", e);
+        // Incoming call ringing: answer it, holding the call we're on.
+        if let Some(handle) = self.ringing_call() {
+            if let Some(active) = self.active_call() {
+                self.hold_call(active);
+            }
+            self.answer_call(handle);
+            return;
+        }
+
+        match (self.active_call(), self.held_call()) {
+            // Idle: place a call to the configured target
+            (None, None) => {
+                self.place_call(self.settings.sip_target);
+            }
+
+            // Only a held call left: pick it back up
+            (None, Some(held)) => self.resume_call(held),
+
+            // Talking with another call on hold: swap
+            (Some(active), Some(held)) => {
+                self.hold_call(active);
+                self.resume_call(held);
+            }
+
+            // Button pressed during a single call: hold it
+            (Some(active), None) => {
+                if self.is_established(active) {
+                    self.hold_call(active);
                 }
+            }
+        }
+    }
+
+    fn answer_call(&mut self, handle: DialogHandle) {
+        let Some(ctx) = self.call_mut(handle) else {
+            return;
+        };
+        ctx.ring_deadline = None;
+        ctx.auto_answer_at = None;
+        if let Some(caller) = &ctx.caller {
+            log::info!("Answering {} from {}", handle, caller);
+        }
+
+        // Clone what we need
+        let invite = ctx.invite.clone();
+        let local_sdp = ctx.local_sdp.clone();
+        let remote_addr = ctx.remote_addr;
+
+        // Build and send 200 OK + SDP; RTP starts once the ACK arrives
+        if let Err(e) = self.send_response_200_ok_with_sdp(handle, &invite, remote_addr, &local_sdp) {
+            log::warn!("Failed to send 200 OK: {:?}", e);
+        }
+        self.update_call_waiting();
+    }
+
+    fn place_call(&mut self, target: &str) -> Option<DialogHandle> {
+        let local_sdp = self.build_local_sdp();
+        let body = local_sdp.render().unwrap_or_default();
+        let call_id = format!(
+            "{:08x}{:08x}@{}",
+            hardware::random_u32(),
+            hardware::random_u32(),
+            self.local_ip
+        );
+
+        let (handle, req) = match self.core.start_call(
+            target,
+            self.settings.sip_contact,
+            &call_id,
+            Some(("application/sdp", &body)),
+            Instant::now(),
+        ) {
+            Ok(r) => r,
+            Err(e) => {
+                log::warn!("failed to build INVITE: {:?}", e);
+                return None;
+            }
+        };
+
+        log::info!("Calling {} ({})", target, handle);
+        let Some(remote_addr) = self.send_request(&req) else {
+            self.end_call(handle);
+            return None;
+        };
+
+        self.calls.push(CallContext {
+            handle,
+            invite: req,
+            caller: None,
+            remote_sdp: None,
+            local_sdp,
+            ring_deadline: None,
+            auto_answer_at: None,
+            ring_pattern: RingPattern::Normal,
+            remote_addr,
+            held: false,
+            forks: Vec::new(),
+            early_media: None,
+            referred_by: None,
+            consult_for: None,
+        });
+        self.broadcast_phone_state();
+        self.update_call_held();
+        Some(handle)
+    }
+
+    fn handle_hangup(&mut self) {
+        // Call waiting: turn the new caller away, stay on the current call.
+        if let Some(waiting) = self.waiting_call() {
+            log::info!("Rejecting waiting call {}", waiting);
+            if let Some(ctx) = self.call(waiting) {
+                let (invite, caller, remote_addr) =
+                    (ctx.invite.clone(), ctx.caller.clone(), ctx.remote_addr);
+                self.reject_busy(waiting, &invite, caller.as_ref(), remote_addr);
+            }
+            self.end_call(waiting);
+            return;
+        }
+
+        // Established call, not ringing. A call on hold is transferred,
+        // if there's somewhere to transfer it to.
+        let Some(handle) = self.active_call() else {
+            if let Some(held) = self.held_call() {
+                self.transfer_call(held);
+            }
+            return;
+        };
 
-                // TODO: Flip the dialog state in core
-                if let Some(ref mut c) = self.call_ctx {
-                    c.ring_deadline = None;
+        // Talking to the transfer target: hand the held call over to it.
+        let consulted = self.call(handle).and_then(|ctx| ctx.consult_for);
+        if let Some(held) = consulted.filter(|_| self.is_established(handle)) {
+            match self.core.build_attended_refer(held, handle) {
+                Ok(refer) => {
+                    log::info!("Transferring {} to the peer of {}", held, handle);
+                    self.send_request(&refer);
                 }
+                Err(e) => log::warn!("failed to build REFER: {:?}", e),
+            }
+            return;
+        }
+        self.hang_up_call(handle);
+    }
 
+    /// Blind transfer: ask the peer to call `sip_transfer_target` instead
+    /// of us. We hang up once the NOTIFYs report it answered. An attended
+    /// transfer calls the target first and is completed from that call.
+    fn transfer_call(&mut self, handle: DialogHandle) {
+        let target = self.settings.sip_transfer_target;
+        if target.is_empty() {
+            return;
+        }
+        if self.settings.sip_transfer_attended {
+            log::info!("Consulting {} before transferring {}", target, handle);
+            let consultation = self.place_call(target);
+            if let Some(ctx) = consultation.and_then(|c| self.call_mut(c)) {
+                ctx.consult_for = Some(handle);
+            }
+            return;
+        }
+        match self.core.build_refer(handle, target) {
+            Ok(refer) => {
+                log::info!("Transferring {} to {}", handle, target);
+                self.send_request(&refer);
             }
+            Err(e) => log::warn!("failed to build REFER: {:?}", e),
+        }
+    }
 
-            // Button pressed in some other state
-            _ => {}
+    /// BYE the call if it was answered, CANCEL it if it is still ringing
+    /// out, then forget it.
+    fn hang_up_call(&mut self, handle: DialogHandle) {
+        if self.is_established(handle) {
+            match self.core.build_bye(handle) {
+                Ok(bye) => {
+                    log::info!("Sending BYE for {}", handle);
+                    self.send_request(&bye);
+                }
+                Err(e) => log::warn!("failed to build BYE: {:?}", e),
+            }
+        } else if let Ok(cancel) = self.core.cancel_call(handle, Instant::now()) {
+            // The stack keeps the dialogs of every fork until the 487 is
+            // ACKed, or a 2xx that crossed the CANCEL is hung up.
+            match cancel {
+                Some(cancel) => {
+                    log::info!("Sending CANCEL for {}", handle);
+                    self.send_request(&cancel);
+                }
+                None => log::info!("CANCEL for {} waits for a provisional response", handle),
+            }
+            let forks = self.call(handle).map(|ctx| ctx.forks.clone()).unwrap_or_default();
+            for fork in forks {
+                self.forget_call(fork);
+            }
+            self.forget_call(handle);
+            return;
         }
+        self.end_call(handle);
     }
 
-    fn handle_hangup(&mut self) {
-        match &self.call_ctx {
+    /// Send a request to its next hop (top Route or Request-URI), over the
+    /// transport the hop asks for (TLS for `sips:`), or TCP if it's too big
+    /// for UDP.
+    fn send_request(&mut self, req: &sip_core::Request) -> Option<TransportAddr> {
+        match req.next_hop() {
+            Ok(hop) => self.send_request_to(req, &hop),
+            Err(e) => {
+                log::warn!("no next hop for {} {}: {:?}", req.method, req.uri, e);
+                None
+            }
+        }
+    }
 
-            // Established call, not ringing
-            Some(ctx) if ctx.ring_deadline.is_none() => {
-                // TODO: Build BYE, send it
-                // Probably implement an "end dialog" helper in core
-                self.stop_rtp_streams();
-                self.core.dialog.terminate_local();
-                self.broadcast_phone_state();
-                self.call_ctx = None;
+    fn send_request_to(
+        &mut self,
+        req: &sip_core::Request,
+        hop: &sip_core::SipUri,
+    ) -> Option<TransportAddr> {
+        let (preferred, port) = sip_core::hop_transport(hop, self.transport);
+        let Some(addr) = resolve_host(&hop.host, port) else {
+            log::warn!("no route for {} {}", req.method, req.uri);
+            return None;
+        };
+
+        let mut req = req.clone();
+        let rendered = sip_core::select_transport(&mut req, preferred)
+            .and_then(|transport| Ok((transport, req.render()?)));
+        let (transport, text) = match rendered {
+            Ok(r) => r,
+            Err(e) => {
+                log::warn!("failed to render {}: {:?}", req.method, e);
+                return None;
             }
+        };
+
+        let target = TransportAddr::new(transport, addr);
+        self.send_to(target, Some(&hop.host), &text);
+        Some(target)
+    }
+
+    /// Send to `target`, reached as `host` if we know its name: TLS checks
+    /// the certificate against it.
+    fn send_to(&mut self, target: TransportAddr, host: Option<&str>, payload: &str) {
+        log::debug!("send_sip: to={:?}\r\n{}", target, payload);
+
+        let Some(transport) = self
+            .transports
+            .iter_mut()
+            .find(|t| t.kind() == target.transport)
+        else {
+            log::warn!("send_sip: no {} transport", target.transport);
+            return;
+        };
 
-            // Double-tap in some other state
-            _ => {}
+        let sent = match host {
+            Some(host) => transport.send_to_host(target.addr, host, payload.as_bytes()),
+            None => transport.send(target.addr, payload.as_bytes()),
+        };
+        if let Err(e) = sent {
+            log::warn!("send_sip: {} to {} failed: {:?}", target.transport, target.addr, e);
         }
     }
 
     fn handle_button_state_changed(&mut self, state: ButtonState) {
-        if let None = self.call_ctx {
+        if self.calls.is_empty() {
             return;
         }
 
@@ -735,54 +1991,93 @@ impl SipTask {
     fn build_local_sdp(&self) -> SessionDescription {
         SessionDescription {
             origin: "-".to_string(),
+            version: 0,
             connection_address: self.local_ip.clone(),
             media: MediaDescription {
                 port: self.local_rtp_port,
                 payload_type: 0, // PCMU/8000
                 codec: sdp::Codec::Pcmu,
+                direction: Direction::SendRecv,
             }
         }
     }
 
     fn check_call_timeouts(&mut self, now: Instant) {
-        let should_timeout = match (&self.core.dialog.state, &self.call_ctx) {
-            (
-                sip_core::DialogState::Ringing { role, .. },
-                Some(ctx),
-            ) if *role == sip_core::DialogRole::Uas => {
-                match ctx.ring_deadline {
-                    Some(deadline) => now >= deadline,
-                    None => false,
-                }
+        let due: Vec<DialogHandle> = self
+            .calls
+            .iter()
+            .filter(|c| c.auto_answer_at.is_some_and(|at| now >= at))
+            .map(|c| c.handle)
+            .collect();
+        for handle in due {
+            log::info!("Auto-answering intercom call {}", handle);
+            self.answer_call(handle);
+            if self.settings.sip_auto_answer_beep {
+                let _ = self.audio_tx.send(AudioCommand::Beep);
             }
-            _ => false,
-        };
-
-        if !should_timeout {
-            return;
+            // The caller talks first; PTT to answer back.
+            let _ = self.audio_tx.send(AudioCommand::SetMode(AudioMode::Listen));
         }
 
-        log::info!("Ringing timed out: sending 480 and returning to idle");
-
-        // Take the context out of self so we don't keep an immutable borrow
-        let ctx = match self.call_ctx.take() {
-            Some(ctx) => ctx,
-            None => return,
-        };
-
-        let _ = self.send_response_480_temporarily_unavailable(&ctx.invite, ctx.remote_addr);
+        let expired: Vec<DialogHandle> = self
+            .calls
+            .iter()
+            .filter(|c| c.ring_deadline.is_some_and(|deadline| now >= deadline))
+            .map(|c| c.handle)
+            .collect();
+
+        for handle in expired {
+            if let Some(ctx) = self.call(handle) {
+                let (invite, caller, remote_addr) =
+                    (ctx.invite.clone(), ctx.caller.clone(), ctx.remote_addr);
+                match self.forward_target(caller.as_ref(), ForwardReason::NoAnswer) {
+                    Some(target) => {
+                        log::info!("Ringing {} timed out: forwarding to {}", handle, target);
+                        let _ = self.send_response_302_moved_temporarily(
+                            handle,
+                            &invite,
+                            remote_addr,
+                            target,
+                            ForwardReason::NoAnswer,
+                        );
+                    }
+                    None => {
+                        log::info!("Ringing {} timed out: sending 480", handle);
+                        let _ = self
+                            .send_response_480_temporarily_unavailable(handle, &invite, remote_addr);
+                    }
+                }
+            }
 
-        // Move dialog to Terminated in core
-        self.stop_rtp_streams();
-        self.core.dialog.terminate_local();
-        self.broadcast_phone_state();
+            // Move dialog to Terminated in core
+            self.end_call(handle);
+        }
+    }
 
-        self.call_ctx = None;
+    /// The phone is in a call if any call is answered, ringing if a call
+    /// is being set up, idle otherwise.
+    fn phone_state(&self) -> PhoneState {
+        let states: Vec<PhoneState> = self
+            .calls
+            .iter()
+            .filter_map(|c| self.core.dialogs.get(c.handle))
+            .map(|d| dialog_state_to_phone_state(&d.state))
+            .collect();
+
+        if states.contains(&PhoneState::Established) {
+            PhoneState::Established
+        } else if states.contains(&PhoneState::Ringing) {
+            PhoneState::Ringing
+        } else {
+            PhoneState::Idle
+        }
     }
 
     fn broadcast_phone_state(&mut self) {
-        let phone = dialog_state_to_phone_state(&self.core.dialog.state);
+        let phone = self.phone_state();
+        let ring = self.calls.iter().find(|c| c.ring_deadline.is_some()).map(|c| c.ring_pattern);
 
+        let _ = self.ui_tx.send(UiCommand::RingPattern(ring.unwrap_or_default()));
         let _ = self
             .ui_tx
             .send(UiCommand::DialogStateChanged(phone.clone()));
@@ -790,66 +2085,109 @@ impl SipTask {
         let _ = self
             .audio_tx
             .send(AudioCommand::SetDialogState(phone));
+
+        let early_media = self
+            .calls
+            .iter()
+            .filter(|c| !self.is_established(c.handle))
+            .find_map(|c| c.early_media);
+        let _ = self.audio_tx.send(AudioCommand::EarlyMedia(early_media));
+
+        // The speaker rings only for a call that isn't waiting on another.
+        let ring = ring.filter(|_| self.settings.ring_sound && phone == PhoneState::Ringing);
+        let _ = self.audio_tx.send(AudioCommand::Ring(ring));
     }
 
     fn process_core_timers(&mut self, now: Instant) {
         let events = self.core.poll_timers(now);
         for ev in events {
-            let target = match &ev {
-                CoreEvent::SendResponseTo { target, .. } => *target,
-                _ => SocketAddr::from(([0, 0, 0, 0], 0)),
-            };
-            self.handle_core_event(ev, target);
+            self.handle_core_event(ev);
         }
     }
 }
 
 // --- Small helpers -----------------------------------------------------------
 
-fn send_sip(socket: &UdpSocket, target: &str, payload: &str) {
-    if let Ok(addr) = target.parse::<std::net::SocketAddr>() {
-        log::debug!("send_sip: to={:?}\r\n{}", addr, payload);
-        let _ = socket.send_to(payload.as_bytes(), addr);
-    } else if target.starts_with("sip:") {
-        // try stripping scheme
-        match target.trim_start_matches("sip:").parse::<std::net::SocketAddr>() {
-            Ok(addr) => {
-                log::debug!("send_sip: to={:?}\r\n{}", addr, payload);
-                let _ = socket.send_to(payload.as_bytes(), addr);
-            }
-            Err(e) => {
-                log::error!("send_sip: couldn't parse {} to SocketAddr: {:?}", target, e);
+fn resolve_host(host: &str, port: u16) -> Option<SocketAddr> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    (host, port).to_socket_addrs().ok()?.next()
+}
 
-            }
+/// Outbound TLS transport. The registrar's certificate must be issued for
+/// `sip_tls_server_name`, or its host if unset; other hosts' for their own
+/// name.
+fn tls_transport(settings: &crate::settings::Settings) -> Option<Box<dyn Transport + Send>> {
+    let registrar_host = sip_core::SipUri::parse(settings.sip_registrar)
+        .map(|uri| uri.host)
+        .unwrap_or_default();
+    let ca_pem = Some(settings.sip_tls_ca).filter(|ca| !ca.is_empty());
+    match TlsTransport::connect_to(&registrar_host, settings.sip_tls_server_name, ca_pem) {
+        Ok(tls) => Some(Box::new(tls)),
+        Err(e) => {
+            log::warn!("SIP TLS transport unavailable: {:?}", e);
+            None
         }
-    } else {
-        log::error!("send_sip: couldn't parse {} to SocketAddr", target);
     }
 }
 
-fn send_sip_addr(socket: &UdpSocket, addr: SocketAddr, payload: &str) {
-    log::debug!("send_sip_addr: to={:?}\r\n{}", addr, payload);
-    let _ = socket.send_to(payload.as_bytes(), addr);
+/// Target of the first `caller=target` rule in `rules` for `caller`.
+fn caller_rule<'a>(rules: &'a str, caller: &CallerIdentity) -> Option<&'a str> {
+    rules
+        .split(',')
+        .filter_map(|rule| rule.split_once('='))
+        .find(|(pattern, _)| caller.matches(pattern.trim()))
+        .map(|(_, target)| target.trim())
 }
 
-fn parse_uri(uri: &str) -> String {
-    let mut host = uri.trim_start_matches("sip:").to_string();
-    if !host.contains(':') {
-        host.push_str(":5060");
+/// Minutes since local midnight, `offset_minutes` east of UTC. `None`
+/// while the clock isn't set yet (SNTP hasn't answered).
+fn local_minute_of_day(offset_minutes: i64) -> Option<u32> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    // Any earlier and the clock still counts from boot.
+    if secs < 1_700_000_000 {
+        return None;
     }
-    host
+    Some((secs / 60 + offset_minutes).rem_euclid(24 * 60) as u32)
 }
 
-fn build_contact_uri(template: &str, ip: &str, port: u16) -> String {
+/// Whether `minute` of the day falls in one of the `HH:MM-HH:MM` ranges
+/// of `spec`. A range may run past midnight.
+fn in_quiet_hours(spec: &str, minute: u32) -> bool {
+    fn parse(time: &str) -> Option<u32> {
+        let (h, m) = time.trim().split_once(':')?;
+        let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+        (h < 24 && m < 60).then_some(h * 60 + m)
+    }
+
+    spec.split(',')
+        .filter_map(|range| range.split_once('-'))
+        .filter_map(|(start, end)| Some((parse(start)?, parse(end)?)))
+        .any(|(start, end)| {
+            if start <= end {
+                (start..end).contains(&minute)
+            } else {
+                minute >= start || minute < end
+            }
+        })
+}
+
+fn build_contact_uri(template: &str, ip: &str, port: u16, transport: TransportKind) -> String {
     let user_part = template
+        .trim_start_matches("sips:")
         .trim_start_matches("sip:")
         .split('@')
         .next()
         .unwrap_or(template);
-    format!("sip:{}@{}:{}", user_part, ip, port)
+    // A TLS contact is a sips: URI (RFC 5630); other transports are named
+    // with the transport parameter.
+    match transport.uri_param() {
+        _ if transport.is_secure() => format!("sips:{}@{}:{}", user_part, ip, port),
+        Some(param) => format!("sip:{}@{}:{};transport={}", user_part, ip, port, param),
+        None => format!("sip:{}@{}:{}", user_part, ip, port),
+    }
 }
 
-fn local_ip_port(sock: &UdpSocket) -> (String, u16) {
+fn local_ip_port(sock: &UdpTransport) -> (String, u16) {
     let addr = sock
         .local_addr()
         .unwrap_or_else(|_| "0.0.0.0:0".parse().unwrap());
@@ -5,7 +5,7 @@ use std::time::{Duration, Instant};
 use hardware::{ButtonState, LedState, UiDevice};
 
 use crate::messages::{
-    ButtonEvent, PhoneState, SipCommand, SipCommandSender, UiCommand, UiCommandReceiver
+    ButtonEvent, PhoneState, RingPattern, SipCommand, SipCommandSender, UiCommand, UiCommandReceiver
 };
 
 use crate::tasks::task::{AppTask, TaskMeta};
@@ -16,6 +16,11 @@ pub struct UiTask {
     sip_tx: SipCommandSender,
     phone_state: PhoneState,
     registered: bool,
+    server_reachable: bool,
+    call_waiting: bool,
+    call_held: bool,
+    dnd: bool,
+    ring_pattern: RingPattern,
     last_button_state: ButtonState,
     press_started_at: Option<Instant>,
     last_short_release_at: Option<Instant>,
@@ -23,8 +28,6 @@ pub struct UiTask {
     led_pattern: LedPattern,
     led_on: bool,
     next_blink_at: Instant,
-    #[cfg(not(target_os = "espidf"))]
-    auto_answer_deadline: Option<Instant>,
 }
 
 impl AppTask for UiTask {
@@ -50,6 +53,8 @@ impl UiTask {
     // should be tweaked for the desired UX.
     const SHORT_PRESS_MAX: Duration = Duration::from_millis(650);
     const DOUBLE_TAP_WINDOW: Duration = Duration::from_millis(400);
+    /// Holding this long while idle toggles do-not-disturb.
+    const LONG_PRESS_MIN: Duration = Duration::from_secs(2);
 
     pub fn new(
         ui_device: UiDevice,
@@ -58,7 +63,15 @@ impl UiTask {
     ) -> Self {
         let initial_state = ui_device.read_button_state();
         let now = Instant::now();
-        let initial_pattern = LedPattern::for_state(PhoneState::Idle, false);
+        let initial_pattern = LedPattern::for_status(LedStatus {
+            phone: PhoneState::Idle,
+            registered: false,
+            server_reachable: true,
+            call_waiting: false,
+            call_held: false,
+            dnd: false,
+            ring: RingPattern::Normal,
+        });
 
         Self {
             ui_device,
@@ -66,6 +79,11 @@ impl UiTask {
             sip_tx,
             phone_state: PhoneState::Idle,
             registered: false,
+            server_reachable: true,
+            call_waiting: false,
+            call_held: false,
+            dnd: false,
+            ring_pattern: RingPattern::Normal,
             last_button_state: initial_state,
             press_started_at: None,
             last_short_release_at: None,
@@ -76,8 +94,6 @@ impl UiTask {
                 + initial_pattern
                     .blink_period
                     .unwrap_or_else(|| Duration::from_secs(3600)),
-            #[cfg(not(target_os = "espidf"))]
-            auto_answer_deadline: None,
         }
     }
 
@@ -93,7 +109,6 @@ impl UiTask {
             }
 
             self.poll_button(now);
-            self.poll_auto_answer(now);
             self.update_led(now);
 
             thread::sleep(Self::POLL_INTERVAL);
@@ -101,29 +116,8 @@ impl UiTask {
     }
 
     fn handle_dialog_state_changed(&mut self, state: PhoneState) {
-        #[cfg(not(target_os = "espidf"))]
-        {
-            let now = Instant::now();
-            match state {
-                PhoneState::Ringing => {
-                    // Host-only: auto-answer is useful for testing without real button hardware.
-                    // Only arm if not already armed.
-                    if self.auto_answer_deadline.is_none() {
-                        self.auto_answer_deadline = Some(now + Duration::from_secs(3));
-                        log::info!("auto-answer armed for 3 seconds");
-                    }
-                }
-                _ => {
-                    // Any non-ringing state cancels the auto-answer.
-                    if self.auto_answer_deadline.take().is_some() {
-                        log::info!("auto-answer cancelled");
-                    }
-                }
-            }
-        }
-
         self.phone_state = state;
-        self.led_pattern = LedPattern::for_state(self.phone_state, self.registered);
+        self.led_pattern = LedPattern::for_status(self.led_status());
         // Force immediate update on next tick.
         self.last_led_state = None;
         self.led_on = true;
@@ -157,18 +151,42 @@ impl UiTask {
             }
             UiCommand::RegistrationStateChanged(registered) => {
                 self.registered = registered;
-                self.led_pattern = LedPattern::for_state(self.phone_state, self.registered);
-                self.last_led_state = None;
-                self.led_on = true;
-                self.next_blink_at = Instant::now()
-                    + self
-                        .led_pattern
-                        .blink_period
-                        .unwrap_or_else(|| Duration::from_secs(3600));
+                self.refresh_led_pattern();
+            }
+            UiCommand::ServerReachabilityChanged(reachable) => {
+                self.server_reachable = reachable;
+                self.refresh_led_pattern();
+            }
+            UiCommand::CallWaiting(waiting) => {
+                self.call_waiting = waiting;
+                self.refresh_led_pattern();
+            }
+            UiCommand::CallHeld(held) => {
+                self.call_held = held;
+                self.refresh_led_pattern();
+            }
+            UiCommand::Dnd(dnd) => {
+                self.dnd = dnd;
+                self.refresh_led_pattern();
+            }
+            UiCommand::RingPattern(ring) => {
+                self.ring_pattern = ring;
+                self.refresh_led_pattern();
             }
         }
     }
 
+    fn refresh_led_pattern(&mut self) {
+        self.led_pattern = LedPattern::for_status(self.led_status());
+        self.last_led_state = None;
+        self.led_on = true;
+        self.next_blink_at = Instant::now()
+            + self
+                .led_pattern
+                .blink_period
+                .unwrap_or_else(|| Duration::from_secs(3600));
+    }
+
     fn poll_button(&mut self, now: Instant) {
         let state = self.ui_device.read_button_state();
 
@@ -222,6 +240,10 @@ impl UiTask {
                             .sip_tx
                             .send(SipCommand::Button(ButtonEvent::ShortPress));
                     }
+                } else if held >= Self::LONG_PRESS_MIN && self.phone_state == PhoneState::Idle {
+                    log::info!("ui_task: long press detected (held {:?})", held);
+                    self.last_short_release_at = None;
+                    let _ = self.sip_tx.send(SipCommand::ToggleDnd);
                 } else {
                     log::info!(
                         "ui_task: press ignored/cancelled (held {:?}, short={:?})",
@@ -235,28 +257,20 @@ impl UiTask {
         self.last_button_state = state;
     }
 
-    #[cfg(not(target_os = "espidf"))]
-    fn poll_auto_answer(&mut self, now: Instant) {
-        // Host-only auto-answer for testing without a physical device.
-        if let Some(deadline) = self.auto_answer_deadline {
-            if now >= deadline {
-                log::info!("auto-answer timeout reached, simulating button");
-
-                // Send button pressed message after ring delay
-                let _ = self
-                    .sip_tx
-                    .send(SipCommand::Button(crate::messages::ButtonEvent::ShortPress));
-
-                self.auto_answer_deadline = None;
-            }
+    fn led_status(&self) -> LedStatus {
+        LedStatus {
+            phone: self.phone_state,
+            registered: self.registered,
+            server_reachable: self.server_reachable,
+            call_waiting: self.call_waiting,
+            call_held: self.call_held,
+            dnd: self.dnd,
+            ring: self.ring_pattern,
         }
     }
 
-    #[cfg(target_os = "espidf")]
-    fn poll_auto_answer(&mut self, _now: Instant) {}
-
     fn update_led(&mut self, now: Instant) {
-        let desired = LedPattern::for_state(self.phone_state, self.registered);
+        let desired = LedPattern::for_status(self.led_status());
 
         if desired != self.led_pattern {
             self.led_pattern = desired;
@@ -300,6 +314,18 @@ impl UiTask {
     }
 }
 
+/// What the LED shows the state of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LedStatus {
+    phone: PhoneState,
+    registered: bool,
+    server_reachable: bool,
+    call_waiting: bool,
+    call_held: bool,
+    dnd: bool,
+    ring: RingPattern,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct LedPattern {
     color: (u8, u8, u8),
@@ -307,18 +333,57 @@ struct LedPattern {
 }
 
 impl LedPattern {
-    fn for_state(phone: PhoneState, registered: bool) -> Self {
+    fn for_status(status: LedStatus) -> Self {
+        let LedStatus { phone, registered, server_reachable, call_waiting, call_held, dnd, ring } =
+            status;
         match phone {
-            PhoneState::Ringing => Self {
-                color: (255, 255, 0),
+            // Each ring pattern has its own colour and rate.
+            PhoneState::Ringing => match ring {
+                RingPattern::Normal => Self {
+                    color: (255, 255, 0),
+                    blink_period: Some(Duration::from_millis(300)),
+                },
+                RingPattern::Double => Self {
+                    color: (255, 255, 255),
+                    blink_period: Some(Duration::from_millis(150)),
+                },
+                RingPattern::Triple => Self {
+                    color: (0, 255, 255),
+                    blink_period: Some(Duration::from_millis(200)),
+                },
+                RingPattern::Urgent => Self {
+                    color: (255, 0, 0),
+                    blink_period: Some(Duration::from_millis(100)),
+                },
+            },
+            // Call waiting: blink blue at the ringing rate.
+            PhoneState::Established if call_waiting => Self {
+                color: (0, 0, 255),
                 blink_period: Some(Duration::from_millis(300)),
             },
+            // On hold: slow blue blink.
+            PhoneState::Established if call_held => Self {
+                color: (0, 0, 255),
+                blink_period: Some(Duration::from_millis(1200)),
+            },
             PhoneState::Established => Self {
                 color: (0, 0, 255),
                 blink_period: None,
             },
             PhoneState::Idle => {
-                if registered {
+                if !server_reachable {
+                    // Server unreachable: slow orange blink.
+                    Self {
+                        color: (255, 80, 0),
+                        blink_period: Some(Duration::from_millis(1500)),
+                    }
+                } else if registered && dnd {
+                    // Do-not-disturb: steady purple instead of green.
+                    Self {
+                        color: (128, 0, 255),
+                        blink_period: None,
+                    }
+                } else if registered {
                     Self {
                         color: (0, 255, 0),
                         blink_period: None,
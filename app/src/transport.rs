@@ -0,0 +1,707 @@
+//! Socket-backed implementations of `sip_core::Transport`.
+
+use std::io::ErrorKind::{Interrupted, WouldBlock};
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use sip_core::{StreamFramer, Transport, TransportKind};
+
+const UDP_RX_BUF: usize = 1500;
+const STREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const STREAM_WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const STREAM_MAX_CONNECTIONS: usize = 4;
+/// CRLF keep-alive interval for connections we opened (RFC 5626 §4.4.1),
+/// short enough to hold NAT bindings open.
+const STREAM_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(25);
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    rx_buf: [u8; UDP_RX_BUF],
+}
+
+impl UdpTransport {
+    pub fn bind(addr: IpAddr, port: u16) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind((addr, port))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            rx_buf: [0u8; UDP_RX_BUF],
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl Transport for UdpTransport {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Udp
+    }
+
+    fn send(&mut self, target: SocketAddr, payload: &[u8]) -> std::io::Result<()> {
+        self.socket.send_to(payload, target).map(|_| ())
+    }
+
+    fn poll_recv(&mut self) -> Option<(Vec<u8>, SocketAddr)> {
+        match self.socket.recv_from(&mut self.rx_buf) {
+            Ok((len, addr)) => Some((self.rx_buf[..len].to_vec(), addr)),
+            Err(ref e) if e.kind() == WouldBlock => None,
+            Err(e) => {
+                log::warn!("SIP UDP recv error: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+/// A connected byte stream the connection manager can drive. Reads must
+/// not block; `write_message` sends one whole message.
+pub trait SipStream: Read {
+    fn write_message(&mut self, payload: &[u8]) -> std::io::Result<()>;
+}
+
+/// Opens, and optionally accepts, the streams for one transport kind.
+pub trait Connector {
+    type Stream: SipStream;
+
+    fn kind(&self) -> TransportKind;
+
+    /// Open a stream to `target`, reached as `host` if we know its name.
+    fn connect(&mut self, target: SocketAddr, host: Option<&str>) -> std::io::Result<Self::Stream>;
+
+    /// Accept one pending inbound connection, if this transport listens.
+    fn accept(&mut self) -> Option<(Self::Stream, SocketAddr)> {
+        None
+    }
+}
+
+/// One open stream connection and its framing state.
+pub struct StreamConnection<S> {
+    stream: S,
+    pub peer: SocketAddr,
+    framer: StreamFramer,
+    outbound: bool,
+    last_activity: Instant,
+    last_sent: Instant,
+}
+
+impl<S: SipStream> StreamConnection<S> {
+    pub fn new(stream: S, peer: SocketAddr, outbound: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            stream,
+            peer,
+            framer: StreamFramer::new(),
+            outbound,
+            last_activity: now,
+            last_sent: now,
+        }
+    }
+
+    pub fn idle_for(&self, now: Instant) -> Duration {
+        now.duration_since(self.last_activity)
+    }
+
+    pub fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    pub fn write(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        self.stream.write_message(payload)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Read whatever is available and return the next framed message.
+    /// `Err(())` means the connection is closed or broken.
+    pub fn pump(&mut self) -> Result<Option<Vec<u8>>, ()> {
+        let mut buf = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    log::info!("SIP stream to {} closed by peer", self.peer);
+                    return Err(());
+                }
+                Ok(n) => {
+                    self.framer.push(&buf[..n]);
+                    self.touch();
+                }
+                Err(ref e) if e.kind() == WouldBlock => break,
+                Err(ref e) if e.kind() == Interrupted => continue,
+                Err(e) => {
+                    log::warn!("SIP stream to {} read error: {:?}", self.peer, e);
+                    return Err(());
+                }
+            }
+        }
+
+        self.framer.next_message().map_err(|e| {
+            log::warn!("SIP stream to {} framing error: {:?}", self.peer, e);
+        })
+    }
+}
+
+/// Connection management shared by the stream transports: reuse by peer,
+/// LRU eviction, CRLF keep-alives on connections we opened, and reconnect
+/// when a stale connection fails on send.
+pub struct StreamTransport<C: Connector> {
+    connector: C,
+    connections: Vec<StreamConnection<C::Stream>>,
+    closed: Vec<SocketAddr>,
+}
+
+impl<C: Connector> StreamTransport<C> {
+    pub fn new(connector: C) -> Self {
+        Self {
+            connector,
+            connections: Vec::new(),
+            closed: Vec::new(),
+        }
+    }
+
+    fn accept_new(&mut self) {
+        while let Some((stream, peer)) = self.connector.accept() {
+            log::info!("SIP {} connection from {}", self.connector.kind(), peer);
+            self.add_connection(StreamConnection::new(stream, peer, false));
+        }
+    }
+
+    fn add_connection(&mut self, conn: StreamConnection<C::Stream>) {
+        if self.connections.len() >= STREAM_MAX_CONNECTIONS {
+            // Drop the least recently used connection.
+            let now = Instant::now();
+            if let Some((idx, _)) = self
+                .connections
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, c)| c.idle_for(now))
+            {
+                log::info!("SIP closing idle connection to {}", self.connections[idx].peer);
+                self.drop_connection(idx);
+            }
+        }
+        self.connections.push(conn);
+    }
+
+    fn connect(&mut self, target: SocketAddr, host: Option<&str>) -> std::io::Result<usize> {
+        log::info!("SIP {} connecting to {}", self.connector.kind(), target);
+        let stream = self.connector.connect(target, host)?;
+        self.add_connection(StreamConnection::new(stream, target, true));
+        Ok(self.connections.len() - 1)
+    }
+
+    /// Remove a connection, remembering outbound ones so the application
+    /// can re-establish its flow to that peer.
+    fn drop_connection(&mut self, idx: usize) {
+        let conn = self.connections.remove(idx);
+        if conn.outbound {
+            self.closed.push(conn.peer);
+        }
+    }
+
+    fn send_keepalives(&mut self, now: Instant) {
+        let mut idx = 0;
+        while idx < self.connections.len() {
+            let conn = &mut self.connections[idx];
+            let due = conn.outbound
+                && now.duration_since(conn.last_sent) >= STREAM_KEEPALIVE_INTERVAL;
+            if due && conn.write(b"\r\n\r\n").is_err() {
+                log::warn!("SIP keep-alive to {} failed", conn.peer);
+                self.drop_connection(idx);
+                continue;
+            }
+            idx += 1;
+        }
+    }
+
+    fn send_message(
+        &mut self,
+        target: SocketAddr,
+        host: Option<&str>,
+        payload: &[u8],
+    ) -> std::io::Result<()> {
+        // Reuse an existing connection to this peer, inbound or outbound. If
+        // it turns out to be dead, fall through and reconnect once.
+        if let Some(idx) = self.connections.iter().position(|c| c.peer == target) {
+            match self.connections[idx].write(payload) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    log::info!("SIP connection to {} is stale ({:?}); reconnecting", target, e);
+                    self.drop_connection(idx);
+                }
+            }
+        }
+
+        let idx = self.connect(target, host)?;
+        let result = self.connections[idx].write(payload);
+        if result.is_err() {
+            self.drop_connection(idx);
+        }
+        result
+    }
+}
+
+impl<C: Connector> Transport for StreamTransport<C> {
+    fn kind(&self) -> TransportKind {
+        self.connector.kind()
+    }
+
+    fn send(&mut self, target: SocketAddr, payload: &[u8]) -> std::io::Result<()> {
+        self.send_message(target, None, payload)
+    }
+
+    fn send_to_host(
+        &mut self,
+        target: SocketAddr,
+        host: &str,
+        payload: &[u8],
+    ) -> std::io::Result<()> {
+        self.send_message(target, Some(host), payload)
+    }
+
+    fn poll_recv(&mut self) -> Option<(Vec<u8>, SocketAddr)> {
+        self.accept_new();
+
+        let now = Instant::now();
+        self.send_keepalives(now);
+
+        let mut idx = 0;
+        while idx < self.connections.len() {
+            let conn = &mut self.connections[idx];
+            match conn.pump() {
+                Ok(Some(msg)) => return Some((msg, conn.peer)),
+                Ok(None) if conn.outbound || conn.idle_for(now) < STREAM_IDLE_TIMEOUT => idx += 1,
+                _ => self.drop_connection(idx),
+            }
+        }
+        None
+    }
+
+    fn take_closed(&mut self) -> Vec<SocketAddr> {
+        core::mem::take(&mut self.closed)
+    }
+}
+
+pub struct TcpConnector {
+    listener: Option<TcpListener>,
+}
+
+impl TcpConnector {
+    /// Listen on `addr:port` for inbound connections. If the listener can't
+    /// be created we still support outbound connections.
+    pub fn bind(addr: IpAddr, port: u16) -> Self {
+        let listener = match TcpListener::bind((addr, port)) {
+            Ok(l) => {
+                if let Err(e) = l.set_nonblocking(true) {
+                    log::warn!("SIP TCP listener non-blocking failed: {:?}", e);
+                }
+                Some(l)
+            }
+            Err(e) => {
+                log::warn!("SIP TCP listener on {}:{} failed: {:?}", addr, port, e);
+                None
+            }
+        };
+
+        Self { listener }
+    }
+}
+
+impl Connector for TcpConnector {
+    type Stream = TcpStream;
+
+    fn kind(&self) -> TransportKind {
+        TransportKind::Tcp
+    }
+
+    fn connect(&mut self, target: SocketAddr, _host: Option<&str>) -> std::io::Result<TcpStream> {
+        let stream = TcpStream::connect_timeout(&target, STREAM_CONNECT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(stream)
+    }
+
+    fn accept(&mut self) -> Option<(TcpStream, SocketAddr)> {
+        let listener = self.listener.as_ref()?;
+        loop {
+            match listener.accept() {
+                Ok((stream, peer)) => match stream.set_nonblocking(true) {
+                    Ok(()) => return Some((stream, peer)),
+                    Err(e) => log::warn!("SIP TCP accept from {}: {:?}", peer, e),
+                },
+                Err(ref e) if e.kind() == WouldBlock => return None,
+                Err(e) => {
+                    log::warn!("SIP TCP accept error: {:?}", e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+impl SipStream for TcpStream {
+    /// Write a whole message on a non-blocking socket by briefly switching
+    /// it to blocking mode with a write timeout.
+    fn write_message(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        self.set_nonblocking(false)?;
+        self.set_write_timeout(Some(STREAM_WRITE_TIMEOUT))?;
+        let result = self.write_all(payload);
+        self.set_nonblocking(true)?;
+        result
+    }
+}
+
+pub type TcpTransport = StreamTransport<TcpConnector>;
+
+impl TcpTransport {
+    pub fn bind(addr: IpAddr, port: u16) -> Self {
+        StreamTransport::new(TcpConnector::bind(addr, port))
+    }
+}
+
+pub use tls::TlsTransport;
+
+/// The name a TLS peer's certificate must be issued for: the host we
+/// reached it as. `sip_tls_server_name` stands in for the registrar's
+/// host, e.g. when that is an address. A peer we know no name for (one a
+/// response has to reconnect to) can't be checked and is refused.
+struct TlsServerNames {
+    registrar_host: String,
+    registrar_name: String,
+}
+
+impl TlsServerNames {
+    fn new(registrar_host: &str, server_name: &str) -> Self {
+        let registrar_name = if server_name.is_empty() { registrar_host } else { server_name };
+        Self {
+            registrar_host: registrar_host.to_string(),
+            registrar_name: registrar_name.to_string(),
+        }
+    }
+
+    fn for_host<'a>(&'a self, host: Option<&'a str>) -> std::io::Result<&'a str> {
+        match host {
+            Some(host) if host.eq_ignore_ascii_case(&self.registrar_host) => {
+                Ok(&self.registrar_name)
+            }
+            Some(host) => Ok(host),
+            None => Err(std::io::Error::other("no host name to check the TLS certificate against")),
+        }
+    }
+}
+
+/// SIP over TLS using the ESP-IDF TLS stack. Outbound only: the device has
+/// no server certificate, so requests from the proxy arrive on the
+/// connection we opened to register.
+#[cfg(target_os = "espidf")]
+mod tls {
+    use std::ffi::CString;
+    use std::io::Read;
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Duration;
+
+    use esp_idf_svc::sys::{self, EspError};
+    use esp_idf_svc::tls::{Config, EspTls, X509};
+    use sip_core::TransportKind;
+
+    use super::{
+        Connector, SipStream, StreamTransport, TlsServerNames, STREAM_CONNECT_TIMEOUT,
+        STREAM_WRITE_TIMEOUT,
+    };
+
+    /// How long a read may wait for TLS records before reporting no data.
+    const TLS_POLL_TIMEOUT: Duration = Duration::from_millis(1);
+
+    pub struct TlsConnector {
+        names: TlsServerNames,
+        ca_pem: Option<CString>,
+    }
+
+    impl TlsConnector {
+        /// `ca_pem` is a PEM bundle of trusted CAs; putting the server's own
+        /// self-signed certificate here pins it. Without one, the built-in
+        /// certificate bundle is used.
+        fn new(names: TlsServerNames, ca_pem: Option<&str>) -> std::io::Result<Self> {
+            let ca_pem = ca_pem
+                .map(CString::new)
+                .transpose()
+                .map_err(|_| std::io::Error::other("SIP TLS CA bundle contains NUL"))?;
+            Ok(Self { names, ca_pem })
+        }
+    }
+
+    impl Connector for TlsConnector {
+        type Stream = TlsStream;
+
+        fn kind(&self) -> TransportKind {
+            TransportKind::Tls
+        }
+
+        fn connect(
+            &mut self,
+            target: SocketAddr,
+            host: Option<&str>,
+        ) -> std::io::Result<TlsStream> {
+            let server_name = self.names.for_host(host)?;
+            let stream = TcpStream::connect_timeout(&target, STREAM_CONNECT_TIMEOUT)?;
+            stream.set_nodelay(true)?;
+            stream.set_read_timeout(Some(TLS_POLL_TIMEOUT))?;
+            stream.set_write_timeout(Some(STREAM_WRITE_TIMEOUT))?;
+
+            let config = Config {
+                common_name: Some(server_name),
+                ca_cert: self.ca_pem.as_deref().map(X509::pem),
+                use_crt_bundle_attach: self.ca_pem.is_none(),
+                timeout_ms: STREAM_CONNECT_TIMEOUT.as_millis() as u32,
+                ..Default::default()
+            };
+
+            let mut tls = EspTls::adopt(stream).map_err(io_error)?;
+            tls.negotiate(server_name, &config).map_err(io_error)?;
+
+            log::info!("SIP TLS session established with {} ({})", server_name, target);
+            Ok(TlsStream { tls })
+        }
+    }
+
+    pub struct TlsStream {
+        tls: EspTls<TcpStream>,
+    }
+
+    impl Read for TlsStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.tls.read(buf).map_err(|e| match e.code() {
+                sys::ESP_TLS_ERR_SSL_WANT_READ | sys::ESP_TLS_ERR_SSL_TIMEOUT => {
+                    std::io::ErrorKind::WouldBlock.into()
+                }
+                _ => io_error(e),
+            })
+        }
+    }
+
+    impl SipStream for TlsStream {
+        fn write_message(&mut self, payload: &[u8]) -> std::io::Result<()> {
+            self.tls.write_all(payload).map_err(io_error)
+        }
+    }
+
+    fn io_error(e: EspError) -> std::io::Error {
+        std::io::Error::other(format!("{e}"))
+    }
+
+    pub type TlsTransport = StreamTransport<TlsConnector>;
+
+    impl TlsTransport {
+        /// Outbound TLS; `registrar_host` is checked against `server_name`
+        /// if set, any other host against its own name.
+        pub fn connect_to(
+            registrar_host: &str,
+            server_name: &str,
+            ca_pem: Option<&str>,
+        ) -> std::io::Result<Self> {
+            let names = TlsServerNames::new(registrar_host, server_name);
+            Ok(StreamTransport::new(TlsConnector::new(names, ca_pem)?))
+        }
+    }
+}
+
+/// SIP over TLS with rustls, for host builds. Outbound only, like the
+/// device: the peer's certificate is checked against `sip_tls_ca`, or the
+/// webpki roots without one.
+#[cfg(not(target_os = "espidf"))]
+mod tls {
+    use std::io::ErrorKind::InvalidData;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::Arc;
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+    use sip_core::TransportKind;
+
+    use super::{
+        Connector, SipStream, StreamTransport, TlsServerNames, STREAM_CONNECT_TIMEOUT,
+        STREAM_WRITE_TIMEOUT,
+    };
+
+    pub struct TlsConnector {
+        names: TlsServerNames,
+        config: Arc<ClientConfig>,
+    }
+
+    impl TlsConnector {
+        /// `ca_pem` is a PEM bundle of trusted CAs; putting the server's own
+        /// self-signed certificate here pins it.
+        fn new(names: TlsServerNames, ca_pem: Option<&str>) -> std::io::Result<Self> {
+            let mut roots = RootCertStore::empty();
+            match ca_pem {
+                Some(pem) => {
+                    for cert in CertificateDer::pem_slice_iter(pem.as_bytes()) {
+                        let cert = cert.map_err(|e| std::io::Error::new(InvalidData, e))?;
+                        roots.add(cert).map_err(|e| std::io::Error::new(InvalidData, e))?;
+                    }
+                    if roots.is_empty() {
+                        return Err(std::io::Error::new(InvalidData, "no certificate in CA bundle"));
+                    }
+                }
+                None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+            }
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let config = ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .map_err(std::io::Error::other)?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            Ok(Self { names, config: Arc::new(config) })
+        }
+    }
+
+    impl Connector for TlsConnector {
+        type Stream = TlsStream;
+
+        fn kind(&self) -> TransportKind {
+            TransportKind::Tls
+        }
+
+        fn connect(
+            &mut self,
+            target: SocketAddr,
+            host: Option<&str>,
+        ) -> std::io::Result<TlsStream> {
+            let server_name = self.names.for_host(host)?;
+            let name = ServerName::try_from(server_name.to_string())
+                .map_err(|e| std::io::Error::new(InvalidData, e))?;
+            let mut conn =
+                ClientConnection::new(self.config.clone(), name).map_err(std::io::Error::other)?;
+
+            let mut sock = TcpStream::connect_timeout(&target, STREAM_CONNECT_TIMEOUT)?;
+            sock.set_nodelay(true)?;
+            sock.set_read_timeout(Some(STREAM_CONNECT_TIMEOUT))?;
+            sock.set_write_timeout(Some(STREAM_WRITE_TIMEOUT))?;
+            while conn.is_handshaking() {
+                conn.complete_io(&mut sock)?;
+            }
+            sock.set_nonblocking(true)?;
+
+            log::info!("SIP TLS session established with {} ({})", server_name, target);
+            Ok(TlsStream { tls: StreamOwned::new(conn, sock) })
+        }
+    }
+
+    pub struct TlsStream {
+        tls: StreamOwned<ClientConnection, TcpStream>,
+    }
+
+    impl Read for TlsStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.tls.read(buf)
+        }
+    }
+
+    impl SipStream for TlsStream {
+        /// Like TCP: blocking, with the write timeout, for one message.
+        fn write_message(&mut self, payload: &[u8]) -> std::io::Result<()> {
+            self.tls.sock.set_nonblocking(false)?;
+            let result = self.tls.write_all(payload).and_then(|()| self.tls.flush());
+            self.tls.sock.set_nonblocking(true)?;
+            result
+        }
+    }
+
+    pub type TlsTransport = StreamTransport<TlsConnector>;
+
+    impl TlsTransport {
+        /// Outbound TLS; `registrar_host` is checked against `server_name`
+        /// if set, any other host against its own name.
+        pub fn connect_to(
+            registrar_host: &str,
+            server_name: &str,
+            ca_pem: Option<&str>,
+        ) -> std::io::Result<Self> {
+            let names = TlsServerNames::new(registrar_host, server_name);
+            Ok(StreamTransport::new(TlsConnector::new(names, ca_pem)?))
+        }
+    }
+}
+
+#[cfg(all(test, not(target_os = "espidf")))]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+    use super::*;
+
+    const OPTIONS: &[u8] = b"OPTIONS sip:localhost SIP/2.0\r\nContent-Length: 0\r\n\r\n";
+    const OK: &[u8] = b"SIP/2.0 200 OK\r\nContent-Length: 0\r\n\r\n";
+
+    /// A local TLS SIP server with a self-signed certificate for
+    /// `localhost` that answers every message with `OK`. Returns its
+    /// address and certificate.
+    fn self_signed_server() -> (SocketAddr, String) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.cert.der().clone()], key)
+            .unwrap();
+        let config = Arc::new(config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            // Keep connections open: the client reads the answer at leisure.
+            let mut open = Vec::new();
+            for sock in listener.incoming().flatten() {
+                let mut tls =
+                    StreamOwned::new(ServerConnection::new(config.clone()).unwrap(), sock);
+                let mut buf = [0u8; 512];
+                if matches!(tls.read(&mut buf), Ok(n) if n > 0) {
+                    tls.write_all(OK).and_then(|()| tls.flush()).unwrap();
+                    open.push(tls);
+                }
+            }
+        });
+        (addr, cert.cert.pem())
+    }
+
+    #[test]
+    fn tls_checks_each_hop_against_a_self_signed_server() {
+        let (server, cert_pem) = self_signed_server();
+
+        // Without the pinned certificate the server isn't trusted.
+        let mut unpinned = TlsTransport::connect_to("localhost", "", None).unwrap();
+        assert!(unpinned.send_to_host(server, "localhost", OPTIONS).is_err());
+
+        let mut tls = TlsTransport::connect_to("pbx.example.com", "", Some(&cert_pem)).unwrap();
+        // The certificate is for localhost, not the registrar; a peer we
+        // know no name for can't be checked at all.
+        assert!(tls.send_to_host(server, "pbx.example.com", OPTIONS).is_err());
+        assert!(tls.send(server, OPTIONS).is_err());
+
+        tls.send_to_host(server, "localhost", OPTIONS).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let answer = loop {
+            if let Some(answer) = tls.poll_recv() {
+                break answer;
+            }
+            assert!(Instant::now() < deadline, "no answer from the TLS server");
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(answer, (OK.to_vec(), server));
+
+        // The server name setting stands in for the registrar's host.
+        let mut named =
+            TlsTransport::connect_to("127.0.0.1", "localhost", Some(&cert_pem)).unwrap();
+        assert!(named.send_to_host(server, "127.0.0.1", OPTIONS).is_ok());
+    }
+}
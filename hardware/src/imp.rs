@@ -24,6 +24,7 @@ mod esp {
     use esp_idf_svc::eventloop::EspSystemEventLoop;
     use esp_idf_svc::wifi::{ClientConfiguration, Configuration, EspWifi};
     use esp_idf_svc::nvs::EspDefaultNvsPartition;
+    use esp_idf_svc::sntp::EspSntp;
     use esp_idf_sys::esp_eap_client_set_identity;
     use esp_idf_sys::EspError;
     use heapless::String;
@@ -34,6 +35,8 @@ mod esp {
     /// are implemented.
     pub struct DeviceInner {
         wifi: EspWifi<'static>,
+        /// Keeps the system clock set from NTP for as long as it lives.
+        _sntp: Option<EspSntp<'static>>,
         addr: Ipv4Addr,
         ui_device: Option<UiDevice>,
         audio_device: Option<AudioDevice>,
@@ -160,8 +163,19 @@ mod esp {
         log::info!("Wi-Fi connected");
         log::info!("IP: {}", ip);
 
+        // Wall-clock time, for schedules; synced in the background. Without
+        // it schedules just don't apply.
+        let sntp = match EspSntp::new_default() {
+            Ok(sntp) => Some(sntp),
+            Err(e) => {
+                log::warn!("SNTP unavailable: {}", e);
+                None
+            }
+        };
+
         Ok(DeviceInner {
             wifi,
+            _sntp: sntp,
             addr: ip,
             ui_device: Some(ui_dev),
             audio_device: Some(audio_dev),
@@ -6,16 +6,69 @@ pub enum Codec {
     Pcmu,
 }
 
+/// Which way media flows, seen from the side that wrote the SDP
+/// (RFC 3264 §5.1). Holding a call is a re-offer with `SendOnly` or
+/// `Inactive`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Direction {
+    #[default]
+    SendRecv,
+    SendOnly,
+    RecvOnly,
+    Inactive,
+}
+
+impl Direction {
+    fn from_flags(send: bool, receive: bool) -> Self {
+        match (send, receive) {
+            (true, true) => Direction::SendRecv,
+            (true, false) => Direction::SendOnly,
+            (false, true) => Direction::RecvOnly,
+            (false, false) => Direction::Inactive,
+        }
+    }
+
+    pub fn sends(self) -> bool {
+        matches!(self, Direction::SendRecv | Direction::SendOnly)
+    }
+
+    pub fn receives(self) -> bool {
+        matches!(self, Direction::SendRecv | Direction::RecvOnly)
+    }
+
+    /// Direction of the answer to an offer in direction `self`, when we'd
+    /// like `wanted` ourselves (RFC 3264 §6.1): a stream the offerer only
+    /// sends is one we only receive, and so on.
+    pub fn answer(self, wanted: Direction) -> Direction {
+        Direction::from_flags(
+            self.receives() && wanted.sends(),
+            self.sends() && wanted.receives(),
+        )
+    }
+
+    fn attribute(self) -> &'static str {
+        match self {
+            Direction::SendRecv => "sendrecv",
+            Direction::SendOnly => "sendonly",
+            Direction::RecvOnly => "recvonly",
+            Direction::Inactive => "inactive",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MediaDescription {
     pub port: u16,
     pub payload_type: u8,
     pub codec: Codec,
+    pub direction: Direction,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SessionDescription {
     pub origin: String,
+    /// Session version of the o= line; goes up with every change we offer.
+    pub version: u64,
     pub connection_address: String,
     pub media: MediaDescription,
 }
@@ -39,15 +92,26 @@ impl SessionDescription {
 
         Ok(Self {
             origin: origin_buf,
+            version: 0,
             connection_address: address_buf,
             media: MediaDescription {
                 port,
                 payload_type: 0,
                 codec: Codec::Pcmu,
+                direction: Direction::SendRecv,
             },
         })
     }
 
+    /// Change the media direction. A changed description gets the next
+    /// session version, as RFC 3264 §8 requires of a new offer.
+    pub fn set_direction(&mut self, direction: Direction) {
+        if self.media.direction != direction {
+            self.media.direction = direction;
+            self.version += 1;
+        }
+    }
+
     pub fn answer(&self, address: &str, port: u16) -> Result<Self, SdpError> {
         //TODO: RFC 3264: If the answer is different from the offer in any way
         //      (different IP addresses, ports, etc.), the origin line MUST be
@@ -62,10 +126,13 @@ impl SessionDescription {
         let mut out = String::new();
         writeln!(out, "v=0").map_err(|_| SdpError::Capacity)?;
         
-        //TODO: "session ID" and "session version" should not both be `0`,
-        //      it will work for now though.
-        writeln!(out, "o={} 0 0 IN IP4 {}", self.origin, self.connection_address)
-            .map_err(|_| SdpError::Capacity)?;
+        //TODO: "session ID" should not be `0`, it will work for now though.
+        writeln!(
+            out,
+            "o={} 0 {} IN IP4 {}",
+            self.origin, self.version, self.connection_address
+        )
+        .map_err(|_| SdpError::Capacity)?;
         writeln!(out, "s=-").map_err(|_| SdpError::Capacity)?;
         writeln!(out, "c=IN IP4 {}", self.connection_address).map_err(|_| SdpError::Capacity)?;
         writeln!(out, "t=0 0").map_err(|_| SdpError::Capacity)?;
@@ -77,18 +144,22 @@ impl SessionDescription {
         .map_err(|_| SdpError::Capacity)?;
         writeln!(out, "a=rtpmap:{} PCMU/8000", self.media.payload_type)
             .map_err(|_| SdpError::Capacity)?;
+        writeln!(out, "a={}", self.media.direction.attribute()).map_err(|_| SdpError::Capacity)?;
         Ok(out)
     }
 }
 
 pub fn parse(input: &str) -> Result<SessionDescription, SdpError> {
     let mut origin: Option<String> = None;
+    let mut version = 0;
     let mut address: Option<String> = None;
     let mut media_port: Option<u16> = None;
 
     // Payload type we will use for PCMU, if any
     let mut pcmu_pt = None;
     let mut saw_pcmu = false;
+    // Session-level, overridden by a media-level attribute (RFC 4566 §6).
+    let mut direction = Direction::SendRecv;
 
     for raw_line in input.lines() {
         let line = raw_line.trim_end_matches('\r');
@@ -109,6 +180,7 @@ pub fn parse(input: &str) -> Result<SessionDescription, SdpError> {
                 let mut buf = String::new();
                 buf.push_str(fields[0]);
                 origin = Some(buf);
+                version = fields[2].parse().unwrap_or(0);
             }
             "c" => {
                 let fields: Vec<&str> = rest.split_whitespace().collect();
@@ -145,6 +217,14 @@ pub fn parse(input: &str) -> Result<SessionDescription, SdpError> {
                     } else {
                         // Ignore telephone-event, G722, etc.
                     }
+                } else {
+                    match rest {
+                        "sendrecv" => direction = Direction::SendRecv,
+                        "sendonly" => direction = Direction::SendOnly,
+                        "recvonly" => direction = Direction::RecvOnly,
+                        "inactive" => direction = Direction::Inactive,
+                        _ => {}
+                    }
                 }
             }
             _ => {}
@@ -163,11 +243,13 @@ pub fn parse(input: &str) -> Result<SessionDescription, SdpError> {
 
     let sdp = SessionDescription {
         origin,
+        version,
         connection_address,
         media: MediaDescription {
             port,
             payload_type,
             codec: Codec::Pcmu,
+            direction,
         }
     };
 
@@ -203,5 +285,22 @@ a=rtpmap:0 PCMU/8000
         assert_eq!(parsed.media.port, 20000);
         assert_eq!(parsed.media.payload_type, 0);
         assert_eq!(parsed.media.codec, Codec::Pcmu);
+        assert_eq!(parsed.media.direction, Direction::SendRecv);
+    }
+
+    #[test]
+    fn hold_renders_direction_and_bumps_version() {
+        let mut sdp = SessionDescription::offer("atom-echo", "192.0.2.10", 10000).unwrap();
+        sdp.set_direction(Direction::SendOnly);
+        sdp.set_direction(Direction::SendOnly);
+        let rendered = sdp.render().unwrap();
+        assert!(rendered.contains("o=atom-echo 0 1 IN IP4 192.0.2.10"));
+        assert!(rendered.contains("a=sendonly"));
+
+        let parsed = parse(&rendered).unwrap();
+        assert_eq!(parsed.version, 1);
+        assert_eq!(parsed.media.direction, Direction::SendOnly);
+        assert_eq!(parsed.media.direction.answer(Direction::SendRecv), Direction::RecvOnly);
+        assert_eq!(parsed.media.direction.answer(Direction::SendOnly), Direction::Inactive);
     }
 }
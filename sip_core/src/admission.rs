@@ -0,0 +1,356 @@
+//! Call admission: who may make us ring at all. Anything that reaches our
+//! port can send an INVITE, so requests from outside our own proxy go
+//! through source and User-Agent filters and a per-source rate limit, and
+//! callers through allow and deny lists.
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::identity::CallerIdentity;
+use crate::message::{header_value, Request};
+use crate::{Result, SipError};
+
+/// Sources whose request rate we keep track of at once; the quietest one
+/// is forgotten to make room.
+const MAX_TRACKED_SOURCES: usize = 32;
+
+/// An address, or a range of them in CIDR notation (`192.0.2.0/24`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn parse(input: &str) -> Result<Self> {
+        let (addr, prefix) = match input.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (input.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| SipError::Invalid("ip address"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| SipError::Invalid("ip prefix"))?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(SipError::Invalid("ip prefix"));
+        }
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                prefix_matches(&range.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                prefix_matches(&range.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(range: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let (whole, bits) = ((prefix / 8) as usize, prefix % 8);
+    if range[..whole] != ip[..whole] {
+        return false;
+    }
+    bits == 0 || (range[whole] ^ ip[whole]) >> (8 - bits) == 0
+}
+
+/// What admission turned away since start-up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AdmissionStats {
+    /// Requests dropped for their source address.
+    pub denied_sources: u32,
+    /// Requests dropped for a scanner's User-Agent.
+    pub scanners: u32,
+    /// Requests dropped for coming too fast, or from a banned source.
+    pub rate_limited: u32,
+    /// Sources banned for going over the rate limit or scanning.
+    pub bans: u32,
+    /// Calls refused for their caller.
+    pub denied_callers: u32,
+}
+
+#[derive(Debug, Clone)]
+struct SourceRate {
+    ip: IpAddr,
+    window_start: Instant,
+    requests: u32,
+    banned_until: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AdmissionPolicy {
+    allow_sources: Vec<IpRange>,
+    deny_sources: Vec<IpRange>,
+    /// Lowercase User-Agent fragments of scanners.
+    deny_user_agents: Vec<String>,
+    allow_callers: Vec<String>,
+    deny_callers: Vec<String>,
+    /// Requests a source may send per `rate_window`; 0 means no limit.
+    rate_limit: u32,
+    rate_window: Duration,
+    ban: Duration,
+    sources: Vec<SourceRate>,
+    stats: AdmissionStats,
+}
+
+impl AdmissionPolicy {
+    /// Only requests from `allow` (if any) and not from `deny` get in; both
+    /// are comma-separated addresses or CIDR ranges.
+    pub fn set_sources(&mut self, allow: &str, deny: &str) -> Result<()> {
+        self.allow_sources = parse_ranges(allow)?;
+        self.deny_sources = parse_ranges(deny)?;
+        Ok(())
+    }
+
+    /// Drop requests whose User-Agent contains one of the comma-separated
+    /// `patterns`, ignoring case, and ban their source.
+    pub fn set_user_agent_denylist(&mut self, patterns: &str) {
+        self.deny_user_agents = split_list(patterns).map(str::to_ascii_lowercase).collect();
+    }
+
+    /// Only callers in `allow` (if any) and not in `deny` may call; both
+    /// are comma-separated users or URIs, as for `CallerIdentity::matches`.
+    pub fn set_callers(&mut self, allow: &str, deny: &str) {
+        self.allow_callers = split_list(allow).map(str::to_string).collect();
+        self.deny_callers = split_list(deny).map(str::to_string).collect();
+    }
+
+    /// Ban a source for `ban` once it sends more than `limit` requests in
+    /// `window`. A `limit` of 0 turns rate limiting off.
+    pub fn set_rate_limit(&mut self, limit: u32, window: Duration, ban: Duration) {
+        self.rate_limit = limit;
+        self.rate_window = window;
+        self.ban = ban;
+    }
+
+    pub fn stats(&self) -> AdmissionStats {
+        self.stats
+    }
+
+    /// Whether to pass on `req`, a request from `source` that did not come
+    /// from our proxy. Refused requests are to be dropped without an
+    /// answer, so scanners learn nothing. A `known` request, one of a
+    /// dialog or INVITE transaction we already have, is not counted
+    /// against the rate limit; every request goes through the source and
+    /// User-Agent filters.
+    pub fn admit_request(
+        &mut self,
+        req: &Request,
+        source: IpAddr,
+        known: bool,
+        now: Instant,
+    ) -> bool {
+        if self.deny_sources.iter().any(|range| range.contains(source))
+            || (!self.allow_sources.is_empty()
+                && !self.allow_sources.iter().any(|range| range.contains(source)))
+        {
+            self.stats.denied_sources = self.stats.denied_sources.saturating_add(1);
+            return false;
+        }
+
+        if self.is_banned(source, now) {
+            self.stats.rate_limited = self.stats.rate_limited.saturating_add(1);
+            return false;
+        }
+
+        let user_agent = header_value(&req.headers, "User-Agent").map(str::to_ascii_lowercase);
+        let scanner = user_agent.is_some_and(|ua| {
+            self.deny_user_agents.iter().any(|pattern| ua.contains(pattern.as_str()))
+        });
+        if scanner {
+            self.stats.scanners = self.stats.scanners.saturating_add(1);
+            self.ban_source(source, now);
+            return false;
+        }
+
+        if !known && !self.within_rate(source, now) {
+            self.stats.rate_limited = self.stats.rate_limited.saturating_add(1);
+            return false;
+        }
+        true
+    }
+
+    /// Whether `caller` may call us.
+    pub fn admit_caller(&mut self, caller: &CallerIdentity) -> bool {
+        let allowed = !self.deny_callers.iter().any(|pattern| caller.matches(pattern))
+            && (self.allow_callers.is_empty()
+                || self.allow_callers.iter().any(|pattern| caller.matches(pattern)));
+        if !allowed {
+            self.stats.denied_callers = self.stats.denied_callers.saturating_add(1);
+        }
+        allowed
+    }
+
+    fn is_banned(&self, source: IpAddr, now: Instant) -> bool {
+        self.sources
+            .iter()
+            .any(|rate| rate.ip == source && rate.banned_until.is_some_and(|until| now < until))
+    }
+
+    /// Count a request from `source`, banning it once it goes over the
+    /// limit.
+    fn within_rate(&mut self, source: IpAddr, now: Instant) -> bool {
+        if self.rate_limit == 0 {
+            return true;
+        }
+        let (limit, window) = (self.rate_limit, self.rate_window);
+        let rate = self.source_rate(source, now);
+        if now.duration_since(rate.window_start) >= window {
+            rate.window_start = now;
+            rate.requests = 0;
+        }
+        rate.requests += 1;
+        if rate.requests <= limit {
+            return true;
+        }
+        self.ban_source(source, now);
+        false
+    }
+
+    fn ban_source(&mut self, source: IpAddr, now: Instant) {
+        if self.ban.is_zero() {
+            return;
+        }
+        let until = now + self.ban;
+        let rate = self.source_rate(source, now);
+        rate.banned_until = Some(until);
+        // The ban starts a new window once it is over.
+        rate.window_start = until;
+        rate.requests = 0;
+        self.stats.bans = self.stats.bans.saturating_add(1);
+    }
+
+    fn source_rate(&mut self, source: IpAddr, now: Instant) -> &mut SourceRate {
+        if let Some(pos) = self.sources.iter().position(|rate| rate.ip == source) {
+            return &mut self.sources[pos];
+        }
+        if self.sources.len() >= MAX_TRACKED_SOURCES {
+            // Make room: a source no longer banned, else the ban ending first.
+            let evict = self
+                .sources
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, rate)| (rate.banned_until.filter(|until| *until > now), rate.window_start))
+                .map(|(pos, _)| pos)
+                .expect("sources is full");
+            self.sources.swap_remove(evict);
+        }
+        self.sources.push(SourceRate {
+            ip: source,
+            window_start: now,
+            requests: 0,
+            banned_until: None,
+        });
+        self.sources.last_mut().expect("just pushed")
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+pub(crate) fn parse_ranges(list: &str) -> Result<Vec<IpRange>> {
+    split_list(list).map(IpRange::parse).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Header, Method};
+
+    fn options(user_agent: &str) -> Request {
+        let mut req = Request::new(Method::Options, "sip:100@192.0.2.50").unwrap();
+        req.add_header(Header::new("To", "<sip:100@192.0.2.50>").unwrap()).unwrap();
+        req.add_header(Header::new("User-Agent", user_agent).unwrap()).unwrap();
+        req
+    }
+
+    #[test]
+    fn ip_ranges_match_by_prefix() {
+        let range = IpRange::parse("192.0.2.0/23").unwrap();
+        assert!(range.contains("192.0.3.200".parse().unwrap()));
+        assert!(!range.contains("192.0.4.1".parse().unwrap()));
+        assert!(IpRange::parse("2001:db8::/32").unwrap().contains("2001:db8:1::5".parse().unwrap()));
+        assert!(IpRange::parse("192.0.2.0/33").is_err());
+    }
+
+    #[test]
+    fn drops_scanners_and_floods_then_bans_the_source() {
+        let mut policy = AdmissionPolicy::default();
+        policy.set_user_agent_denylist("friendly-scanner, sipvicious");
+        policy.set_rate_limit(3, Duration::from_secs(10), Duration::from_secs(60));
+        let (scanner, flooder) = ("198.51.100.7".parse().unwrap(), "198.51.100.8".parse().unwrap());
+        let now = Instant::now();
+
+        assert!(!policy.admit_request(&options("Friendly-Scanner"), scanner, false, now));
+        // Banned, whatever it claims to be now.
+        assert!(!policy.admit_request(&options("Yealink"), scanner, false, now));
+
+        for _ in 0..3 {
+            assert!(policy.admit_request(&options("Yealink"), flooder, false, now));
+        }
+        assert!(!policy.admit_request(&options("Yealink"), flooder, false, now));
+        assert!(!policy.admit_request(&options("Yealink"), flooder, false, now + Duration::from_secs(30)));
+        assert!(policy.admit_request(&options("Yealink"), flooder, false, now + Duration::from_secs(61)));
+
+        let stats = policy.stats();
+        assert_eq!((stats.scanners, stats.rate_limited, stats.bans), (1, 3, 2));
+    }
+
+    #[test]
+    fn a_to_tag_gets_no_one_past_the_filters() {
+        let mut policy = AdmissionPolicy::default();
+        policy.set_sources("", "192.0.2.66").unwrap();
+        policy.set_user_agent_denylist("friendly-scanner");
+        policy.set_rate_limit(1, Duration::from_secs(10), Duration::from_secs(60));
+        let (denied, scanner, peer) = (
+            "192.0.2.66".parse().unwrap(),
+            "198.51.100.7".parse().unwrap(),
+            "192.0.2.10".parse().unwrap(),
+        );
+        let now = Instant::now();
+        let tagged = |user_agent: &str| {
+            let mut req = Request::new(Method::Invite, "sip:100@192.0.2.50").unwrap();
+            req.add_header(Header::new("To", "<sip:100@192.0.2.50>;tag=x").unwrap()).unwrap();
+            req.add_header(Header::new("User-Agent", user_agent).unwrap()).unwrap();
+            req
+        };
+
+        assert!(!policy.admit_request(&tagged("Yealink"), denied, false, now));
+        assert!(!policy.admit_request(&tagged("friendly-scanner"), scanner, false, now));
+        assert!(!policy.admit_request(&tagged("Yealink"), scanner, true, now));
+
+        // Requests of our own calls are not rate limited; strays are.
+        assert!(policy.admit_request(&tagged("Yealink"), peer, false, now));
+        assert!(policy.admit_request(&tagged("Yealink"), peer, true, now));
+        assert!(!policy.admit_request(&tagged("Yealink"), peer, false, now));
+    }
+
+    #[test]
+    fn filters_sources_and_callers() {
+        let mut policy = AdmissionPolicy::default();
+        policy.set_sources("192.0.2.0/24", "192.0.2.66").unwrap();
+        policy.set_callers("", "sip:spam@example.com, 666");
+        let now = Instant::now();
+
+        assert!(policy.admit_request(&options("Yealink"), "192.0.2.10".parse().unwrap(), false, now));
+        assert!(!policy.admit_request(&options("Yealink"), "192.0.2.66".parse().unwrap(), false, now));
+        assert!(!policy.admit_request(&options("Yealink"), "203.0.113.1".parse().unwrap(), false, now));
+
+        let caller = |from: &str| {
+            let mut req = options("Yealink");
+            req.add_header(Header::new("From", from).unwrap()).unwrap();
+            CallerIdentity::from_request(&req, false)
+        };
+        assert!(!policy.admit_caller(&caller("<sip:spam@EXAMPLE.com>;tag=1")));
+        assert!(!policy.admit_caller(&caller("<sip:666@192.0.2.9>;tag=1")));
+        assert!(policy.admit_caller(&caller("<sip:alice@example.com>;tag=1")));
+        assert_eq!(policy.stats().denied_callers, 2);
+    }
+}
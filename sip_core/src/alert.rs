@@ -0,0 +1,114 @@
+//! How an INVITE asks to be alerted: intercom and paging calls that want
+//! to be answered by themselves, and the ring a PBX picked for a call.
+
+use crate::message::{header_values, HeaderList};
+use crate::uri::split_header_values;
+
+/// Seconds after which the caller asks us to answer by ourselves, from
+/// `Call-Info: <uri>;answer-after=N`, or 0 for an `Alert-Info` with
+/// `info=alert-autoanswer`. Whether to honor it is up to the application.
+pub fn auto_answer_after(headers: &HeaderList) -> Option<u32> {
+    let call_info = header_params(headers, "Call-Info")
+        .find(|(name, _)| name.eq_ignore_ascii_case("answer-after"))
+        .and_then(|(_, value)| value?.parse().ok());
+    call_info.or_else(|| {
+        header_params(headers, "Alert-Info")
+            .any(|(name, value)| {
+                name.eq_ignore_ascii_case("info")
+                    && value.is_some_and(|v| v.eq_ignore_ascii_case("alert-autoanswer"))
+            })
+            .then_some(0)
+    })
+}
+
+/// What the INVITE's Alert-Info headers ask us to ring with: each entry's
+/// URI (e.g. `urn:alert:service:normal`, RFC 7462) and the `info`
+/// parameters many PBXes use instead (`info=ring2`), in header order.
+pub fn alert_info(headers: &HeaderList) -> Vec<&str> {
+    let mut out = Vec::new();
+    for entry in header_values(headers, "Alert-Info").into_iter().flat_map(split_header_values) {
+        if let (Some(open), Some(close)) = (entry.find('<'), entry.rfind('>')) {
+            let uri = entry[open + 1..close].trim();
+            if open < close && !uri.is_empty() {
+                out.push(uri);
+            }
+        }
+    }
+    out.extend(
+        header_params(headers, "Alert-Info")
+            .filter(|(name, _)| name.eq_ignore_ascii_case("info"))
+            .filter_map(|(_, value)| value),
+    );
+    out
+}
+
+/// Parameters of every entry of header `name`. The URI in front may be
+/// missing, as some PBXes send `Call-Info: ;answer-after=0` or
+/// `Alert-Info: info=ring2`.
+fn header_params<'a>(
+    headers: &'a HeaderList,
+    name: &str,
+) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+    header_values(headers, name)
+        .into_iter()
+        .flat_map(split_header_values)
+        .flat_map(|entry| {
+            let params = match entry.rfind('>') {
+                Some(close) => &entry[close + 1..],
+                None => entry,
+            };
+            params.split(';').filter(|param| !param.trim().is_empty())
+        })
+        .map(|param| match param.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (param.trim(), None),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Header;
+
+    fn headers(list: &[(&str, &str)]) -> HeaderList {
+        list.iter().map(|(name, value)| Header::new(name, value).unwrap()).collect()
+    }
+
+    #[test]
+    fn reads_answer_after_and_alert_autoanswer() {
+        assert_eq!(auto_answer_after(&headers(&[("Call-Info", ";answer-after=0")])), Some(0));
+        assert_eq!(
+            auto_answer_after(&headers(&[("Call-Info", "<sip:pbx.example.com>;answer-after=3")])),
+            Some(3)
+        );
+        assert_eq!(
+            auto_answer_after(&headers(&[("Alert-Info", "<http://127.0.0.1>;info=alert-autoanswer")])),
+            Some(0)
+        );
+        assert_eq!(
+            auto_answer_after(&headers(&[
+                ("Call-Info", "<http://www.example.com/alice/photo.jpg>;purpose=icon"),
+                ("Alert-Info", "<http://www.example.com/sounds/moo.wav>"),
+            ])),
+            None
+        );
+    }
+
+    #[test]
+    fn lists_alert_info_uris_and_info_params() {
+        let list = headers(&[
+            ("Alert-Info", "<urn:alert:source:external>, <urn:alert:priority:high>"),
+            ("Alert-Info", "<http://127.0.0.1/Bellcore-dr2>;info=ring2"),
+        ]);
+        assert_eq!(
+            alert_info(&list),
+            vec![
+                "urn:alert:source:external",
+                "urn:alert:priority:high",
+                "http://127.0.0.1/Bellcore-dr2",
+                "ring2",
+            ]
+        );
+        assert_eq!(alert_info(&headers(&[("Alert-Info", "info=ring3")])), vec!["ring3"]);
+    }
+}
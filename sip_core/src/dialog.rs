@@ -1,11 +1,25 @@
 use core::fmt::Write;
 use core::mem;
 use std::fmt::Display;
+use std::time::{Duration, Instant};
 
 use crate::{
-    CoreDialogEvent, CoreEvent, Result, SipError, header_value, message::{Header, Method, Request, Response}, stack::InviteKind
+    CoreDialogEvent, CoreEvent, Result, SipError, header_value,
+    message::{build_via, format_cseq, header_values, Header, HeaderList, Method, Request, Response},
+    dialog_manager::DialogHandle,
+    identity::ANONYMOUS_FROM,
+    redirect::Redirects,
+    refer::{Transfer, SIPFRAG},
+    replaces::{take_replaces, uri_with_replaces, Replaces},
+    session_timer::{SessionTimer, SessionTimerDue, MIN_SE},
+    stack::{send_request_event, LocalEndpoint},
+    transaction::ack_for_non_2xx,
+    uri::{bracketed, NameAddr},
 };
 
+/// Extensions we support, for `Supported` headers.
+pub(crate) const SUPPORTED_HEADER_VALUE: &str = "100rel, replaces, timer";
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DialogState {
     Idle,
@@ -60,20 +74,101 @@ pub struct CancelResult {
 
 #[derive(Debug, Default)]
 pub struct Dialog {
+    handle: DialogHandle,
     pub state: DialogState,
     pub cseq: u32,
     next_tag_counter: u32,
+    next_branch_counter: u32,
+
+    /// Route set (RFC 3261 §12.1), already in the order the entries must
+    /// appear as Route headers on in-dialog requests.
+    pub route_set: Vec<String>,
+    /// The peer's Contact URI, refreshed by target refresh requests.
+    pub remote_target: Option<String>,
+    /// Our side of the dialog (From for UAC, To for UAS), without tag.
+    local_party: String,
+    /// The peer's side of the dialog, without tag.
+    remote_party: String,
+    remote_cseq: Option<u32>,
+
+    /// CSeq number of the last INVITE we sent; ACK for 2xx reuses it.
+    invite_cseq: u32,
+    /// Our outstanding INVITE (initial or re-INVITE), until a final response.
+    pending_invite: Option<Request>,
+    /// ACK we sent for the last 2xx, resent on 2xx retransmissions.
+    last_ack: Option<Request>,
+    /// The initial INVITE we sent (UAC side). Responses from other forks
+    /// of it start sibling dialogs.
+    uac_invite: Option<Request>,
+    /// SDP went out or came in with a provisional response, so media flows
+    /// before the call is answered (RFC 3960).
+    early_media: bool,
+    /// We answered an offerless INVITE with our offer in the 2xx; the
+    /// answer is due in the ACK (RFC 3261 §13.2.1).
+    offer_in_2xx: bool,
+    /// RSeq of the last reliable provisional response we sent (RFC 3262).
+    local_rseq: u32,
+    /// RSeq of the last reliable provisional response we PRACKed, and the
+    /// PRACK, resent if that response is retransmitted.
+    remote_rseq: Option<u32>,
+    last_prack: Option<Request>,
+    /// Our UPDATE (RFC 3311), until a final response.
+    pending_update: Option<Request>,
+    /// When to send again an UPDATE the peer refused with 491, and its
+    /// body (RFC 3261 §14.1).
+    update_retry: Option<(Instant, Option<(String, String)>)>,
+    session_timer: SessionTimer,
+    /// The peer listed UPDATE in Allow, so session refreshes can use it
+    /// instead of re-INVITE.
+    peer_allows_update: bool,
+    /// The last SDP we sent, offered again in re-INVITE refreshes.
+    local_sdp: Option<String>,
+    /// REFER subscriptions, ours or the peer's (RFC 3515).
+    transfer: Transfer,
+    /// We CANCELled our initial INVITE; a 2xx that crosses the CANCEL is
+    /// ACKed and hung up at once.
+    cancelled: bool,
+    /// Targets from 3xx responses to our initial INVITE (RFC 3261 §8.1.3.4).
+    redirects: Redirects,
 }
 
 impl Dialog {
     pub fn new() -> Self {
+        Self::with_handle(DialogHandle::default())
+    }
+
+    /// A dialog the application knows as `handle`. Tags and branches are
+    /// numbered from the handle so concurrent dialogs never share one.
+    pub(crate) fn with_handle(handle: DialogHandle) -> Self {
+        let counter_base = (handle.0 << 16) | 1;
         Self {
+            handle,
             state: DialogState::Idle,
             cseq: 0,
-            next_tag_counter: 1,
+            next_tag_counter: counter_base,
+            next_branch_counter: counter_base,
+            ..Default::default()
         }
     }
 
+    pub fn handle(&self) -> DialogHandle {
+        self.handle
+    }
+
+    /// Whether a provisional response carried SDP, either one we received
+    /// on an outgoing call or one we sent on an incoming call.
+    pub fn early_media(&self) -> bool {
+        self.early_media
+    }
+
+    /// Event reporting the current state to the application.
+    pub(crate) fn state_event(&self) -> CoreEvent {
+        CoreEvent::Dialog(CoreDialogEvent::DialogStateChanged {
+            handle: self.handle,
+            state: self.state.clone(),
+        })
+    }
+
     fn allocate_tag(&mut self) -> String {
         let mut tag = String::new();
         let idx = self.next_tag_counter;
@@ -98,45 +193,782 @@ impl Dialog {
         }
     }
 
+    fn next_branch(&mut self) -> String {
+        let mut branch = String::new();
+        let idx = self.next_branch_counter;
+        self.next_branch_counter = self.next_branch_counter.wrapping_add(1);
+        let _ = write!(branch, "z9hG4bKdlg{:08x}", idx);
+        branch
+    }
+
+    fn reset_dialog_data(&mut self) {
+        self.route_set.clear();
+        self.remote_target = None;
+        self.local_party.clear();
+        self.remote_party.clear();
+        self.remote_cseq = None;
+        self.pending_invite = None;
+        self.last_ack = None;
+        self.uac_invite = None;
+        self.early_media = false;
+        self.offer_in_2xx = false;
+        self.remote_rseq = None;
+        self.last_prack = None;
+        self.pending_update = None;
+        self.update_retry = None;
+        self.session_timer = SessionTimer::default();
+        self.peer_allows_update = false;
+        self.local_sdp = None;
+        self.transfer = Transfer::default();
+        self.cancelled = false;
+        self.redirects = Redirects::default();
+    }
+
     /// Start an outgoing INVITE (UAC side).
-    pub fn start_outgoing(&mut self, target: &str) -> Result<Request> {
+    ///
+    /// `from_uri` is our address-of-record; `body` is an optional
+    /// (Content-Type, data) pair, normally the SDP offer.
+    pub fn start_outgoing(
+        &mut self,
+        target: &str,
+        from_uri: &str,
+        call_id: &str,
+        local: &LocalEndpoint,
+        body: Option<(&str, &str)>,
+    ) -> Result<Request> {
         if self.state != DialogState::Idle && self.state != DialogState::Terminated {
             return Err(SipError::InvalidState("dialog busy"));
         }
-        self.state = DialogState::Inviting;
+
+        self.reset_dialog_data();
         self.cseq = self.cseq.wrapping_add(1);
+        self.invite_cseq = self.cseq;
+
+        // A Refer-To from an attended transfer carries Replaces in the URI.
+        let (target, replaces) = take_replaces(target);
+        let local_tag = self.allocate_tag();
+        self.local_party = bracketed(from_uri)?;
+        self.remote_party = bracketed(&target)?;
+
+        let mut req = Request::new(Method::Invite, &target)?;
+        req.add_header(build_via(local.transport, &local.host, local.port, &self.next_branch())?)?;
+        req.add_header(Header::new("Max-Forwards", "70")?)?;
+        req.add_header(Header::new("From", &tagged(&self.local_party, &local_tag)?)?)?;
+        req.add_header(Header::new("To", &self.remote_party)?)?;
+        req.add_header(Header::new("Call-ID", call_id)?)?;
+        req.add_header(Header::new("CSeq", &format_cseq(self.cseq, "INVITE")?)?)?;
+        req.add_header(Header::new("Contact", &bracketed(&local.contact_uri)?)?)?;
+        req.add_header(Header::new("Supported", SUPPORTED_HEADER_VALUE)?)?;
+        req.add_header(Header::new("Session-Expires", &self.session_timer.request_header())?)?;
+        req.add_header(Header::new("Min-SE", &MIN_SE.to_string())?)?;
+        if let Some(replaces) = replaces {
+            req.add_header(Header::new("Replaces", &replaces)?)?;
+        }
+        add_body(&mut req, body)?;
+        self.remember_local_sdp(body);
 
-        let mut req = Request::new(Method::Invite, target)?;
-        // Call-ID and tags should be set by the application (using headers),
-        // but we keep cseq internally so we can build ACK/BYE later.
-        let cseq_header = self.cseq_header("INVITE")?;
-        req.add_header(cseq_header)?;
+        self.state = DialogState::Inviting;
+        self.pending_invite = Some(req.clone());
+        self.uac_invite = Some(req.clone());
+        Ok(req)
+    }
+
+    /// Withhold our identity on the call `start_outgoing` just set up
+    /// (RFC 3323, RFC 3325): an anonymous From for the whole dialog,
+    /// `Privacy: id`, and `from_uri` in P-Preferred-Identity for our proxy
+    /// to assert to trusted peers only. Returns the INVITE to send instead.
+    pub(crate) fn make_anonymous(&mut self, from_uri: &str) -> Result<Request> {
+        let mut req = self
+            .uac_invite
+            .take()
+            .ok_or(SipError::InvalidState("no outgoing INVITE"))?;
+        let tag = from_tag(&req.headers).ok_or(SipError::Invalid("missing From tag"))?;
+        self.local_party = ANONYMOUS_FROM.to_string();
+        let from = tagged(&self.local_party, tag)?;
+        for header in req.headers.iter_mut().filter(|h| h.name.eq_ignore_ascii_case("From")) {
+            header.value = from.clone();
+        }
+        req.add_header(Header::new("Privacy", "id")?)?;
+        req.add_header(Header::new("P-Preferred-Identity", &bracketed(from_uri)?)?)?;
+
+        self.pending_invite = Some(req.clone());
+        self.uac_invite = Some(req.clone());
+        Ok(req)
+    }
+
+    /// A sibling for a response from another fork of our initial INVITE
+    /// (RFC 3261 §12.1.2, §13.2.2.4): it starts out `Inviting` and takes
+    /// its own remote tag from that response.
+    pub(crate) fn fork(&self, handle: DialogHandle) -> Option<Dialog> {
+        let invite = self.uac_invite.clone()?;
+        let mut dialog = Dialog::with_handle(handle);
+        dialog.cseq = self.cseq;
+        dialog.invite_cseq = cseq_number(&invite.headers)?;
+        dialog.local_party = self.local_party.clone();
+        dialog.remote_party = self.remote_party.clone();
+        dialog.pending_invite = Some(invite.clone());
+        dialog.uac_invite = Some(invite);
+        dialog.cancelled = self.cancelled;
+        dialog.state = DialogState::Inviting;
+        Some(dialog)
+    }
+
+    /// The peer's URI, from its side of the dialog.
+    pub(crate) fn remote_uri(&self) -> Option<String> {
+        NameAddr::parse(&self.remote_party).ok().map(|addr| addr.uri)
+    }
+
+    /// The peer's tag, also after the dialog ended.
+    pub(crate) fn remote_tag(&self) -> Option<&str> {
+        match self.id_ref() {
+            Some(id) => Some(id.remote_tag.as_str()),
+            None => self
+                .last_ack
+                .as_ref()
+                .and_then(|ack| header_value(&ack.headers, "To"))
+                .and_then(parse_tag_param),
+        }
+    }
+
+    /// Whether this is a UAC dialog still waiting for a final response.
+    pub(crate) fn is_early_uac(&self) -> bool {
+        matches!(
+            self.state,
+            DialogState::Inviting | DialogState::Ringing { role: DialogRole::Uac, .. }
+        )
+    }
+
+    /// Whether `call_id`/`cseq` names our outstanding INVITE.
+    pub(crate) fn has_pending_invite(&self, call_id: &str, cseq: u32) -> bool {
+        self.pending_invite.as_ref().is_some_and(|invite| {
+            header_value(&invite.headers, "Call-ID") == Some(call_id)
+                && cseq_number(&invite.headers) == Some(cseq)
+        })
+    }
+
+    /// Call-ID and CSeq of our initial INVITE while it can still be
+    /// CANCELled, i.e. has no final response yet.
+    pub(crate) fn cancellable_invite(&self) -> Option<(String, u32)> {
+        if !self.is_early_uac() {
+            return None;
+        }
+        let invite = self.pending_invite.as_ref()?;
+        let call_id = header_value(&invite.headers, "Call-ID")?;
+        Some((call_id.to_string(), cseq_number(&invite.headers)?))
+    }
+
+    pub(crate) fn mark_cancelled(&mut self) {
+        self.cancelled = true;
+    }
+
+    /// Our INVITE got no final response in time. An initial INVITE ends
+    /// the dialog; a failed re-INVITE leaves it as it was. Returns true
+    /// if the state changed.
+    pub(crate) fn invite_timed_out(&mut self) -> bool {
+        self.pending_invite = None;
+        if matches!(self.state, DialogState::Established { .. }) {
+            return false;
+        }
+        self.state = DialogState::Terminated;
+        true
+    }
+
+    /// Build a request inside the current dialog (RFC 3261 §12.2.1.1).
+    ///
+    /// The Request-URI and Route headers come from the remote target and
+    /// route set, including the strict-routing case where the first route
+    /// has no `lr` parameter. ACK reuses the INVITE's CSeq number; every
+    /// other method takes the next local CSeq.
+    pub fn build_request(
+        &mut self,
+        method: Method,
+        local: &LocalEndpoint,
+        body: Option<(&str, &str)>,
+    ) -> Result<Request> {
+        let id = self
+            .id_ref()
+            .cloned()
+            .ok_or(SipError::InvalidState("no dialog"))?;
+
+        let cseq = match method {
+            Method::Ack | Method::Cancel => self.invite_cseq,
+            _ => {
+                self.cseq = self.cseq.wrapping_add(1);
+                self.cseq
+            }
+        };
+        if method == Method::Invite {
+            self.invite_cseq = cseq;
+        }
+
+        let (request_uri, routes) = self.request_uri_and_routes()?;
+
+        let mut method_name = String::new();
+        write!(method_name, "{}", method).map_err(|_| SipError::Capacity)?;
+
+        let mut req = Request::new(method, &request_uri)?;
+        req.add_header(build_via(local.transport, &local.host, local.port, &self.next_branch())?)?;
+        req.add_header(Header::new("Max-Forwards", "70")?)?;
+        for route in &routes {
+            req.add_header(Header::new("Route", route)?)?;
+        }
+        req.add_header(Header::new("From", &tagged(&self.local_party, &id.local_tag)?)?)?;
+        req.add_header(Header::new("To", &tagged(&self.remote_party, &id.remote_tag)?)?)?;
+        req.add_header(Header::new("Call-ID", &id.call_id)?)?;
+        req.add_header(Header::new("CSeq", &format_cseq(cseq, &method_name)?)?)?;
+        if matches!(method, Method::Invite | Method::Update | Method::Refer | Method::Notify) {
+            req.add_header(Header::new("Contact", &bracketed(&local.contact_uri)?)?)?;
+        }
+        if matches!(method, Method::Invite | Method::Update) {
+            req.add_header(Header::new("Supported", SUPPORTED_HEADER_VALUE)?)?;
+            req.add_header(Header::new("Session-Expires", &self.session_timer.request_header())?)?;
+            req.add_header(Header::new("Min-SE", &MIN_SE.to_string())?)?;
+            self.remember_local_sdp(body);
+        }
+        add_body(&mut req, body)?;
+
+        if method == Method::Invite {
+            self.pending_invite = Some(req.clone());
+        }
+        Ok(req)
+    }
+
+    /// Build an UPDATE (RFC 3311), in an early or confirmed dialog. `body`
+    /// is normally an SDP offer; without one the UPDATE just refreshes the
+    /// session. Only one of our offers can be outstanding at a time.
+    pub fn build_update(
+        &mut self,
+        local: &LocalEndpoint,
+        body: Option<(&str, &str)>,
+    ) -> Result<Request> {
+        if !matches!(self.state, DialogState::Ringing { .. } | DialogState::Established { .. }) {
+            return Err(SipError::InvalidState("UPDATE outside a dialog"));
+        }
+        if self.pending_update.is_some() || self.offer_pending() {
+            return Err(SipError::InvalidState("UPDATE already pending"));
+        }
+        let req = self.build_request(Method::Update, local, body)?;
+        self.pending_update = Some(req.clone());
+        self.update_retry = None;
         Ok(req)
     }
 
-    pub fn build_bye(&mut self, target: &str) -> Option<Request> {
+    /// Build a re-INVITE for the confirmed dialog, typically with a new SDP
+    /// offer (hold, resume). Only one INVITE of ours can be in progress.
+    pub fn build_reinvite(
+        &mut self,
+        local: &LocalEndpoint,
+        body: Option<(&str, &str)>,
+    ) -> Result<Request> {
+        if !matches!(self.state, DialogState::Established { .. }) {
+            return Err(SipError::InvalidState("re-INVITE outside established dialog"));
+        }
+        if self.pending_invite.is_some() || self.offer_pending() {
+            return Err(SipError::InvalidState("offer already pending"));
+        }
+        self.build_request(Method::Invite, local, body)
+    }
+
+    /// Build a REFER asking the peer to call `refer_to` (blind transfer).
+    /// Its NOTIFYs then report how that call goes.
+    pub fn build_refer(&mut self, local: &LocalEndpoint, refer_to: &str) -> Result<Request> {
+        if !matches!(self.state, DialogState::Established { .. }) {
+            return Err(SipError::InvalidState("REFER outside established dialog"));
+        }
+        if self.transfer.in_progress() {
+            return Err(SipError::InvalidState("transfer already in progress"));
+        }
+        let mut req = self.build_request(Method::Refer, local, None)?;
+        req.add_header(Header::new("Refer-To", &bracketed(refer_to)?)?)?;
+        self.transfer.refer_sent(&req);
+        Ok(req)
+    }
+
+    /// Refer-To for an attended transfer to our peer in this dialog: its
+    /// Contact, with Replaces naming this dialog so the transferee's call
+    /// takes its place (RFC 5589 §7).
+    pub(crate) fn replaces_target(&self) -> Result<String> {
         if !matches!(self.state, DialogState::Established { .. }) {
+            return Err(SipError::InvalidState("consultation call not answered"));
+        }
+        let id = self.id_ref().ok_or(SipError::InvalidState("no dialog"))?;
+        let uri = match &self.remote_target {
+            Some(target) => target.clone(),
+            None => NameAddr::parse(&self.remote_party)?.uri,
+        };
+        uri_with_replaces(&uri, &Replaces::for_peer_of(id))
+    }
+
+    /// Whether an INVITE with `replaces` names this dialog.
+    pub(crate) fn is_replaced_by(&self, replaces: &Replaces) -> bool {
+        self.id_ref().is_some_and(|id| replaces.matches(id))
+    }
+
+    /// Whether `resp` answers our pending REFER.
+    pub(crate) fn matches_refer_response(&self, resp: &Response) -> bool {
+        self.transfer
+            .pending_refer()
+            .is_some_and(|req| same_transaction(req, resp))
+    }
+
+    /// Final response to our REFER: the status if the peer refused the
+    /// transfer, `None` if NOTIFYs will tell how it goes.
+    pub(crate) fn handle_refer_response(&mut self, resp: &Response) -> Option<u16> {
+        if resp.status_code < 200 || !self.matches_refer_response(resp) {
             return None;
         }
-        self.cseq = self.cseq.wrapping_add(1);
-        let mut req = Request::new(Method::Bye, target).ok()?;
-        let cseq_header = self.cseq_header("BYE").ok()?;
-        req.add_header(cseq_header).ok()?;
+        self.transfer.on_refer_response(resp)
+    }
+
+    /// NOTIFY for our REFER: the status of the transferee's call, if its
+    /// sipfrag body has one. Fails when no REFER of ours is in progress.
+    pub(crate) fn handle_incoming_notify(&mut self, req: &Request) -> Result<Option<u16>> {
+        self.transfer
+            .on_notify(req)
+            .ok_or(SipError::InvalidState("no REFER subscription"))
+    }
+
+    /// We took on the peer's REFER and will report on the call it asked
+    /// for with `build_refer_notify`.
+    pub(crate) fn accept_refer(&mut self) {
+        self.transfer.accept();
+    }
+
+    /// NOTIFY reporting `status` of the call the peer's REFER asked for.
+    /// A final status ends the subscription.
+    pub fn build_refer_notify(
+        &mut self,
+        local: &LocalEndpoint,
+        status: u16,
+        reason: &str,
+    ) -> Result<Request> {
+        if !matches!(self.state, DialogState::Established { .. }) {
+            return Err(SipError::InvalidState("NOTIFY outside established dialog"));
+        }
+        let state = self
+            .transfer
+            .notify_state(status)
+            .ok_or(SipError::InvalidState("no REFER to report on"))?;
+        let body = format!("SIP/2.0 {} {}\r\n", status, reason);
+        let mut req = self.build_request(Method::Notify, local, Some((SIPFRAG, &body)))?;
+        req.add_header(Header::new("Event", "refer")?)?;
+        req.add_header(Header::new("Subscription-State", &state)?)?;
+        Ok(req)
+    }
+
+    /// Whether we made an SDP offer that is still unanswered, so an offer
+    /// from the peer now would collide (glare).
+    pub(crate) fn offer_pending(&self) -> bool {
+        let reinvite = matches!(self.state, DialogState::Established { .. })
+            .then_some(self.pending_invite.as_ref())
+            .flatten();
+        self.pending_update
+            .iter()
+            .chain(reinvite)
+            .any(|req| has_sdp(&req.headers, &req.body))
+    }
+
+    /// Whether we created the dialog, which makes us wait longer before
+    /// retrying after 491 (RFC 3261 §14.1).
+    pub(crate) fn owns_call_id(&self) -> bool {
+        matches!(
+            self.state,
+            DialogState::Ringing { role: DialogRole::Uac, .. }
+                | DialogState::Established { role: DialogRole::Uac, .. }
+        )
+    }
+
+    /// Whether `resp` answers our pending UPDATE.
+    pub(crate) fn matches_update_response(&self, resp: &Response) -> bool {
+        self.pending_update.as_ref().is_some_and(|req| same_transaction(req, resp))
+    }
+
+    /// Final response to our UPDATE. A 491 schedules a retry `retry_after`
+    /// from `now`; a 2xx refreshes the remote target.
+    pub(crate) fn handle_update_response(
+        &mut self,
+        resp: &Response,
+        retry_after: Duration,
+        now: Instant,
+    ) {
+        if resp.status_code < 200 || !self.matches_update_response(resp) {
+            return;
+        }
+        let Some(update) = self.pending_update.take() else {
+            return;
+        };
+
+        match resp.status_code {
+            200..=299 => {
+                if let Some(target) = contact_uri(&resp.headers) {
+                    self.remote_target = Some(target);
+                }
+                self.session_timer.on_response(resp);
+            }
+            491 => {
+                let body = header_value(&update.headers, "Content-Type")
+                    .filter(|_| !update.body.is_empty())
+                    .map(|content_type| (content_type.to_string(), update.body.clone()));
+                self.update_retry = Some((now + retry_after, body));
+            }
+            _ => {}
+        }
+    }
+
+    /// The UPDATE to send again once its 491 backoff is over.
+    pub(crate) fn poll_update_retry(&mut self, now: Instant, local: &LocalEndpoint) -> Option<Request> {
+        if !self.update_retry.as_ref().is_some_and(|(at, _)| now >= *at) {
+            return None;
+        }
+        let (_, body) = self.update_retry.take()?;
+        let body = body.as_ref().map(|(t, b)| (t.as_str(), b.as_str()));
+        match self.build_update(local, body) {
+            Ok(update) => Some(update),
+            Err(e) => {
+                log::warn!("poll_update_retry: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Drive the session timer of a confirmed dialog: a refresh (UPDATE if
+    /// the peer takes it, re-INVITE otherwise) when we are the refresher,
+    /// or a BYE once the session expired. Skips the refresh while another
+    /// offer of ours is out; its answer refreshes the session as well.
+    pub(crate) fn poll_session_timer(
+        &mut self,
+        now: Instant,
+        local: &LocalEndpoint,
+    ) -> Option<(SessionTimerDue, Request)> {
+        if !matches!(self.state, DialogState::Established { .. }) {
+            return None;
+        }
+
+        let due = self.session_timer.poll(now)?;
+        let req = match due {
+            SessionTimerDue::Refresh if self.pending_update.is_some() || self.offer_pending() => {
+                return None;
+            }
+            SessionTimerDue::Refresh if self.peer_allows_update => self.build_update(local, None),
+            SessionTimerDue::Refresh => {
+                let sdp = self.local_sdp.clone();
+                let body = sdp.as_deref().map(|sdp| ("application/sdp", sdp));
+                self.build_request(Method::Invite, local, body)
+            }
+            SessionTimerDue::Expired => {
+                log::info!("poll_session_timer: session expired, sending BYE");
+                self.build_bye(local)
+            }
+        };
+        match req {
+            Ok(req) => Some((due, req)),
+            Err(e) => {
+                log::warn!("poll_session_timer: {:?}", e);
+                None
+            }
+        }
+    }
+
+    fn remember_local_sdp(&mut self, body: Option<(&str, &str)>) {
+        if let Some((_, sdp)) = body.filter(|(content_type, _)| is_sdp(content_type)) {
+            self.local_sdp = Some(sdp.to_string());
+        }
+    }
+
+    /// Build a BYE for the established dialog and move to Terminated.
+    pub fn build_bye(&mut self, local: &LocalEndpoint) -> Result<Request> {
+        if !matches!(self.state, DialogState::Established { .. }) {
+            return Err(SipError::InvalidState("BYE outside established dialog"));
+        }
+        let req = self.build_request(Method::Bye, local, None)?;
         self.state = DialogState::Terminated;
-        Some(req)
+        Ok(req)
+    }
+
+    fn request_uri_and_routes(&self) -> Result<(String, Vec<String>)> {
+        let remote_target = match &self.remote_target {
+            Some(t) => t.clone(),
+            None => NameAddr::parse(&self.remote_party)?.uri,
+        };
+
+        let Some(first) = self.route_set.first() else {
+            return Ok((remote_target, Vec::new()));
+        };
+
+        let first_uri = NameAddr::parse(first)?.sip_uri()?;
+        if first_uri.has_param("lr") {
+            return Ok((remote_target, self.route_set.clone()));
+        }
+
+        // Strict router: it takes the Request-URI and the remote target is
+        // carried as the last Route entry instead.
+        let mut routes: Vec<String> = self.route_set[1..].to_vec();
+        routes.push(bracketed(&remote_target)?);
+        Ok((first_uri.without_headers().to_string(), routes))
     }
 
-    fn build_ack(&mut self) -> Result<Request> {
-        let mut req = Request::new(Method::Ack, "sip:remote")?;
-        let cseq_header = self.cseq_header("ACK")?;
-        req.add_header(cseq_header)?;
+    /// Handle a response to an INVITE we sent (UAC side).
+    ///
+    /// Provisional responses with a To tag create an early dialog, 2xx
+    /// confirms it and is ACKed through the route set, and any other final
+    /// response is ACKed hop-by-hop and ends the attempt.
+    pub fn handle_invite_response(
+        &mut self,
+        resp: &Response,
+        local: &LocalEndpoint,
+    ) -> Vec<CoreEvent> {
+        let mut events = Vec::new();
+
+        let Some(invite) = self.pending_invite.clone() else {
+            // A retransmitted 2xx after we already ACKed: ACK it again.
+            if (200..300).contains(&resp.status_code) {
+                if let Some(ack) = &self.last_ack {
+                    if same_transaction(ack, resp) {
+                        events.extend(send_request_event(ack.clone()));
+                    }
+                }
+            }
+            return events;
+        };
+
+        if !same_transaction(&invite, resp) {
+            log::debug!("handle_invite_response: response does not match pending INVITE");
+            return events;
+        }
+
+        let is_reinvite = matches!(self.state, DialogState::Established { .. });
+        let remote_tag = header_value(&resp.headers, "To")
+            .and_then(|to| NameAddr::parse(to).ok())
+            .and_then(|to| to.tag().map(str::to_string));
+
+        events.push(CoreEvent::Dialog(CoreDialogEvent::InviteResponse {
+            handle: self.handle,
+            response: resp.clone(),
+        }));
+
+        match resp.status_code {
+            100 => {}
+            101..=199 => {
+                let Some(remote_tag) = remote_tag else {
+                    return events;
+                };
+                if is_reinvite {
+                    return events;
+                }
+                // 180 then 183 with SDP is common, so look at every one.
+                if has_sdp(&resp.headers, &resp.body) {
+                    self.early_media = true;
+                }
+                if matches!(self.state, DialogState::Inviting) {
+                    let Some(id) = self.uac_dialog_id(&invite, &remote_tag) else {
+                        return events;
+                    };
+                    self.capture_uac_dialog(resp);
+                    self.state = DialogState::Ringing {
+                        role: DialogRole::Uac,
+                        id,
+                        original_invite: invite,
+                    };
+                    events.push(self.state_event());
+                }
+                if let Some(rseq) = reliable_rseq(resp) {
+                    events.extend(self.prack(rseq, local));
+                }
+            }
+            200..=299 => {
+                if is_reinvite {
+                    // Target refresh: only the remote target may change.
+                    if let Some(target) = contact_uri(&resp.headers) {
+                        self.remote_target = Some(target);
+                    }
+                } else {
+                    let Some(remote_tag) = remote_tag else {
+                        log::warn!("handle_invite_response: 2xx without To tag");
+                        return events;
+                    };
+                    let Some(id) = self.uac_dialog_id(&invite, &remote_tag) else {
+                        return events;
+                    };
+                    // The route set is recomputed from the 2xx even if an
+                    // early dialog already had one (RFC 3261 §13.2.2.4).
+                    self.capture_uac_dialog(resp);
+                    self.peer_allows_update = allows_update(&resp.headers);
+                    self.state = DialogState::Established {
+                        role: DialogRole::Uac,
+                        id,
+                    };
+                }
+                self.pending_invite = None;
+                self.session_timer.on_response(resp);
+
+                match self.build_request(Method::Ack, local, None) {
+                    Ok(ack) => {
+                        self.last_ack = Some(ack.clone());
+                        events.extend(send_request_event(ack));
+                    }
+                    Err(e) => log::warn!("handle_invite_response: failed to build ACK: {:?}", e),
+                }
+
+                if self.cancelled && !is_reinvite {
+                    // The 2xx crossed our CANCEL (RFC 3261 §9.1): the call
+                    // is confirmed only to be hung up.
+                    match self.build_bye(local) {
+                        Ok(bye) => events.extend(send_request_event(bye)),
+                        Err(e) => log::warn!("handle_invite_response: failed to build BYE: {:?}", e),
+                    }
+                }
+
+                if !is_reinvite {
+                    events.push(self.state_event());
+                }
+            }
+            _ => {
+                match ack_for_non_2xx(&invite, resp) {
+                    Ok(ack) => events.extend(send_request_event(ack)),
+                    Err(e) => log::warn!("handle_invite_response: failed to build ACK: {:?}", e),
+                }
+                self.pending_invite = None;
+
+                if !is_reinvite {
+                    if let Some(retry) = self.follow_redirect(resp, &invite, local) {
+                        // Not the end of the call: the application only
+                        // hears of the hop.
+                        events.retain(|ev| {
+                            !matches!(ev, CoreEvent::Dialog(CoreDialogEvent::InviteResponse { .. }))
+                        });
+                        events.push(CoreEvent::Dialog(CoreDialogEvent::Redirected {
+                            handle: self.handle,
+                            status_code: resp.status_code,
+                            target: retry.uri.clone(),
+                        }));
+                        events.extend(send_request_event(retry));
+                        events.push(self.state_event());
+                        return events;
+                    }
+                    self.state = DialogState::Terminated;
+                    events.push(self.state_event());
+                }
+            }
+        }
+
+        events
+    }
+
+    /// The INVITE to send next after `resp` ended our initial `invite`:
+    /// on to the best Contact of a 3xx, or to the next target left from an
+    /// earlier one. A CANCEL or a 6xx ends the call instead.
+    fn follow_redirect(
+        &mut self,
+        resp: &Response,
+        invite: &Request,
+        local: &LocalEndpoint,
+    ) -> Option<Request> {
+        if self.cancelled || resp.status_code >= 600 {
+            return None;
+        }
+        if matches!(resp.status_code, 300..=302) {
+            self.redirects.add_targets(resp, &invite.uri);
+        }
+        let target = self.redirects.next(&invite.uri)?;
+        match self.retarget(&target, local) {
+            Ok(retry) => Some(retry),
+            Err(e) => {
+                log::warn!("follow_redirect: cannot call {}: {:?}", target, e);
+                None
+            }
+        }
+    }
+
+    /// Send our initial INVITE again, to `target` (RFC 3261 §8.1.3.4):
+    /// same Call-ID, From and To, the next CSeq and a new branch. The
+    /// dialog starts over as `Inviting`.
+    fn retarget(&mut self, target: &str, local: &LocalEndpoint) -> Result<Request> {
+        let invite = self
+            .uac_invite
+            .clone()
+            .ok_or(SipError::InvalidState("no INVITE to redirect"))?;
+        self.cseq = self.cseq.wrapping_add(1);
+        self.invite_cseq = self.cseq;
+
+        let mut req = Request::new(Method::Invite, target)?;
+        req.add_header(build_via(local.transport, &local.host, local.port, &self.next_branch())?)?;
+        for header in invite.headers.iter().filter(|h| !h.name.eq_ignore_ascii_case("Via")) {
+            if header.name.eq_ignore_ascii_case("CSeq") {
+                req.add_header(Header::new("CSeq", &format_cseq(self.cseq, "INVITE")?)?)?;
+            } else {
+                req.add_header(header.clone())?;
+            }
+        }
+        req.set_body(&invite.body)?;
+
+        self.route_set.clear();
+        self.remote_target = None;
+        self.early_media = false;
+        self.remote_rseq = None;
+        self.last_prack = None;
+        self.state = DialogState::Inviting;
+        self.pending_invite = Some(req.clone());
+        self.uac_invite = Some(req.clone());
         Ok(req)
     }
 
-    fn cseq_header(&self, method: &str) -> Result<Header> {
-        let mut value = String::new();
-        write!(value, "{} {}", self.cseq, method).map_err(|_| SipError::Capacity)?;
-        Header::new("CSeq", &value)
+    /// PRACK a reliable provisional response (RFC 3262 §4). Responses must
+    /// be PRACKed in RSeq order; a retransmission gets the same PRACK again.
+    fn prack(&mut self, rseq: u32, local: &LocalEndpoint) -> Option<CoreEvent> {
+        match self.remote_rseq {
+            Some(last) if rseq == last => return self.last_prack.clone().and_then(send_request_event),
+            Some(last) if rseq != last.wrapping_add(1) => {
+                log::debug!("prack: RSeq {} out of order after {}", rseq, last);
+                return None;
+            }
+            _ => {}
+        }
+
+        let rack = format!("{} {} INVITE", rseq, self.invite_cseq);
+        let prack = self
+            .build_request(Method::Prack, local, None)
+            .and_then(|mut req| {
+                req.add_header(Header::new("RAck", &rack)?)?;
+                Ok(req)
+            });
+        match prack {
+            Ok(prack) => {
+                self.remote_rseq = Some(rseq);
+                self.last_prack = Some(prack.clone());
+                send_request_event(prack)
+            }
+            Err(e) => {
+                log::warn!("prack: failed to build PRACK: {:?}", e);
+                None
+            }
+        }
+    }
+
+    fn uac_dialog_id(&self, invite: &Request, remote_tag: &str) -> Option<SipDialogId> {
+        let call_id = header_value(&invite.headers, "Call-ID")?;
+        let local_tag = header_value(&invite.headers, "From")
+            .and_then(|from| NameAddr::parse(from).ok())
+            .and_then(|from| from.tag().map(str::to_string))?;
+        Some(SipDialogId {
+            call_id: call_id.to_string(),
+            local_tag,
+            remote_tag: remote_tag.to_string(),
+        })
+    }
+
+    fn capture_uac_dialog(&mut self, resp: &Response) {
+        // UAC: the route set is the Record-Route list in reverse order.
+        self.route_set = header_values(&resp.headers, "Record-Route")
+            .into_iter()
+            .rev()
+            .map(str::to_string)
+            .collect();
+        if let Some(target) = contact_uri(&resp.headers) {
+            self.remote_target = Some(target);
+        }
+        if let Some(to) = header_value(&resp.headers, "To").and_then(|v| NameAddr::parse(v).ok()) {
+            self.remote_party = to.without_tag().to_string();
+        }
     }
 
     /// Build a 180/100/486… response based on an incoming request.
@@ -155,10 +987,13 @@ impl Dialog {
 
         // SIP/2.0 fields already set in Response::new.
 
-        // Via: copy as-is
-        if let Some(via) = header_value(&req.headers, "Via") {
-            resp.add_header(Header::new("Via", via)?);
-        } else {
+        // Via: copy every value, in order (RFC 3261 §8.2.6.2)
+        let mut has_via = false;
+        for via in req.headers.iter().filter(|h| h.name.eq_ignore_ascii_case("Via")) {
+            resp.add_header(Header::new("Via", &via.value)?);
+            has_via = true;
+        }
+        if !has_via {
             return Err(SipError::Invalid("missing Via"));
         }
 
@@ -216,6 +1051,54 @@ impl Dialog {
 
         resp.add_header(Header::new("To", &to_value)?);
 
+        // Dialog-creating responses echo the Record-Route set (RFC 3261 §12.1.1)
+        if req.method == Method::Invite && (101..300).contains(&status) {
+            for rr in req.headers.iter().filter(|h| h.name.eq_ignore_ascii_case("Record-Route")) {
+                resp.add_header(rr.clone());
+            }
+        }
+
+        if req.method == Method::Invite
+            && (101..200).contains(&status)
+            && body.is_some_and(|(content_type, _)| is_sdp(content_type))
+        {
+            self.early_media = true;
+        }
+
+        // Provisional responses go reliably when the caller insists, or
+        // when it can and they carry SDP (RFC 3262 §3).
+        if req.method == Method::Invite
+            && (101..200).contains(&status)
+            && (has_option_tag(&req.headers, "Require", "100rel")
+                || (body.is_some() && has_option_tag(&req.headers, "Supported", "100rel")))
+        {
+            self.local_rseq = self.local_rseq.wrapping_add(1).max(1);
+            resp.add_header(Header::new("Require", "100rel")?);
+            resp.add_header(Header::new("RSeq", &self.local_rseq.to_string())?);
+        }
+        if req.method == Method::Invite
+            && (200..300).contains(&status)
+            && !has_sdp(&req.headers, &req.body)
+            && body.is_some_and(|(content_type, _)| is_sdp(content_type))
+        {
+            self.offer_in_2xx = true;
+        }
+
+        // Every 2xx to INVITE, or UPDATE once confirmed, settles the
+        // session interval anew (RFC 4028 §9).
+        let refresh = req.method == Method::Invite
+            || (req.method == Method::Update && matches!(self.state, DialogState::Established { .. }));
+        if refresh && (200..300).contains(&status) {
+            let (session_expires, require) = self.session_timer.answer(req);
+            resp.add_header(Header::new("Session-Expires", &session_expires)?);
+            if require {
+                resp.add_header(Header::new("Require", "timer")?);
+            }
+        }
+        if matches!(req.method, Method::Invite | Method::Update) && (101..300).contains(&status) {
+            self.remember_local_sdp(body);
+        }
+
         // Content-Length / body
         if let Some(b) = body {
             resp.add_header(Header::new("Content-Type", b.0)?);
@@ -229,171 +1112,72 @@ impl Dialog {
         Ok(resp)
     }
 
-    pub fn handle_incoming_invite(&mut self, req: Request) -> Vec<CoreEvent> {
-        let mut events = Vec::new();
-
-        let call_id = match header_value(&req.headers, "Call-ID") {
-            Some(v) => v,
-            None => {
-                log::debug!("handle_incoming_invite: missing Call-ID");
-                // Maybe return 400
-                return events;
-            }
+    /// Match a request the peer sent within this dialog: its From tag is
+    /// the remote tag and its To tag our local tag. A UAS dialog that has
+    /// not answered yet has no local tag, so only a tagless To matches it.
+    pub(crate) fn matches_request(&self, req: &Request) -> bool {
+        let Some(id) = self.id_ref() else {
+            return false;
         };
+        let call_id = header_value(&req.headers, "Call-ID");
+        let from_tag = header_value(&req.headers, "From").and_then(parse_tag_param);
+        let to_tag = header_value(&req.headers, "To").and_then(parse_tag_param);
 
-        let from = match header_value(&req.headers, "From") {
-            Some(v) => v,
-            None => {
-                log::debug!("handle_incoming_invite: missing From");
-                return events;
-            }
-        };
+        call_id == Some(id.call_id.as_str())
+            && from_tag.unwrap_or("") == id.remote_tag
+            && to_tag.unwrap_or("") == id.local_tag
+    }
 
-        let to = match header_value(&req.headers, "To") {
-            Some(v) => v,
-            None => {
-                log::debug!("handle_incoming_invite: missing To");
-                return events;
-            }
+    /// The INVITE of an early UAS dialog, while it has not been answered.
+    pub(crate) fn ringing_invite(&self, call_id: &str, cseq: u32) -> Option<&Request> {
+        let DialogState::Ringing { role: DialogRole::Uas, id, original_invite } = &self.state else {
+            return None;
         };
+        (id.call_id == call_id && cseq_number(&original_invite.headers) == Some(cseq))
+            .then_some(original_invite)
+    }
 
-        let from_tag = match parse_tag_param(from) {
-            Some(tag) => tag,
-            None => {
-                // No From tag: this is weird for an in-dialog INVITE, treat as new
-                log::debug!(
-                    "handle_incoming_invite: no From tag, treating as initial. call_id={}",
-                    call_id
-                );
-                // do NOT change state here; just tell the app it's an initial
-                // attempt, and let it decide what to do.
-                events.push(CoreEvent::Dialog(CoreDialogEvent::IncomingInvite {
-                    request: req,
-                    kind: InviteKind::Initial,
-                }));
-                return events;
-            }
+    /// Match a CANCEL against the INVITE that created this early UAS dialog.
+    pub(crate) fn matches_cancel(&self, req: &Request) -> bool {
+        let DialogState::Ringing { role: DialogRole::Uas, id, original_invite } = &self.state else {
+            return false;
         };
+        let from_tag = header_value(&req.headers, "From").and_then(parse_tag_param);
+        header_value(&req.headers, "Call-ID") == Some(id.call_id.as_str())
+            && from_tag.unwrap_or("") == id.remote_tag
+            && cseq_number(&req.headers) == cseq_number(&original_invite.headers)
+    }
 
-        let to_tag = parse_tag_param(to);
-
-        log::debug!(
-            "handle_incoming_invite: call_id={} from_tag={:?} to_tag={:?} state={:?}",
-            call_id,
-            from_tag,
-            to_tag,
-            self.state,
-        );
-
-        // Decide if this matches the existing dialog
-        let in_dialog = match &self.state {
-            DialogState::Ringing { id, role, .. }
-            | DialogState::Established { id, role , .. } if *role == DialogRole::Uas => {
-                log::debug!(
-                    "handle_incoming_invite: current dialog id: call_id={} local_tag={:?} remote_tag={:?}",
-                    id.call_id,
-                    id.local_tag,
-                    id.remote_tag,
-                );
-
-                if id.call_id != call_id {
-                    log::debug!("handle_incoming_invite: Call-ID mismatch");
-                    false
-                } else if id.remote_tag != from_tag {
-                    log::debug!(
-                        "handle_incoming_invite: remote_tag mismatch: expected={:?} got={:?}",
-                        id.remote_tag,
-                        from_tag
-                    );
-                    false
-                } else if id.local_tag.is_empty() {
-                    // Early state: we haven't committed a local tag yet,
-                    // so we can't rely on To-tag matching.
-                    // Treat as "same dialog" based on Call-ID + remote tag alone.
-                    log::debug!(
-                        "handle_incoming_invite: local_tag empty, accepting based on Call-ID+remote_tag"
-                    );
-                    true
-                } else {
-                    // Fully formed dialog: require To-tag match as well.
-                    match to_tag {
-                        Some(tag) if tag == id.local_tag => {
-                            log::debug!("handle_incoming_invite: To-tag matches local_tag");
-                            true
-                        }
-                        Some(tag) => {
-                            log::debug!(
-                                "handle_incoming_invite: To-tag mismatch: expected={:?} got={:?}",
-                                id.local_tag,
-                                tag
-                            );
-                            false
-                        }
-                        None => {
-                            log::debug!(
-                                "handle_incoming_invite: missing To-tag but dialog has local_tag={:?}",
-                                id.local_tag
-                            );
-                            false
-                        },
-                    }
-                }
+    /// Match a response to an INVITE we sent from this dialog: same
+    /// Call-ID and our From tag. Forks of one INVITE all match; the manager
+    /// picks among them by To tag.
+    pub(crate) fn matches_response(&self, resp: &Response) -> bool {
+        let (call_id, local_tag) = match self.id_ref() {
+            Some(id) => (Some(id.call_id.as_str()), Some(id.local_tag.as_str())),
+            None => {
+                let ours = self
+                    .pending_invite
+                    .as_ref()
+                    .or(self.uac_invite.as_ref())
+                    .or(self.last_ack.as_ref());
+                let Some(ours) = ours else {
+                    return false;
+                };
+                (header_value(&ours.headers, "Call-ID"), from_tag(&ours.headers))
             }
-            other_state => {
-                log::debug!(
-                    "handle_incoming_invite: state not considered in-dialog\r\n{:?}",
-                    other_state
-                );
-                false
-            },
         };
+        header_value(&resp.headers, "Call-ID") == call_id && from_tag(&resp.headers) == local_tag
+    }
 
-        if in_dialog {
-            // DO NOT reset state to Ringing here.
-            // Just emit "incoming INVITE, in-dialog"
-            log::debug!(
-                "handle_incoming_invite: classified as RE-INVITE (in-dialog) for Call-ID={}",
-                call_id
-            );
-            events.push(CoreEvent::Dialog(CoreDialogEvent::IncomingInvite {
-                request: req,
-                kind: InviteKind::Reinvite,
-            }));
-
-            return events;
-        }
-        
-        // Not in-dialog: This is some kind of initial INVITE.
-        // Decide whether we are free to accept a new dialog.
-        let can_start_new_dialog = matches!(self.state, DialogState::Idle | DialogState::Terminated);
-
-        if can_start_new_dialog {
-            log::debug!(
-                "handle_incoming_invite: classified INITIAL INVITE for Call-ID={}",
-                call_id
-            );
-
-            self.handle_initial_invite(&req);
-            events.push(CoreEvent::Dialog(CoreDialogEvent::IncomingInvite {
-                request: req,
-                kind: InviteKind::Initial,
-            }));
-        } else {
-            log::debug!(
-                "handle_incoming_invite: got NEW INVITE while busy in state {}, reporting InitialWhileBusy and not changing dialog state",
-                self.state
-            );
-
-            events.push(CoreEvent::Dialog(CoreDialogEvent::IncomingInvite {
-                request: req,
-                kind: InviteKind::InitialWhileBusy,
-            }));
+    /// Target refresh from a re-INVITE or UPDATE (RFC 3261 §12.2.2).
+    pub(crate) fn handle_target_refresh(&mut self, req: &Request) {
+        if let Some(target) = contact_uri(&req.headers) {
+            self.remote_target = Some(target);
         }
-        
-        events
+        self.remote_cseq = cseq_number(&req.headers);
     }
 
-    fn handle_initial_invite(&mut self, req: &Request) {
+    pub(crate) fn handle_initial_invite(&mut self, req: &Request) {
         let call_id = match header_value(&req.headers, "Call-ID") {
             Some(v) => v,
             None => return,
@@ -404,11 +1188,27 @@ impl Dialog {
             None => return,
         };
 
-        let from_tag = match parse_tag_param(from) {
-            Some(tag) => tag,
-            None => return,
-        };
+        // RFC 2543 peers may leave the From tag out.
+        let from_tag = parse_tag_param(from).unwrap_or("");
 
+        self.reset_dialog_data();
+
+        // UAS: the route set is the Record-Route list in request order,
+        // the remote target is the caller's Contact.
+        self.route_set = header_values(&req.headers, "Record-Route")
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        self.remote_target = contact_uri(&req.headers);
+        self.remote_party = NameAddr::parse(from)
+            .map(|na| na.without_tag().to_string())
+            .unwrap_or_default();
+        self.local_party = header_value(&req.headers, "To")
+            .and_then(|to| NameAddr::parse(to).ok())
+            .map(|na| na.without_tag().to_string())
+            .unwrap_or_default();
+        self.remote_cseq = cseq_number(&req.headers);
+        self.peer_allows_update = allows_update(&req.headers);
 
         self.state = DialogState::Ringing {
             role: DialogRole::Uas,
@@ -486,7 +1286,9 @@ impl Dialog {
         })
     }
 
-    pub fn handle_incoming_ack(&mut self, ack_req: &Request) -> Result<()> {
+    /// Confirm the dialog on the ACK for our 2xx. Returns the SDP answer it
+    /// carries when our 2xx made the offer.
+    pub fn handle_incoming_ack(&mut self, ack_req: &Request) -> Result<Option<String>> {
         // Only meaningful if we are UAS and currently ringing or already established
         let (role, id) = match &self.state {
             DialogState::Ringing { role, id, .. }
@@ -518,35 +1320,22 @@ impl Dialog {
             };
         }
 
-        Ok(())
+        if !mem::take(&mut self.offer_in_2xx) {
+            return Ok(None);
+        }
+        if !has_sdp(&ack_req.headers, &ack_req.body) {
+            log::warn!("handle_incoming_ack: no answer to the offer in our 2xx");
+            return Ok(None);
+        }
+        Ok(Some(ack_req.body.clone()))
     }
 
     pub fn handle_incoming_bye(&mut self, bye_req: &Request) -> Result<Response> {
-        let (role, id) = match &self.state {
-            DialogState::Established { role, id } => (role, id),
-            _ => return Err(SipError::InvalidState("BYE in wrong state")),
-        };
-
-        // For UAS: From is remote, To is local. For UAC it's reversed.
-        let call_id = header_value(&bye_req.headers, "Call-ID")
-            .ok_or(SipError::Invalid("missing Call-ID"))?;
-        let from = header_value(&bye_req.headers, "From")
-            .ok_or(SipError::Invalid("missing From"))?;
-        let to = header_value(&bye_req.headers, "To")
-            .ok_or(SipError::Invalid("missing To"))?;
-
-        let from_tag = parse_tag_param(from).unwrap_or("");
-        let to_tag = parse_tag_param(to).unwrap_or("");
-
-        let matches = if *role == DialogRole::Uas {
-            // remote = From, local = To
-            call_id == id.call_id && from_tag == id.remote_tag && to_tag == id.local_tag
-        } else {
-            // UAC: remote = To, local = From
-            call_id == id.call_id && to_tag == id.remote_tag && from_tag == id.local_tag
-        };
+        if !matches!(self.state, DialogState::Established { .. }) {
+            return Err(SipError::InvalidState("BYE in wrong state"));
+        }
 
-        if !matches {
+        if !self.matches_request(bye_req) {
             return Err(SipError::Invalid("BYE does not match current dialog"));
         }
 
@@ -562,7 +1351,83 @@ impl Dialog {
     }
 }
 
-fn parse_tag_param(input: &str) -> Option<&str> {
+fn tagged(party: &str, tag: &str) -> Result<String> {
+    let mut value = String::new();
+    write!(value, "{};tag={}", party, tag).map_err(|_| SipError::Capacity)?;
+    Ok(value)
+}
+
+fn add_body(req: &mut Request, body: Option<(&str, &str)>) -> Result<()> {
+    match body {
+        Some((content_type, data)) => {
+            req.add_header(Header::new("Content-Type", content_type)?)?;
+            req.add_header(Header::new("Content-Length", &data.len().to_string())?)?;
+            req.set_body(data)
+        }
+        None => req.add_header(Header::new("Content-Length", "0")?),
+    }
+}
+
+fn contact_uri(headers: &HeaderList) -> Option<String> {
+    header_values(headers, "Contact")
+        .first()
+        .and_then(|c| NameAddr::parse(c).ok())
+        .map(|na| na.uri)
+}
+
+fn from_tag(headers: &HeaderList) -> Option<&str> {
+    header_value(headers, "From").and_then(parse_tag_param)
+}
+
+fn cseq_number(headers: &HeaderList) -> Option<u32> {
+    header_value(headers, "CSeq")?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Same Call-ID and CSeq number as `req`.
+fn same_transaction(req: &Request, resp: &Response) -> bool {
+    header_value(&req.headers, "Call-ID") == header_value(&resp.headers, "Call-ID")
+        && cseq_number(&req.headers) == cseq_number(&resp.headers)
+}
+
+/// Whether one of the `name` headers lists option tag `tag`.
+pub(crate) fn has_option_tag(headers: &HeaderList, name: &str, tag: &str) -> bool {
+    header_values(headers, name)
+        .iter()
+        .flat_map(|value| value.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(tag))
+}
+
+fn allows_update(headers: &HeaderList) -> bool {
+    header_values(headers, "Allow")
+        .iter()
+        .flat_map(|value| value.split(','))
+        .any(|m| m.trim().eq_ignore_ascii_case("UPDATE"))
+}
+
+/// RSeq of a provisional response that must be PRACKed.
+fn reliable_rseq(resp: &Response) -> Option<u32> {
+    if !has_option_tag(&resp.headers, "Require", "100rel") {
+        return None;
+    }
+    header_value(&resp.headers, "RSeq")?.trim().parse().ok()
+}
+
+fn is_sdp(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|t| t.trim().eq_ignore_ascii_case("application/sdp"))
+}
+
+pub(crate) fn has_sdp(headers: &HeaderList, body: &str) -> bool {
+    !body.is_empty() && header_value(headers, "Content-Type").is_some_and(is_sdp)
+}
+
+pub(crate) fn parse_tag_param(input: &str) -> Option<&str> {
     // naive parse: search for "tag=" and take until next semicolon
     let lower = input.to_ascii_lowercase();
     let pos = lower.find("tag=")?;
@@ -570,3 +1435,430 @@ fn parse_tag_param(input: &str) -> Option<&str> {
     let end = rest.find(';').unwrap_or(rest.len());
     Some(&rest[..end])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local() -> LocalEndpoint {
+        LocalEndpoint {
+            host: "192.0.2.50".to_string(),
+            port: 5060,
+            contact_uri: "sip:me@192.0.2.50:5060".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn incoming_invite(record_route: &str) -> Request {
+        let mut req = Request::new(Method::Invite, "sip:me@192.0.2.50").unwrap();
+        let headers = [
+            ("Via", "SIP/2.0/UDP 192.0.2.1;branch=z9hG4bKp1"),
+            ("Record-Route", record_route),
+            ("From", "\"Alice\" <sip:alice@example.com>;tag=a1"),
+            ("To", "<sip:me@example.com>"),
+            ("Call-ID", "call-1"),
+            ("CSeq", "10 INVITE"),
+            ("Contact", "<sip:alice@192.0.2.99:5062>"),
+        ];
+        for (name, value) in headers {
+            req.add_header(Header::new(name, value).unwrap()).unwrap();
+        }
+        req
+    }
+
+    fn answer(dialog: &mut Dialog, invite: &Request) {
+        dialog.handle_initial_invite(invite);
+        let ok = dialog.build_response_for_request(invite, 200, "OK", None).unwrap();
+        assert!(header_value(&ok.headers, "Record-Route").is_some());
+
+        let mut ack = Request::new(Method::Ack, "sip:me@192.0.2.50").unwrap();
+        ack.add_header(Header::new("Call-ID", "call-1").unwrap()).unwrap();
+        ack.add_header(header_of(&ok, "To")).unwrap();
+        dialog.handle_incoming_ack(&ack).unwrap();
+        assert!(matches!(dialog.state, DialogState::Established { .. }));
+    }
+
+    fn header_of(resp: &Response, name: &str) -> Header {
+        Header::new(name, header_value(&resp.headers, name).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn uas_bye_uses_loose_route_set_and_remote_target() {
+        let mut dialog = Dialog::new();
+        answer(&mut dialog, &incoming_invite("<sip:p1.example.com;lr>, <sip:p2.example.com;lr>"));
+
+        let bye = dialog.build_bye(&local()).unwrap();
+        assert_eq!(bye.uri, "sip:alice@192.0.2.99:5062");
+        assert_eq!(
+            header_values(&bye.headers, "Route"),
+            vec!["<sip:p1.example.com;lr>", "<sip:p2.example.com;lr>"]
+        );
+        assert_eq!(bye.next_hop().unwrap().host, "p1.example.com");
+        assert_eq!(header_value(&bye.headers, "To"), Some("\"Alice\" <sip:alice@example.com>;tag=a1"));
+        assert_eq!(dialog.state, DialogState::Terminated);
+    }
+
+    #[test]
+    fn responses_copy_every_via_in_order() {
+        let mut invite = incoming_invite("<sip:p1.example.com;lr>");
+        invite
+            .add_header(Header::new("Via", "SIP/2.0/UDP 10.0.0.1;branch=z9hG4bKu1").unwrap())
+            .unwrap();
+
+        let mut dialog = Dialog::new();
+        let ringing = dialog.build_response_for_request(&invite, 180, "Ringing", None).unwrap();
+        let vias: Vec<_> = ringing
+            .headers
+            .iter()
+            .filter(|h| h.name == "Via")
+            .map(|h| h.value.as_str())
+            .collect();
+        assert_eq!(
+            vias,
+            vec!["SIP/2.0/UDP 192.0.2.1;branch=z9hG4bKp1", "SIP/2.0/UDP 10.0.0.1;branch=z9hG4bKu1"]
+        );
+    }
+
+    #[test]
+    fn strict_router_takes_request_uri() {
+        let mut dialog = Dialog::new();
+        answer(&mut dialog, &incoming_invite("<sip:strict.example.com>, <sip:p2.example.com;lr>"));
+
+        let bye = dialog.build_bye(&local()).unwrap();
+        assert_eq!(bye.uri, "sip:strict.example.com");
+        assert_eq!(
+            header_values(&bye.headers, "Route"),
+            vec!["<sip:p2.example.com;lr>", "<sip:alice@192.0.2.99:5062>"]
+        );
+    }
+
+    #[test]
+    fn anonymous_call_hides_our_identity_for_the_whole_dialog() {
+        let mut dialog = Dialog::new();
+        dialog
+            .start_outgoing("sip:bob@example.com", "sip:me@example.com", "call-2", &local(), None)
+            .unwrap();
+        let invite = dialog.make_anonymous("sip:me@example.com").unwrap();
+
+        let from = NameAddr::parse(header_value(&invite.headers, "From").unwrap()).unwrap();
+        assert_eq!(from.display_name.as_deref(), Some("Anonymous"));
+        assert_eq!(from.uri, "sip:anonymous@anonymous.invalid");
+        assert!(from.tag().is_some());
+        assert_eq!(header_value(&invite.headers, "Privacy"), Some("id"));
+        assert_eq!(header_value(&invite.headers, "P-Preferred-Identity"), Some("<sip:me@example.com>"));
+
+        let mut ok = response_to(&invite, 200, "<sip:bob@example.com>;tag=b1");
+        ok.add_header(Header::new("Contact", "<sip:bob@192.0.2.77>").unwrap());
+        dialog.handle_invite_response(&ok, &local());
+
+        let bye = dialog.build_bye(&local()).unwrap();
+        assert_eq!(header_value(&bye.headers, "From"), header_value(&invite.headers, "From"));
+    }
+
+    #[test]
+    fn uac_2xx_sets_reversed_route_set_and_acks_through_it() {
+        let mut dialog = Dialog::new();
+        let invite = dialog
+            .start_outgoing("sip:bob@example.com", "sip:me@example.com", "call-2", &local(), None)
+            .unwrap();
+
+        let mut ok = response_to(&invite, 200, "<sip:bob@example.com>;tag=b1");
+        ok.add_header(Header::new("Record-Route", "<sip:p2.example.com;lr>").unwrap());
+        ok.add_header(Header::new("Record-Route", "<sip:p1.example.com;lr>").unwrap());
+        ok.add_header(Header::new("Contact", "<sip:bob@192.0.2.77>").unwrap());
+
+        let events = dialog.handle_invite_response(&ok, &local());
+        let ack = events
+            .iter()
+            .find_map(|ev| match ev {
+                CoreEvent::SendRequest { request, .. } => Some(request.clone()),
+                _ => None,
+            })
+            .expect("ACK for 2xx");
+
+        assert_eq!(ack.method, Method::Ack);
+        assert_eq!(ack.uri, "sip:bob@192.0.2.77");
+        assert_eq!(header_value(&ack.headers, "CSeq"), Some("1 ACK"));
+        assert_eq!(
+            header_values(&ack.headers, "Route"),
+            vec!["<sip:p1.example.com;lr>", "<sip:p2.example.com;lr>"]
+        );
+        assert!(matches!(dialog.state, DialogState::Established { role: DialogRole::Uac, .. }));
+    }
+
+    #[test]
+    fn a_2xx_crossing_our_cancel_is_acked_and_hung_up() {
+        let mut dialog = Dialog::new();
+        let invite = dialog
+            .start_outgoing("sip:bob@example.com", "sip:me@example.com", "call-2", &local(), None)
+            .unwrap();
+        assert_eq!(dialog.cancellable_invite(), Some(("call-2".to_string(), 1)));
+        dialog.mark_cancelled();
+
+        let mut ok = response_to(&invite, 200, "<sip:bob@example.com>;tag=b1");
+        ok.add_header(Header::new("Contact", "<sip:bob@192.0.2.77>").unwrap());
+
+        let events = dialog.handle_invite_response(&ok, &local());
+        let sent: Vec<Method> = events
+            .iter()
+            .filter_map(|ev| match ev {
+                CoreEvent::SendRequest { request, .. } => Some(request.method),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sent, vec![Method::Ack, Method::Bye]);
+        assert_eq!(dialog.state, DialogState::Terminated);
+        assert_eq!(dialog.cancellable_invite(), None);
+    }
+
+    #[test]
+    fn a_302_sends_the_invite_on_to_the_best_contact() {
+        let mut dialog = Dialog::new();
+        let sdp = Some(("application/sdp", "v=0\r\n"));
+        let invite = dialog
+            .start_outgoing("sip:bob@example.com", "sip:me@example.com", "call-2", &local(), sdp)
+            .unwrap();
+
+        let mut moved = response_to(&invite, 302, "<sip:bob@example.com>;tag=r1");
+        moved.add_header(Header::new("Contact", "<sip:bob@192.0.2.20>;q=0.5, <sip:bob@192.0.2.30>;q=0.8").unwrap());
+
+        let events = dialog.handle_invite_response(&moved, &local());
+        assert!(events.iter().any(|ev| matches!(
+            ev,
+            CoreEvent::Dialog(CoreDialogEvent::Redirected { status_code: 302, target, .. })
+                if target == "sip:bob@192.0.2.30"
+        )));
+        assert!(!events.iter().any(|ev| matches!(ev, CoreEvent::Dialog(CoreDialogEvent::InviteResponse { .. }))));
+        let sent: Vec<&Request> = events
+            .iter()
+            .filter_map(|ev| match ev {
+                CoreEvent::SendRequest { request, .. } => Some(request),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].method, Method::Ack);
+
+        let retry = sent[1];
+        assert_eq!(retry.uri, "sip:bob@192.0.2.30");
+        assert_eq!(header_value(&retry.headers, "CSeq"), Some("2 INVITE"));
+        assert_eq!(header_value(&retry.headers, "To"), Some("<sip:bob@example.com>"));
+        for name in ["From", "Call-ID"] {
+            assert_eq!(header_value(&retry.headers, name), header_value(&invite.headers, name));
+        }
+        assert_ne!(header_value(&retry.headers, "Via"), header_value(&invite.headers, "Via"));
+        assert_eq!(retry.body, "v=0\r\n");
+        assert_eq!(dialog.state, DialogState::Inviting);
+        assert_eq!(dialog.cancellable_invite(), Some(("call-2".to_string(), 2)));
+    }
+
+    #[test]
+    fn provisional_sdp_marks_early_media_both_ways() {
+        let mut dialog = Dialog::new();
+        let invite = dialog
+            .start_outgoing("sip:bob@example.com", "sip:me@example.com", "call-3", &local(), None)
+            .unwrap();
+        let provisional = |status: u16, body: Option<&str>| {
+            let mut resp = response_to(&invite, status, "<sip:bob@example.com>;tag=b1");
+            if let Some(body) = body {
+                resp.add_header(Header::new("Content-Type", "application/sdp").unwrap());
+                resp.set_body(body);
+            }
+            resp
+        };
+
+        dialog.handle_invite_response(&provisional(180, None), &local());
+        assert!(matches!(dialog.state, DialogState::Ringing { .. }));
+        assert!(!dialog.early_media());
+        dialog.handle_invite_response(&provisional(183, Some("v=0\r\n")), &local());
+        assert!(dialog.early_media());
+
+        let mut uas = Dialog::new();
+        let invite = incoming_invite("<sip:p1.example.com;lr>");
+        uas.handle_initial_invite(&invite);
+        uas.build_response_for_request(&invite, 180, "Ringing", None).unwrap();
+        assert!(!uas.early_media());
+        let body = Some(("application/sdp", "v=0\r\n"));
+        uas.build_response_for_request(&invite, 183, "Session Progress", body).unwrap();
+        assert!(uas.early_media());
+    }
+
+    #[test]
+    fn offer_in_2xx_takes_the_answer_from_the_ack() {
+        let mut dialog = Dialog::new();
+        let invite = incoming_invite("<sip:p1.example.com;lr>");
+        dialog.handle_initial_invite(&invite);
+        let offer = Some(("application/sdp", "v=0\r\no=- 1 1 IN IP4 192.0.2.50\r\n"));
+        let ok = dialog.build_response_for_request(&invite, 200, "OK", offer).unwrap();
+
+        let mut ack = Request::new(Method::Ack, "sip:me@192.0.2.50").unwrap();
+        ack.add_header(Header::new("Call-ID", "call-1").unwrap()).unwrap();
+        ack.add_header(header_of(&ok, "To")).unwrap();
+        ack.add_header(Header::new("Content-Type", "application/sdp").unwrap()).unwrap();
+        ack.set_body("v=0\r\no=- 7 7 IN IP4 192.0.2.1\r\n").unwrap();
+
+        let answer = dialog.handle_incoming_ack(&ack).unwrap();
+        assert_eq!(answer.as_deref(), Some(ack.body.as_str()));
+        assert!(matches!(dialog.state, DialogState::Established { .. }));
+
+        // A retransmitted ACK carries no new answer.
+        assert_eq!(dialog.handle_incoming_ack(&ack).unwrap(), None);
+    }
+
+    #[test]
+    fn reliable_provisionals_are_numbered_and_pracked() {
+        let mut uas = Dialog::new();
+        let mut invite = incoming_invite("<sip:p1.example.com;lr>");
+        invite.add_header(Header::new("Require", "100rel").unwrap()).unwrap();
+        uas.handle_initial_invite(&invite);
+        let ringing = uas.build_response_for_request(&invite, 180, "Ringing", None).unwrap();
+        assert_eq!(header_value(&ringing.headers, "RSeq"), Some("1"));
+        assert_eq!(reliable_rseq(&ringing), Some(1));
+
+        let mut uac = Dialog::new();
+        let invite = uac
+            .start_outgoing("sip:bob@example.com", "sip:me@example.com", "call-4", &local(), None)
+            .unwrap();
+        assert!(has_option_tag(&invite.headers, "Supported", "100rel"));
+
+        let mut progress = response_to(&invite, 183, "<sip:bob@example.com>;tag=b1");
+        progress.add_header(Header::new("Contact", "<sip:bob@192.0.2.77>").unwrap());
+        progress.add_header(Header::new("Require", "100rel").unwrap());
+        progress.add_header(Header::new("RSeq", "5").unwrap());
+
+        let pracks = |events: Vec<CoreEvent>| -> Vec<Request> {
+            events
+                .into_iter()
+                .filter_map(|ev| match ev {
+                    CoreEvent::SendRequest { request, .. } => Some(request),
+                    _ => None,
+                })
+                .collect()
+        };
+        let first = pracks(uac.handle_invite_response(&progress, &local()));
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].method, Method::Prack);
+        assert_eq!(first[0].uri, "sip:bob@192.0.2.77");
+        assert_eq!(header_value(&first[0].headers, "RAck"), Some("5 1 INVITE"));
+        assert_eq!(header_value(&first[0].headers, "CSeq"), Some("2 PRACK"));
+
+        // A retransmission gets the same PRACK.
+        assert_eq!(pracks(uac.handle_invite_response(&progress, &local())), first);
+    }
+
+    #[test]
+    fn update_glare_is_retried_after_the_backoff() {
+        let mut dialog = Dialog::new();
+        answer(&mut dialog, &incoming_invite("<sip:p1.example.com;lr>"));
+
+        let offer = Some(("application/sdp", "v=0\r\n"));
+        let update = dialog.build_update(&local(), offer).unwrap();
+        assert_eq!(update.method, Method::Update);
+        assert!(header_value(&update.headers, "Contact").is_some());
+        assert!(dialog.offer_pending());
+        assert!(dialog.build_update(&local(), offer).is_err(), "one offer at a time");
+
+        let pending = response_to(&update, 491, header_value(&update.headers, "To").unwrap());
+        let now = Instant::now();
+        dialog.handle_update_response(&pending, Duration::from_secs(3), now);
+        assert!(!dialog.offer_pending());
+        assert!(dialog.poll_update_retry(now + Duration::from_secs(2), &local()).is_none());
+
+        let retry = dialog.poll_update_retry(now + Duration::from_secs(3), &local()).unwrap();
+        assert_eq!(retry.body, update.body);
+        assert_ne!(header_value(&retry.headers, "CSeq"), header_value(&update.headers, "CSeq"));
+        assert!(dialog.offer_pending());
+    }
+
+    #[test]
+    fn hold_reinvite_waits_for_the_previous_one() {
+        let mut dialog = Dialog::new();
+        answer(&mut dialog, &incoming_invite("<sip:p1.example.com;lr>"));
+
+        let hold = Some(("application/sdp", "v=0\r\na=sendonly\r\n"));
+        let reinvite = dialog.build_reinvite(&local(), hold).unwrap();
+        assert_eq!(reinvite.method, Method::Invite);
+        assert_eq!(reinvite.uri, "sip:alice@192.0.2.99:5062");
+        assert!(dialog.build_reinvite(&local(), hold).is_err(), "one INVITE at a time");
+        assert!(dialog.build_update(&local(), hold).is_err(), "one offer at a time");
+
+        let ok = response_to(&reinvite, 200, header_value(&reinvite.headers, "To").unwrap());
+        let events = dialog.handle_invite_response(&ok, &local());
+        assert!(events.iter().any(|ev| matches!(
+            ev,
+            CoreEvent::SendRequest { request, .. } if request.method == Method::Ack
+        )));
+        assert!(matches!(dialog.state, DialogState::Established { .. }));
+
+        let resume = Some(("application/sdp", "v=0\r\na=sendrecv\r\n"));
+        assert!(dialog.build_reinvite(&local(), resume).is_ok());
+    }
+
+    #[test]
+    fn refer_and_its_notifies_stay_in_the_dialog() {
+        let mut dialog = Dialog::new();
+        answer(&mut dialog, &incoming_invite("<sip:p1.example.com;lr>"));
+
+        let refer = dialog.build_refer(&local(), "sip:carol@example.com").unwrap();
+        assert_eq!(refer.method, Method::Refer);
+        assert_eq!(header_value(&refer.headers, "Refer-To"), Some("<sip:carol@example.com>"));
+        assert_eq!(header_value(&refer.headers, "Route"), Some("<sip:p1.example.com;lr>"));
+        assert!(header_value(&refer.headers, "Contact").is_some());
+        assert!(dialog.build_refer(&local(), "sip:dave@example.com").is_err());
+
+        let declined = response_to(&refer, 603, header_value(&refer.headers, "To").unwrap());
+        assert_eq!(dialog.handle_refer_response(&declined), Some(603));
+        assert!(dialog.build_refer(&local(), "sip:dave@example.com").is_ok());
+
+        assert!(dialog.build_refer_notify(&local(), 100, "Trying").is_err(), "no REFER accepted");
+        dialog.accept_refer();
+        let notify = dialog.build_refer_notify(&local(), 200, "OK").unwrap();
+        assert_eq!(header_value(&notify.headers, "Event"), Some("refer"));
+        assert_eq!(header_value(&notify.headers, "Content-Type"), Some("message/sipfrag"));
+        assert_eq!(
+            header_value(&notify.headers, "Subscription-State"),
+            Some("terminated;reason=noresource")
+        );
+        assert_eq!(notify.body, "SIP/2.0 200 OK\r\n");
+    }
+
+    #[test]
+    fn we_refresh_with_update_for_a_peer_without_timers_then_bye() {
+        let mut dialog = Dialog::new();
+        let mut invite = incoming_invite("<sip:p1.example.com;lr>");
+        invite.add_header(Header::new("Allow", "INVITE, ACK, BYE, UPDATE").unwrap()).unwrap();
+        invite.add_header(Header::new("Session-Expires", "120").unwrap()).unwrap();
+        dialog.handle_initial_invite(&invite);
+        let ok = dialog.build_response_for_request(&invite, 200, "OK", None).unwrap();
+        assert_eq!(header_value(&ok.headers, "Session-Expires"), Some("120;refresher=uas"));
+        assert_eq!(header_value(&ok.headers, "Require"), None);
+
+        let mut ack = Request::new(Method::Ack, "sip:me@192.0.2.50").unwrap();
+        ack.add_header(Header::new("Call-ID", "call-1").unwrap()).unwrap();
+        ack.add_header(header_of(&ok, "To")).unwrap();
+        dialog.handle_incoming_ack(&ack).unwrap();
+
+        let start = Instant::now();
+        assert!(dialog.poll_session_timer(start, &local()).is_none());
+        let (due, refresh) = dialog.poll_session_timer(start + Duration::from_secs(60), &local()).unwrap();
+        assert_eq!(due, SessionTimerDue::Refresh);
+        assert_eq!(refresh.method, Method::Update);
+        assert_eq!(header_value(&refresh.headers, "Session-Expires"), Some("120;refresher=uac"));
+
+        // The refresh went unanswered.
+        let (due, bye) = dialog.poll_session_timer(start + Duration::from_secs(88), &local()).unwrap();
+        assert_eq!(due, SessionTimerDue::Expired);
+        assert_eq!(bye.method, Method::Bye);
+        assert_eq!(dialog.state, DialogState::Terminated);
+    }
+
+    /// The peer's response to `req`, with `to` as its To.
+    fn response_to(req: &Request, status: u16, to: &str) -> Response {
+        let mut resp = Response::new(status, "Reason").unwrap();
+        for name in ["Via", "From", "Call-ID", "CSeq"] {
+            resp.add_header(Header::new(name, header_value(&req.headers, name).unwrap()).unwrap());
+        }
+        resp.add_header(Header::new("To", to).unwrap());
+        resp
+    }
+}
@@ -0,0 +1,282 @@
+//! Several dialogs at once: incoming requests and responses are routed to
+//! the dialog they belong to (RFC 3261 §12.2), and the application refers
+//! to each dialog by a small `DialogHandle`.
+
+use core::fmt;
+
+use crate::{
+    Result, SipError,
+    dialog::{parse_tag_param, Dialog, DialogState},
+    message::{header_value, Request, Response},
+};
+
+/// How many dialogs (early or confirmed) we track at once. Further
+/// INVITEs are turned away with 486.
+pub const MAX_DIALOGS: usize = 4;
+
+/// Names one dialog in `DialogManager` and in the events it reports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DialogHandle(pub(crate) u32);
+
+impl fmt::Display for DialogHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct DialogManager {
+    dialogs: Vec<Dialog>,
+    next_handle: u32,
+    /// Builds responses to requests outside any dialog (OPTIONS, 481, 486…).
+    standalone: Dialog,
+}
+
+impl Default for DialogManager {
+    fn default() -> Self {
+        Self {
+            dialogs: Vec::new(),
+            next_handle: 1,
+            standalone: Dialog::new(),
+        }
+    }
+}
+
+impl DialogManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an idle dialog. Terminated dialogs are dropped first; a dialog
+    /// stays around after it ends so retransmitted 2xx can still be ACKed.
+    pub fn create(&mut self) -> Result<DialogHandle> {
+        let handle = DialogHandle(self.next_handle);
+        self.insert(Dialog::with_handle(handle))?;
+        Ok(handle)
+    }
+
+    fn insert(&mut self, dialog: Dialog) -> Result<()> {
+        self.dialogs.retain(|d| d.state != DialogState::Terminated);
+        if self.dialogs.len() >= MAX_DIALOGS {
+            return Err(SipError::Capacity);
+        }
+
+        // Zero is the standalone dialog's handle.
+        self.next_handle = self.next_handle.wrapping_add(1).max(1);
+        self.dialogs.push(dialog);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, handle: DialogHandle) {
+        self.dialogs.retain(|d| d.handle() != handle);
+    }
+
+    pub fn get(&self, handle: DialogHandle) -> Option<&Dialog> {
+        self.dialogs.iter().find(|d| d.handle() == handle)
+    }
+
+    pub fn get_mut(&mut self, handle: DialogHandle) -> Option<&mut Dialog> {
+        self.dialogs.iter_mut().find(|d| d.handle() == handle)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Dialog> {
+        self.dialogs.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Dialog> {
+        self.dialogs.iter_mut()
+    }
+
+    /// The dialog an in-dialog request from the peer belongs to.
+    pub fn find_for_request(&self, req: &Request) -> Option<DialogHandle> {
+        self.find(|d| d.matches_request(req))
+    }
+
+    /// The early UAS dialog a CANCEL is meant for.
+    pub fn find_for_cancel(&self, req: &Request) -> Option<DialogHandle> {
+        self.find(|d| d.matches_cancel(req))
+    }
+
+    /// The dialog whose INVITE a response answers. Forks of one INVITE
+    /// all match; the one with the response's To tag wins, then the one
+    /// still waiting for any response.
+    pub fn find_for_response(&self, resp: &Response) -> Option<DialogHandle> {
+        let to_tag = header_value(&resp.headers, "To").and_then(parse_tag_param);
+        let candidates = || self.dialogs.iter().filter(|d| d.matches_response(resp));
+
+        candidates()
+            .find(|d| to_tag.is_some() && d.remote_tag() == to_tag)
+            .or_else(|| candidates().find(|d| d.state == DialogState::Inviting))
+            .or_else(|| candidates().next())
+            .map(Dialog::handle)
+    }
+
+    /// Start a sibling of `from` for a response from another fork of its
+    /// INVITE.
+    pub fn fork(&mut self, from: DialogHandle) -> Result<DialogHandle> {
+        let handle = DialogHandle(self.next_handle);
+        let dialog = self
+            .get(from)
+            .and_then(|d| d.fork(handle))
+            .ok_or(SipError::InvalidState("dialog cannot fork"))?;
+        self.insert(dialog)?;
+        Ok(handle)
+    }
+
+    /// Build a response to `req` from the dialog `handle`, or outside any
+    /// dialog when `handle` is `None` or no longer exists.
+    pub fn build_response(
+        &mut self,
+        handle: Option<DialogHandle>,
+        req: &Request,
+        status: u16,
+        reason: &str,
+        body: Option<(&str, &str)>,
+    ) -> Result<Response> {
+        let dialog = match handle {
+            Some(handle) => self
+                .dialogs
+                .iter_mut()
+                .find(|d| d.handle() == handle)
+                .unwrap_or(&mut self.standalone),
+            None => &mut self.standalone,
+        };
+        dialog.build_response_for_request(req, status, reason, body)
+    }
+
+    fn find(&self, pred: impl Fn(&Dialog) -> bool) -> Option<DialogHandle> {
+        self.dialogs.iter().find(|d| pred(d)).map(Dialog::handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{header_value, Header, Method};
+
+    fn invite(call_id: &str, from_tag: &str) -> Request {
+        let mut req = Request::new(Method::Invite, "sip:me@192.0.2.50").unwrap();
+        let from = format!("<sip:{}@example.com>;tag={}", call_id, from_tag);
+        let headers = [
+            ("Via", "SIP/2.0/UDP 192.0.2.1;branch=z9hG4bKp1"),
+            ("From", from.as_str()),
+            ("To", "<sip:me@example.com>"),
+            ("Call-ID", call_id),
+            ("CSeq", "1 INVITE"),
+            ("Contact", "<sip:peer@192.0.2.1>"),
+        ];
+        for (name, value) in headers {
+            req.add_header(Header::new(name, value).unwrap()).unwrap();
+        }
+        req
+    }
+
+    fn in_dialog(method: Method, invite: &Request, to: &str) -> Request {
+        let mut req = Request::new(method, "sip:me@192.0.2.50").unwrap();
+        for name in ["Via", "From", "Call-ID"] {
+            let value = header_value(&invite.headers, name).unwrap();
+            req.add_header(Header::new(name, value).unwrap()).unwrap();
+        }
+        req.add_header(Header::new("To", to).unwrap()).unwrap();
+        req.add_header(Header::new("CSeq", "2 BYE").unwrap()).unwrap();
+        req
+    }
+
+    /// Take an incoming INVITE through 200 OK and ACK; returns the To
+    /// header of the 200.
+    fn establish(dialogs: &mut DialogManager, invite: &Request) -> (DialogHandle, String) {
+        let handle = dialogs.create().unwrap();
+        dialogs.get_mut(handle).unwrap().handle_initial_invite(invite);
+        let ok = dialogs.build_response(Some(handle), invite, 200, "OK", None).unwrap();
+        let to = header_value(&ok.headers, "To").unwrap().to_string();
+
+        let ack = in_dialog(Method::Ack, invite, &to);
+        assert_eq!(dialogs.find_for_request(&ack), Some(handle));
+        dialogs.get_mut(handle).unwrap().handle_incoming_ack(&ack).unwrap();
+        (handle, to)
+    }
+
+    #[test]
+    fn requests_are_routed_to_their_own_dialog() {
+        let mut dialogs = DialogManager::new();
+        let first = invite("call-1", "a1");
+        let second = invite("call-2", "b1");
+        let (h1, to1) = establish(&mut dialogs, &first);
+        let (h2, to2) = establish(&mut dialogs, &second);
+        assert_ne!(h1, h2);
+        assert_ne!(to1, to2, "each dialog picks its own local tag");
+
+        let bye = in_dialog(Method::Bye, &second, &to2);
+        assert_eq!(dialogs.find_for_request(&bye), Some(h2));
+        dialogs.get_mut(h2).unwrap().handle_incoming_bye(&bye).unwrap();
+
+        assert_eq!(dialogs.get(h2).unwrap().state, DialogState::Terminated);
+        assert!(matches!(dialogs.get(h1).unwrap().state, DialogState::Established { .. }));
+
+        // A BYE with a tag we never handed out matches nothing.
+        let stray = in_dialog(Method::Bye, &first, "<sip:me@example.com>;tag=nope");
+        assert_eq!(dialogs.find_for_request(&stray), None);
+    }
+
+    #[test]
+    fn cancel_finds_the_ringing_dialog_and_full_manager_refuses() {
+        let mut dialogs = DialogManager::new();
+        establish(&mut dialogs, &invite("call-1", "a1"));
+
+        let ringing = invite("call-2", "b1");
+        let handle = dialogs.create().unwrap();
+        dialogs.get_mut(handle).unwrap().handle_initial_invite(&ringing);
+
+        let mut cancel = in_dialog(Method::Cancel, &ringing, "<sip:me@example.com>");
+        cancel.headers.retain(|h| h.name != "CSeq");
+        cancel.add_header(Header::new("CSeq", "1 CANCEL").unwrap()).unwrap();
+        assert_eq!(dialogs.find_for_cancel(&cancel), Some(handle));
+
+        while dialogs.iter().count() < MAX_DIALOGS {
+            dialogs.create().unwrap();
+        }
+        assert!(matches!(dialogs.create(), Err(SipError::Capacity)));
+    }
+
+    #[test]
+    fn forked_responses_get_a_dialog_per_to_tag() {
+        let mut dialogs = DialogManager::new();
+        let local = crate::stack::LocalEndpoint {
+            host: "192.0.2.50".to_string(),
+            port: 5060,
+            ..Default::default()
+        };
+        let handle = dialogs.create().unwrap();
+        let invite = dialogs
+            .get_mut(handle)
+            .unwrap()
+            .start_outgoing("sip:hunt@example.com", "sip:me@example.com", "fork-1", &local, None)
+            .unwrap();
+
+        let ringing = |tag: &str| {
+            let mut resp = Response::new(180, "Ringing").unwrap();
+            for name in ["Via", "From", "Call-ID", "CSeq"] {
+                let value = header_value(&invite.headers, name).unwrap();
+                resp.add_header(Header::new(name, value).unwrap());
+            }
+            let to = format!("<sip:hunt@example.com>;tag={}", tag);
+            resp.add_header(Header::new("To", &to).unwrap());
+            resp
+        };
+
+        let first = ringing("phone-a");
+        assert_eq!(dialogs.find_for_response(&first), Some(handle));
+        dialogs.get_mut(handle).unwrap().handle_invite_response(&first, &local);
+
+        // Another phone of the group rings: its tag matches no dialog yet.
+        let second = ringing("phone-b");
+        assert_eq!(dialogs.find_for_response(&second), Some(handle));
+        let fork = dialogs.fork(handle).unwrap();
+        assert_ne!(fork, handle);
+        dialogs.get_mut(fork).unwrap().handle_invite_response(&second, &local);
+
+        assert_eq!(dialogs.find_for_response(&first), Some(handle));
+        assert_eq!(dialogs.find_for_response(&second), Some(fork));
+        assert_eq!(dialogs.get(fork).unwrap().remote_tag(), Some("phone-b"));
+    }
+}
@@ -0,0 +1,136 @@
+//! Call forwarding by redirect: a 302 pointing the caller at another
+//! target, with Diversion (RFC 5806) and History-Info (RFC 7044) telling
+//! the next hop where the call was meant to go and why it went elsewhere.
+
+use crate::message::{header_value, header_values, Header, Request, Response};
+use crate::uri::{split_header_values, NameAddr, SipUri};
+use crate::{Result, SipError};
+
+/// Why we forward a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardReason {
+    Unconditional,
+    Busy,
+    NoAnswer,
+    DoNotDisturb,
+}
+
+impl ForwardReason {
+    fn diversion(self) -> &'static str {
+        match self {
+            ForwardReason::Unconditional => "unconditional",
+            ForwardReason::Busy => "user-busy",
+            ForwardReason::NoAnswer => "no-answer",
+            ForwardReason::DoNotDisturb => "do-not-disturb",
+        }
+    }
+
+    /// History-Info cause (RFC 4458 §4.1).
+    fn cause(self) -> u16 {
+        match self {
+            ForwardReason::Unconditional => 302,
+            ForwardReason::Busy => 486,
+            ForwardReason::NoAnswer => 408,
+            // Deflection on an immediate response.
+            ForwardReason::DoNotDisturb => 480,
+        }
+    }
+}
+
+/// Make `resp`, a 302 to `invite`, send the caller to `target`: a Contact
+/// for it, our own Diversion on top of any the INVITE brought, and the
+/// INVITE's History-Info extended by the new target.
+pub fn add_forwarding(
+    resp: &mut Response,
+    invite: &Request,
+    target: &str,
+    reason: ForwardReason,
+) -> Result<()> {
+    let mut target = SipUri::parse(target)?;
+    resp.add_header(Header::new("Contact", &format!("<{}>", target))?);
+
+    let to = header_value(&invite.headers, "To").ok_or(SipError::Invalid("missing To"))?;
+    let diverted_from = NameAddr::parse(to)?.uri;
+    resp.add_header(Header::new(
+        "Diversion",
+        &format!("<{}>;reason={};counter=1", diverted_from, reason.diversion()),
+    )?);
+    for diversion in header_values(&invite.headers, "Diversion") {
+        resp.add_header(Header::new("Diversion", diversion)?);
+    }
+
+    let mut history: Vec<String> = header_values(&invite.headers, "History-Info")
+        .into_iter()
+        .flat_map(split_header_values)
+        .map(str::to_string)
+        .collect();
+    let last_index = history
+        .last()
+        .and_then(|entry| NameAddr::parse(entry).ok())
+        .and_then(|entry| entry.param("index").map(str::to_string));
+    let index = match last_index {
+        Some(last) => format!("{}.1", last),
+        None => {
+            history.push(format!("<{}>;index=1", invite.uri));
+            "1.1".to_string()
+        }
+    };
+    target.params.push(("cause".to_string(), Some(reason.cause().to_string())));
+    history.push(format!("<{}>;index={}", target, index));
+    resp.add_header(Header::new("History-Info", &history.join(", "))?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Method;
+
+    fn invite() -> Request {
+        let mut req = Request::new(Method::Invite, "sip:me@192.0.2.50:5060").unwrap();
+        req.add_header(Header::new("To", "<sip:me@example.com>").unwrap()).unwrap();
+        req
+    }
+
+    #[test]
+    fn starts_the_history_when_the_invite_has_none() {
+        let mut resp = Response::new(302, "Moved Temporarily").unwrap();
+        add_forwarding(&mut resp, &invite(), "sip:desk@example.com", ForwardReason::NoAnswer).unwrap();
+
+        assert_eq!(header_value(&resp.headers, "Contact"), Some("<sip:desk@example.com>"));
+        assert_eq!(
+            header_value(&resp.headers, "Diversion"),
+            Some("<sip:me@example.com>;reason=no-answer;counter=1")
+        );
+        assert_eq!(
+            header_value(&resp.headers, "History-Info"),
+            Some("<sip:me@192.0.2.50:5060>;index=1, <sip:desk@example.com;cause=408>;index=1.1")
+        );
+    }
+
+    #[test]
+    fn extends_the_diversions_and_history_of_a_forwarded_call() {
+        let mut req = invite();
+        req.add_header(Header::new("Diversion", "<sip:100@example.com>;reason=user-busy").unwrap())
+            .unwrap();
+        req.add_header(
+            Header::new("History-Info", "<sip:100@example.com>;index=1, <sip:me@example.com;cause=486>;index=1.1")
+                .unwrap(),
+        )
+        .unwrap();
+
+        let mut resp = Response::new(302, "Moved Temporarily").unwrap();
+        add_forwarding(&mut resp, &req, "sip:desk@example.com", ForwardReason::Unconditional).unwrap();
+
+        assert_eq!(
+            header_values(&resp.headers, "Diversion"),
+            vec![
+                "<sip:me@example.com>;reason=unconditional;counter=1",
+                "<sip:100@example.com>;reason=user-busy",
+            ]
+        );
+        assert!(header_value(&resp.headers, "History-Info")
+            .unwrap()
+            .ends_with(", <sip:desk@example.com;cause=302>;index=1.1.1"));
+    }
+}
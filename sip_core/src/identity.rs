@@ -0,0 +1,225 @@
+//! Who is calling: the identity a trusted proxy asserts for the caller
+//! (P-Asserted-Identity, RFC 3325, or the older Remote-Party-ID), else the
+//! one the caller claims in From, and whether it asked for privacy
+//! (RFC 3323).
+
+use crate::message::{header_value, header_values, Request};
+use crate::uri::{split_header_values, NameAddr, SipUri};
+
+/// The From of a caller that withholds its identity (RFC 3323 §4.1.1.3).
+pub(crate) const ANONYMOUS_FROM: &str = "\"Anonymous\" <sip:anonymous@anonymous.invalid>";
+
+/// Where a caller identity was taken from, most trustworthy first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdentitySource {
+    PAssertedIdentity,
+    RemotePartyId,
+    #[default]
+    From,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CallerIdentity {
+    pub display_name: Option<String>,
+    /// The URI as received: `sip:`, `sips:` or `tel:`.
+    pub uri: String,
+    /// User part of a SIP URI without user parameters, or a tel number.
+    pub user: Option<String>,
+    /// Host of a SIP URI; a tel URI has none.
+    pub host: Option<String>,
+    pub source: IdentitySource,
+    /// The caller asked for its identity to be withheld (`Privacy: id`,
+    /// `privacy=full` in Remote-Party-ID, or an anonymous From). It is
+    /// still there for local policy, but must not be shown or logged.
+    pub private: bool,
+}
+
+impl CallerIdentity {
+    /// The caller of `req`, an incoming INVITE. P-Asserted-Identity and
+    /// Remote-Party-ID only count when `trusted`, i.e. `req` came from our
+    /// trust domain (RFC 3325 §5); anyone else only gets to claim a From.
+    /// An identity that fails to parse is passed over; a request with none
+    /// at all gives an empty, private identity.
+    pub fn from_request(req: &Request, trusted: bool) -> Self {
+        let privacy_id = header_values(&req.headers, "Privacy")
+            .into_iter()
+            .flat_map(|value| value.split(';'))
+            .any(|value| value.trim().eq_ignore_ascii_case("id"));
+
+        let asserted = header_values(&req.headers, "P-Asserted-Identity")
+            .into_iter()
+            .filter(|_| trusted)
+            .flat_map(split_header_values)
+            .filter_map(|value| NameAddr::parse(value).ok())
+            .map(|addr| Self::from_name_addr(addr, IdentitySource::PAssertedIdentity))
+            // A sip: identity says more than a tel: one: prefer it.
+            .reduce(|best, next| if best.host.is_none() && next.host.is_some() { next } else { best });
+        if let Some(mut identity) = asserted {
+            identity.private = privacy_id;
+            return identity;
+        }
+
+        let remote_party = header_values(&req.headers, "Remote-Party-ID")
+            .into_iter()
+            .filter(|_| trusted)
+            .flat_map(split_header_values)
+            .filter_map(|value| NameAddr::parse(value).ok())
+            .find(|addr| addr.param("party").map_or(true, |p| p.eq_ignore_ascii_case("calling")));
+        if let Some(addr) = remote_party {
+            let privacy = addr.param("privacy").unwrap_or("off").to_ascii_lowercase();
+            let mut identity = Self::from_name_addr(addr, IdentitySource::RemotePartyId);
+            if privacy == "name" {
+                identity.display_name = None;
+            }
+            identity.private = privacy_id || privacy == "full" || privacy == "uri";
+            return identity;
+        }
+
+        match header_value(&req.headers, "From").and_then(|from| NameAddr::parse(from).ok()) {
+            Some(from) => {
+                let mut identity = Self::from_name_addr(from, IdentitySource::From);
+                identity.private = privacy_id || identity.is_anonymous_uri();
+                identity
+            }
+            None => Self { private: true, ..Self::default() },
+        }
+    }
+
+    fn from_name_addr(addr: NameAddr, source: IdentitySource) -> Self {
+        let (user, host) = match SipUri::parse(&addr.uri) {
+            Ok(uri) => {
+                let user = uri.user.map(|user| match user.split_once(';') {
+                    Some((user, _params)) => user.to_string(),
+                    None => user,
+                });
+                (user, Some(uri.host))
+            }
+            Err(_) => {
+                let number = addr
+                    .uri
+                    .get(..4)
+                    .filter(|scheme| scheme.eq_ignore_ascii_case("tel:"))
+                    .and_then(|_| addr.uri[4..].split(';').next())
+                    .filter(|number| !number.is_empty())
+                    .map(str::to_string);
+                (number, None)
+            }
+        };
+        Self {
+            display_name: addr.display_name.filter(|name| !name.is_empty()),
+            uri: addr.uri,
+            user,
+            host,
+            source,
+            private: false,
+        }
+    }
+
+    /// The RFC 3323 §4.1.1.3 anonymous URI, `sip:anonymous@anonymous.invalid`
+    /// (any user at that host).
+    fn is_anonymous_uri(&self) -> bool {
+        self.host
+            .as_deref()
+            .is_some_and(|host| host.eq_ignore_ascii_case("anonymous.invalid"))
+    }
+
+    /// Whether `pattern`, a whole URI or only a user part, names this
+    /// caller. Identities withheld from display still match.
+    pub fn matches(&self, pattern: &str) -> bool {
+        if !pattern.contains(':') {
+            return self.user.as_deref() == Some(pattern);
+        }
+        let pattern = Self::from_name_addr(
+            NameAddr { display_name: None, uri: pattern.to_string(), params: Vec::new() },
+            IdentitySource::From,
+        );
+        pattern.user == self.user
+            && match (&pattern.host, &self.host) {
+                (Some(pattern), Some(host)) => pattern.eq_ignore_ascii_case(host),
+                (pattern, host) => pattern == host,
+            }
+    }
+
+    /// Whether we don't know, or may not tell, who is calling.
+    pub fn is_anonymous(&self) -> bool {
+        self.private || self.is_anonymous_uri()
+    }
+}
+
+/// How to show the caller: `"Name" <user@host>`, or `Anonymous` for a
+/// caller that withholds its identity.
+impl core::fmt::Display for CallerIdentity {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.is_anonymous() {
+            return f.write_str("Anonymous");
+        }
+        if let Some(name) = &self.display_name {
+            write!(f, "\"{}\" ", name)?;
+        }
+        match (&self.user, &self.host) {
+            (Some(user), Some(host)) => write!(f, "<{}@{}>", user, host),
+            (Some(number), None) => write!(f, "<{}>", number),
+            (None, Some(host)) => write!(f, "<{}>", host),
+            (None, None) => write!(f, "<{}>", self.uri),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Header, Method};
+
+    fn invite(headers: &[(&str, &str)]) -> Request {
+        let mut req = Request::new(Method::Invite, "sip:me@192.0.2.50").unwrap();
+        for (name, value) in headers {
+            req.add_header(Header::new(name, value).unwrap()).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn prefers_the_asserted_identity_over_from() {
+        let req = invite(&[
+            ("From", "\"Front door\" <sip:door@192.0.2.10>;tag=1"),
+            ("P-Asserted-Identity", "<tel:+15551234567>, \"Alice\" <sip:+15551234567;user=phone@pbx.example.com>"),
+        ]);
+        let caller = CallerIdentity::from_request(&req, true);
+        assert_eq!(caller.source, IdentitySource::PAssertedIdentity);
+        assert_eq!(caller.display_name.as_deref(), Some("Alice"));
+        assert_eq!(caller.user.as_deref(), Some("+15551234567"));
+        assert_eq!(caller.host.as_deref(), Some("pbx.example.com"));
+        assert_eq!(caller.to_string(), "\"Alice\" <+15551234567@pbx.example.com>");
+
+        let from_only = CallerIdentity::from_request(&invite(&[("From", "\"Front door\" <sip:door@192.0.2.10>;tag=1")]), true);
+        assert_eq!(from_only.source, IdentitySource::From);
+        assert_eq!(from_only.to_string(), "\"Front door\" <door@192.0.2.10>");
+
+        // Outside our trust domain anyone can assert anything.
+        let untrusted = CallerIdentity::from_request(&req, false);
+        assert_eq!(untrusted.source, IdentitySource::From);
+        assert_eq!(untrusted.user.as_deref(), Some("door"));
+    }
+
+    #[test]
+    fn withholds_identities_that_ask_for_privacy() {
+        let asserted = CallerIdentity::from_request(&invite(&[
+            ("From", "\"Anonymous\" <sip:anonymous@anonymous.invalid>;tag=1"),
+            ("P-Asserted-Identity", "<sip:alice@example.com>"),
+            ("Privacy", "id;critical"),
+        ]), true);
+        assert!(asserted.private);
+        assert_eq!(asserted.user.as_deref(), Some("alice"));
+        assert_eq!(asserted.to_string(), "Anonymous");
+
+        let rpid = CallerIdentity::from_request(&invite(&[
+            ("From", "<sip:bob@example.com>;tag=1"),
+            ("Remote-Party-ID", "\"Bob\" <sip:bob@example.com>;party=calling;privacy=full"),
+        ]), true);
+        assert_eq!(rpid.source, IdentitySource::RemotePartyId);
+        assert!(rpid.is_anonymous());
+
+        let from = CallerIdentity::from_request(&invite(&[("From", "<sip:anonymous@anonymous.invalid>;tag=1")]), true);
+        assert!(from.is_anonymous());
+    }
+}
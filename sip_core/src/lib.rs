@@ -4,33 +4,67 @@
 #![forbid(unsafe_code)]
 
 mod message;
+mod admission;
+mod alert;
 mod auth;
+mod qualify;
+mod redirect;
+mod refer;
+mod replaces;
 mod registration;
+mod session_timer;
 mod dialog;
+mod forward;
+mod identity;
+mod dialog_manager;
 mod stack;
 mod transaction;
+mod transport;
+mod uri;
+mod via;
 
 pub use crate::message::{
-    header_value, parse_message, Header, HeaderList, Method, Message, Request,
+    header_value, header_values, parse_message, Header, HeaderList, Method, Message, Request,
     Response, Version,
 };
 
+pub use crate::admission::{AdmissionPolicy, AdmissionStats, IpRange};
+
+pub use crate::alert::{alert_info, auto_answer_after};
+
 pub use crate::auth::{
     authorization_header, compute_digest_response, parse_www_authenticate,
     DigestChallenge, DigestCredentials,
 };
 
+pub use crate::identity::{CallerIdentity, IdentitySource};
+
+pub use crate::qualify::{Qualify, QualifyPoll, Reachability};
+
 pub use crate::registration::{
     RegistrationResult, RegistrationState, RegistrationTransaction,
 };
 
 pub use crate::dialog::{Dialog, DialogRole, DialogState, SipDialogId};
 
+pub use crate::dialog_manager::{DialogHandle, DialogManager, MAX_DIALOGS};
+
+pub use crate::forward::{add_forwarding, ForwardReason};
+
 pub use crate::stack::{
     CoreEvent, CoreRegistrationEvent, CoreDialogEvent,
-    InviteKind, SipStack,
+    InviteKind, LocalEndpoint, SipStack,
+};
+
+pub use crate::transport::{
+    hop_transport, select_transport, via_transport, StreamFramer, Transport, TransportAddr,
+    TransportKind, UDP_MAX_MESSAGE,
 };
 
+pub use crate::uri::{split_header_values, NameAddr, SipUri};
+
+pub use crate::via::{response_target, stamp_received, top_via, Via};
+
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -1,5 +1,7 @@
 use core::fmt::Write;
 
+use crate::transport::TransportKind;
+use crate::uri::{split_header_values, NameAddr, SipUri};
 use crate::{Result, SipError};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -10,6 +12,10 @@ pub enum Method {
     Bye,
     Cancel,
     Options,
+    Prack,
+    Update,
+    Refer,
+    Notify,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -92,6 +98,15 @@ impl Request {
         Ok(())
     }
 
+    /// Where this request goes first: the topmost Route if there is one,
+    /// otherwise the Request-URI (RFC 3261 §8.1.2).
+    pub fn next_hop(&self) -> Result<SipUri> {
+        match header_values(&self.headers, "Route").first() {
+            Some(route) => NameAddr::parse(route)?.sip_uri(),
+            None => SipUri::parse(&self.uri),
+        }
+    }
+
     pub fn render(&self) -> Result<String> {
         let mut out = String::new();
         write!(
@@ -159,6 +174,10 @@ impl core::fmt::Display for Method {
             Method::Bye => write!(f, "BYE"),
             Method::Cancel => write!(f, "CANCEL"),
             Method::Options => write!(f, "OPTIONS"),
+            Method::Prack => write!(f, "PRACK"),
+            Method::Update => write!(f, "UPDATE"),
+            Method::Refer => write!(f, "REFER"),
+            Method::Notify => write!(f, "NOTIFY"),
         }
     }
 }
@@ -262,6 +281,10 @@ fn parse_method(input: &str) -> Result<Method> {
         "BYE" => Ok(Method::Bye),
         "CANCEL" => Ok(Method::Cancel),
         "OPTIONS" => Ok(Method::Options),
+        "PRACK" => Ok(Method::Prack),
+        "UPDATE" => Ok(Method::Update),
+        "REFER" => Ok(Method::Refer),
+        "NOTIFY" => Ok(Method::Notify),
         _ => Err(SipError::Invalid("unknown method")),
     }
 }
@@ -273,6 +296,33 @@ pub fn header_value<'a>(headers: &'a HeaderList, name: &str) -> Option<&'a str>
         .map(|h| h.value.as_str())
 }
 
+/// All values of a header, in order, with comma-separated lists expanded.
+pub fn header_values<'a>(headers: &'a HeaderList, name: &str) -> Vec<&'a str> {
+    headers
+        .iter()
+        .filter(|h| h.name.eq_ignore_ascii_case(name))
+        .flat_map(|h| split_header_values(&h.value))
+        .collect()
+}
+
+pub(crate) fn build_via(
+    transport: TransportKind,
+    host: &str,
+    port: u16,
+    branch: &str,
+) -> Result<Header> {
+    let mut value = String::new();
+    write!(value, "SIP/2.0/{} {}:{};branch={};rport", transport, host, port, branch)
+        .map_err(|_| SipError::Capacity)?;
+    Header::new("Via", &value)
+}
+
+pub(crate) fn format_cseq(seq: u32, method: &str) -> Result<String> {
+    let mut buf = String::new();
+    write!(buf, "{} {}", seq, method).map_err(|_| SipError::Capacity)?;
+    Ok(buf)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -301,6 +351,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn next_hop_prefers_top_route() {
+        let mut req = Request::new(Method::Bye, "sip:bob@192.0.2.20:5062").unwrap();
+        assert_eq!(req.next_hop().unwrap().host, "192.0.2.20");
+
+        req.add_header(
+            Header::new("Route", "<sip:192.0.2.1;lr>, <sip:192.0.2.2;lr>").unwrap(),
+        )
+        .unwrap();
+        let hop = req.next_hop().unwrap();
+        assert_eq!(hop.host, "192.0.2.1");
+        assert_eq!(header_values(&req.headers, "Route").len(), 2);
+    }
+
     #[test]
     fn parses_options_request() {
         let raw = "OPTIONS sip:ping SIP/2.0\r\nVia: SIP/2.0/UDP host\r\n\r\n";
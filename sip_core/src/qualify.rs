@@ -0,0 +1,273 @@
+//! OPTIONS "qualify" probes towards the registrar: periodic pings with RTT
+//! measurement and reachability tracking, so we notice a dead server long
+//! before the next REGISTER refresh.
+
+use core::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::{
+    Result, header_value,
+    message::{build_via, format_cseq, Header, Method, Request, Response},
+    stack::LocalEndpoint,
+};
+
+// Non-INVITE client transaction timers (RFC 3261 §17.1.2.2).
+const T1: Duration = Duration::from_millis(500);
+const T2: Duration = Duration::from_secs(4);
+const TIMER_F: Duration = Duration::from_millis(500 * 64); // 64 * T1
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reachability {
+    #[default]
+    Unknown,
+    Reachable,
+    Unreachable,
+}
+
+/// What `Qualify::poll` wants done.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct QualifyPoll {
+    /// OPTIONS to send (a new probe or a retransmission).
+    pub send: Option<Request>,
+    /// Set when the probe in flight timed out and the server just became
+    /// unreachable.
+    pub changed: Option<Reachability>,
+}
+
+#[derive(Debug, Clone)]
+struct Probe {
+    request: Request,
+    cseq: u32,
+    sent_at: Instant,
+    retransmit_interval: Duration,
+    next_retransmit: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct Qualify {
+    target: Option<String>,
+    from_uri: String,
+    interval: Duration,
+    call_id: String,
+    from_tag: String,
+    cseq: u32,
+    branch_counter: u32,
+    next_probe: Option<Instant>,
+    in_flight: Option<Probe>,
+    state: Reachability,
+    last_rtt: Option<Duration>,
+}
+
+impl Default for Qualify {
+    fn default() -> Self {
+        Self {
+            target: None,
+            from_uri: String::new(),
+            interval: Duration::from_secs(60),
+            call_id: "qualify-1".to_string(),
+            from_tag: "qualify-1".to_string(),
+            cseq: 0,
+            branch_counter: 1,
+            next_probe: None,
+            in_flight: None,
+            state: Reachability::Unknown,
+            last_rtt: None,
+        }
+    }
+}
+
+impl Qualify {
+    /// Start probing `target` every `interval`, or stop when `None`.
+    pub fn configure(
+        &mut self,
+        target: &str,
+        from_uri: &str,
+        interval: Option<Duration>,
+        now: Instant,
+    ) {
+        self.in_flight = None;
+        self.state = Reachability::Unknown;
+        self.last_rtt = None;
+
+        match interval {
+            Some(interval) if !interval.is_zero() => {
+                self.target = Some(target.to_string());
+                self.from_uri = from_uri.to_string();
+                self.interval = interval;
+                self.next_probe = Some(now);
+            }
+            _ => {
+                self.target = None;
+                self.next_probe = None;
+            }
+        }
+    }
+
+    pub fn state(&self) -> Reachability {
+        self.state
+    }
+
+    /// Round-trip time of the last answered probe.
+    pub fn last_rtt(&self) -> Option<Duration> {
+        self.last_rtt
+    }
+
+    /// Drive probe timers. `reliable` disables retransmissions (Timer E).
+    pub fn poll(&mut self, now: Instant, local: &LocalEndpoint, reliable: bool) -> QualifyPoll {
+        let mut out = QualifyPoll::default();
+
+        if let Some(probe) = &mut self.in_flight {
+            if now >= probe.sent_at + TIMER_F {
+                log::info!("qualify: no answer to OPTIONS within {:?}", TIMER_F);
+                self.in_flight = None;
+                self.next_probe = Some(now + self.interval);
+                if self.state != Reachability::Unreachable {
+                    self.state = Reachability::Unreachable;
+                    out.changed = Some(self.state);
+                }
+            } else if !reliable && probe.next_retransmit.is_some_and(|t| now >= t) {
+                probe.retransmit_interval = (probe.retransmit_interval * 2).min(T2);
+                probe.next_retransmit = Some(now + probe.retransmit_interval);
+                out.send = Some(probe.request.clone());
+            }
+            return out;
+        }
+
+        if !self.next_probe.is_some_and(|t| now >= t) {
+            return out;
+        }
+
+        match self.build_options(local) {
+            Ok(request) => {
+                self.in_flight = Some(Probe {
+                    request: request.clone(),
+                    cseq: self.cseq,
+                    sent_at: now,
+                    retransmit_interval: T1,
+                    next_retransmit: Some(now + T1),
+                });
+                out.send = Some(request);
+            }
+            Err(e) => {
+                log::warn!("qualify: failed to build OPTIONS: {:?}", e);
+                self.next_probe = Some(now + self.interval);
+            }
+        }
+        out
+    }
+
+    /// Whether `resp` answers our probe (matching Call-ID and CSeq).
+    pub fn matches(&self, resp: &Response) -> bool {
+        let Some(probe) = &self.in_flight else {
+            return false;
+        };
+        header_value(&resp.headers, "Call-ID") == Some(self.call_id.as_str())
+            && header_value(&resp.headers, "CSeq")
+                .and_then(|c| c.split_whitespace().next())
+                .and_then(|n| n.parse::<u32>().ok())
+                == Some(probe.cseq)
+    }
+
+    /// Handle the answer to our probe. Any final response, even an error,
+    /// proves the server is alive. Returns the new state if it changed.
+    pub fn handle_response(&mut self, resp: &Response, now: Instant) -> Option<Reachability> {
+        if resp.status_code < 200 || !self.matches(resp) {
+            return None;
+        }
+        let probe = self.in_flight.take()?;
+
+        let rtt = now.duration_since(probe.sent_at);
+        log::debug!("qualify: {} {} in {:?}", resp.status_code, resp.reason, rtt);
+        self.last_rtt = Some(rtt);
+        self.next_probe = Some(now + self.interval);
+
+        if self.state == Reachability::Reachable {
+            return None;
+        }
+        self.state = Reachability::Reachable;
+        Some(self.state)
+    }
+
+    fn build_options(&mut self, local: &LocalEndpoint) -> Result<Request> {
+        let target = self.target.clone().unwrap_or_default();
+        self.cseq = self.cseq.wrapping_add(1);
+
+        let mut req = Request::new(Method::Options, &target)?;
+        req.add_header(build_via(local.transport, &local.host, local.port, &self.next_branch())?)?;
+        req.add_header(Header::new("Max-Forwards", "70")?)?;
+        req.add_header(Header::new("From", &format!("<{}>;tag={}", self.from_uri, self.from_tag))?)?;
+        req.add_header(Header::new("To", &format!("<{}>", target))?)?;
+        req.add_header(Header::new("Call-ID", &self.call_id)?)?;
+        req.add_header(Header::new("CSeq", &format_cseq(self.cseq, "OPTIONS")?)?)?;
+        req.add_header(Header::new("Accept", "application/sdp")?)?;
+        req.add_header(Header::new("Content-Length", "0")?)?;
+        Ok(req)
+    }
+
+    fn next_branch(&mut self) -> String {
+        let mut branch = String::new();
+        let counter = self.branch_counter;
+        self.branch_counter = self.branch_counter.wrapping_add(1);
+        let _ = write!(branch, "z9hG4bKqfy{:08x}", counter);
+        branch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local() -> LocalEndpoint {
+        LocalEndpoint {
+            host: "192.0.2.50".to_string(),
+            port: 5060,
+            ..Default::default()
+        }
+    }
+
+    fn answer(req: &Request, status: u16) -> Response {
+        let mut resp = Response::new(status, "OK").unwrap();
+        for name in ["Via", "From", "To", "Call-ID", "CSeq"] {
+            resp.add_header(Header::new(name, header_value(&req.headers, name).unwrap()).unwrap());
+        }
+        resp
+    }
+
+    #[test]
+    fn answered_probe_marks_reachable_and_measures_rtt() {
+        let start = Instant::now();
+        let mut q = Qualify::default();
+        let interval = Some(Duration::from_secs(30));
+        q.configure("sip:pbx.example.com", "sip:me@example.com", interval, start);
+
+        let options = q.poll(start, &local(), false).send.expect("probe");
+        assert_eq!(options.method, Method::Options);
+
+        let later = start + Duration::from_millis(40);
+        assert_eq!(q.handle_response(&answer(&options, 404), later), Some(Reachability::Reachable));
+        assert_eq!(q.last_rtt(), Some(Duration::from_millis(40)));
+
+        // Next probe waits for the interval.
+        assert!(q.poll(later + Duration::from_secs(1), &local(), false).send.is_none());
+        assert!(q.poll(later + Duration::from_secs(30), &local(), false).send.is_some());
+    }
+
+    #[test]
+    fn unanswered_probe_retransmits_then_goes_unreachable() {
+        let start = Instant::now();
+        let mut q = Qualify::default();
+        let interval = Some(Duration::from_secs(30));
+        q.configure("sip:pbx.example.com", "sip:me@example.com", interval, start);
+
+        let first = q.poll(start, &local(), false).send.unwrap();
+        let again = q.poll(start + T1, &local(), false).send.unwrap();
+        assert_eq!(first, again);
+
+        let timeout = q.poll(start + TIMER_F, &local(), false);
+        assert_eq!(timeout.changed, Some(Reachability::Unreachable));
+        assert_eq!(q.state(), Reachability::Unreachable);
+
+        // A late answer to the old probe is ignored.
+        assert_eq!(q.handle_response(&answer(&first, 200), start + TIMER_F), None);
+    }
+}
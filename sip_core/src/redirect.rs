@@ -0,0 +1,121 @@
+//! Redirection of our outgoing calls (RFC 3261 §8.1.3.4): a 3xx lists new
+//! targets in its Contacts, which we try in q-value order, best first.
+
+use core::cmp::Reverse;
+
+use crate::message::{header_values, Response};
+use crate::uri::{split_header_values, NameAddr};
+
+/// Most targets a call tries after the first, so redirect loops and long
+/// Contact lists end.
+const MAX_REDIRECTS: usize = 5;
+
+/// The targets of one outgoing call.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Redirects {
+    /// Targets tried so far, the original one first.
+    tried: Vec<String>,
+    /// Targets still to try, best first.
+    pending: Vec<String>,
+}
+
+impl Redirects {
+    /// Queue the Contacts of a 3xx from `current`, skipping any we tried
+    /// or queued already. They go before older targets: the latest
+    /// redirect knows best.
+    pub(crate) fn add_targets(&mut self, resp: &Response, current: &str) {
+        self.note_first(current);
+        let mut contacts: Vec<(u16, String)> = header_values(&resp.headers, "Contact")
+            .into_iter()
+            .flat_map(split_header_values)
+            .filter_map(|contact| NameAddr::parse(contact).ok())
+            .map(|contact| (q_value(contact.param("q")), contact.uri))
+            .collect();
+        // Stable, so equal q-values keep the order they came in.
+        contacts.sort_by_key(|(q, _)| Reverse(*q));
+
+        let mut added = Vec::new();
+        for (_, uri) in contacts {
+            if !self.known(&uri) && !added.iter().any(|a: &String| a.eq_ignore_ascii_case(&uri)) {
+                added.push(uri);
+            }
+        }
+        self.pending.splice(0..0, added);
+    }
+
+    /// The target to try after `current` failed, if any is left within
+    /// the limit.
+    pub(crate) fn next(&mut self, current: &str) -> Option<String> {
+        self.note_first(current);
+        if self.pending.is_empty() || self.tried.len() > MAX_REDIRECTS {
+            return None;
+        }
+        let target = self.pending.remove(0);
+        self.tried.push(target.clone());
+        Some(target)
+    }
+
+    fn note_first(&mut self, current: &str) {
+        if self.tried.is_empty() {
+            self.tried.push(current.to_string());
+        }
+    }
+
+    fn known(&self, uri: &str) -> bool {
+        self.tried
+            .iter()
+            .chain(&self.pending)
+            .any(|t| t.eq_ignore_ascii_case(uri))
+    }
+}
+
+/// A Contact's q parameter in thousandths; no q means 1.
+fn q_value(q: Option<&str>) -> u16 {
+    q.and_then(|q| q.trim().parse::<f32>().ok())
+        .map_or(1000, |q| (q.clamp(0.0, 1.0) * 1000.0) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Header;
+
+    fn redirect(contacts: &[&str]) -> Response {
+        let mut resp = Response::new(302, "Moved Temporarily").unwrap();
+        for contact in contacts {
+            resp.add_header(Header::new("Contact", contact).unwrap());
+        }
+        resp
+    }
+
+    #[test]
+    fn tries_contacts_by_q_value_without_loops() {
+        let mut redirects = Redirects::default();
+        redirects.add_targets(
+            &redirect(&["<sip:desk@192.0.2.20>;q=0.5, <sip:mobile@192.0.2.30>", "<sip:bob@example.com>"]),
+            "sip:bob@example.com",
+        );
+        assert_eq!(redirects.next("sip:bob@example.com").as_deref(), Some("sip:mobile@192.0.2.30"));
+
+        // The mobile bounces us back to the desk and to where we started.
+        redirects.add_targets(
+            &redirect(&["<sip:bob@example.com>;q=1.0", "<sip:desk@192.0.2.20>;q=0.9"]),
+            "sip:mobile@192.0.2.30",
+        );
+        assert_eq!(redirects.next("sip:mobile@192.0.2.30").as_deref(), Some("sip:desk@192.0.2.20"));
+        assert_eq!(redirects.next("sip:desk@192.0.2.20"), None);
+    }
+
+    #[test]
+    fn gives_up_after_the_limit() {
+        let mut redirects = Redirects::default();
+        let mut current = "sip:bob@example.com".to_string();
+        for hop in 0..MAX_REDIRECTS {
+            let next = format!("<sip:hop{}@192.0.2.40>", hop);
+            redirects.add_targets(&redirect(&[next.as_str()]), &current);
+            current = redirects.next(&current).expect("within the limit");
+        }
+        redirects.add_targets(&redirect(&["<sip:one-more@192.0.2.40>"]), &current);
+        assert_eq!(redirects.next(&current), None);
+    }
+}
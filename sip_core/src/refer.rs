@@ -0,0 +1,145 @@
+//! Call transfer (RFC 3515): a REFER asks the peer to call a third party,
+//! and the implicit subscription it creates reports how that call goes in
+//! NOTIFYs carrying `message/sipfrag` status lines.
+
+use crate::message::{header_value, HeaderList, Request, Response};
+
+/// Content type of the NOTIFY bodies (RFC 3420).
+pub(crate) const SIPFRAG: &str = "message/sipfrag";
+/// Lifetime we give the implicit subscription while the call is tried.
+const SUBSCRIPTION_EXPIRES: u32 = 60;
+
+/// Both sides of the implicit REFER subscription of one dialog.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Transfer {
+    /// Our REFER, until a final response.
+    pending_refer: Option<Request>,
+    /// We sent a REFER and its NOTIFYs are still due.
+    subscribed: bool,
+    /// We accepted the peer's REFER and owe it NOTIFYs until the final
+    /// status of our call.
+    notifying: bool,
+}
+
+impl Transfer {
+    pub(crate) fn in_progress(&self) -> bool {
+        self.subscribed
+    }
+
+    pub(crate) fn refer_sent(&mut self, refer: &Request) {
+        self.pending_refer = Some(refer.clone());
+        self.subscribed = true;
+    }
+
+    pub(crate) fn pending_refer(&self) -> Option<&Request> {
+        self.pending_refer.as_ref()
+    }
+
+    /// Final response to our REFER. Returns its status if the peer
+    /// refused: no NOTIFY will follow then.
+    pub(crate) fn on_refer_response(&mut self, resp: &Response) -> Option<u16> {
+        self.pending_refer = None;
+        if resp.status_code < 300 {
+            return None;
+        }
+        self.subscribed = false;
+        Some(resp.status_code)
+    }
+
+    /// A NOTIFY for our REFER: the status line of its sipfrag body, if it
+    /// has one. `None` when we aren't subscribed.
+    pub(crate) fn on_notify(&mut self, req: &Request) -> Option<Option<u16>> {
+        if !self.subscribed {
+            return None;
+        }
+        if subscription_terminated(&req.headers) {
+            self.subscribed = false;
+        }
+        Some(sipfrag_status(&req.body))
+    }
+
+    pub(crate) fn accept(&mut self) {
+        self.notifying = true;
+    }
+
+    /// Subscription-State for the NOTIFY reporting `status`; a final
+    /// status ends the subscription (RFC 3515 §2.4.7). `None` once it has.
+    pub(crate) fn notify_state(&mut self, status: u16) -> Option<String> {
+        if !self.notifying {
+            return None;
+        }
+        if status >= 200 {
+            self.notifying = false;
+            return Some("terminated;reason=noresource".to_string());
+        }
+        Some(format!("active;expires={}", SUBSCRIPTION_EXPIRES))
+    }
+}
+
+/// Whether the Event header names the REFER event package.
+pub(crate) fn is_refer_event(headers: &HeaderList) -> bool {
+    header_value(headers, "Event")
+        .and_then(|event| event.split(';').next())
+        .is_some_and(|package| package.trim().eq_ignore_ascii_case("refer"))
+}
+
+fn subscription_terminated(headers: &HeaderList) -> bool {
+    header_value(headers, "Subscription-State")
+        .and_then(|state| state.split(';').next())
+        .is_some_and(|state| state.trim().eq_ignore_ascii_case("terminated"))
+}
+
+/// Status code of a sipfrag body such as `SIP/2.0 180 Ringing`.
+fn sipfrag_status(body: &str) -> Option<u16> {
+    let mut parts = body.lines().next()?.split_whitespace();
+    if parts.next() != Some("SIP/2.0") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Header, Method};
+
+    fn notify(state: &str, body: &str) -> Request {
+        let mut req = Request::new(Method::Notify, "sip:me@192.0.2.50").unwrap();
+        req.add_header(Header::new("Event", "refer").unwrap()).unwrap();
+        req.add_header(Header::new("Subscription-State", state).unwrap()).unwrap();
+        req.set_body(body).unwrap();
+        req
+    }
+
+    #[test]
+    fn notifies_report_progress_until_terminated() {
+        let mut transfer = Transfer::default();
+        assert_eq!(transfer.on_notify(&notify("active", "SIP/2.0 100 Trying\r\n")), None);
+
+        let refer = Request::new(Method::Refer, "sip:alice@192.0.2.99").unwrap();
+        transfer.refer_sent(&refer);
+        assert!(is_refer_event(&notify("active", "").headers));
+        assert_eq!(
+            transfer.on_notify(&notify("active;expires=60", "SIP/2.0 180 Ringing\r\n")),
+            Some(Some(180))
+        );
+        assert_eq!(
+            transfer.on_notify(&notify("terminated;reason=noresource", "SIP/2.0 200 OK\r\n")),
+            Some(Some(200))
+        );
+        assert!(!transfer.in_progress());
+    }
+
+    #[test]
+    fn notifier_terminates_on_final_status() {
+        let mut transfer = Transfer::default();
+        assert_eq!(transfer.notify_state(100), None);
+        transfer.accept();
+        assert_eq!(transfer.notify_state(100).as_deref(), Some("active;expires=60"));
+        assert_eq!(
+            transfer.notify_state(486).as_deref(),
+            Some("terminated;reason=noresource")
+        );
+        assert_eq!(transfer.notify_state(200), None);
+    }
+}
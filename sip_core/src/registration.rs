@@ -1,7 +1,9 @@
 use core::fmt::Write;
 
 use crate::{
-    Result, SipError, auth::DigestChallenge, header_value, message::{Header, Request}
+    Result, SipError, auth::DigestChallenge, header_value,
+    message::{build_via, format_cseq, Header, Request},
+    stack::LocalEndpoint,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -53,8 +55,7 @@ impl RegistrationTransaction {
         &mut self,
         registrar_uri: &str,
         contact_uri: &str,
-        via_host: &str,
-        via_port: u16,
+        local: &LocalEndpoint,
         expires: u32,
         auth_header: Option<Header>,
     ) -> Result<Request> {
@@ -66,7 +67,7 @@ impl RegistrationTransaction {
         self.state = RegistrationState::Registering;
 
         let mut req = Request::new(crate::message::Method::Register, registrar_uri)?;
-        let via = build_via(via_host, via_port, self.next_branch())?;
+        let via = build_via(local.transport, &local.host, local.port, &self.next_branch())?;
         let from = build_from(contact_uri, &self.from_tag)?;
         let to = build_to(contact_uri, &self.to_tag)?;
 
@@ -155,17 +156,6 @@ fn simple_token(prefix: &str, counter: u32) -> String {
     token
 }
 
-fn build_via(
-    host: &str,
-    port: u16,
-    branch: String,
-) -> Result<Header> {
-    let mut value = String::new();
-    write!(value, "SIP/2.0/UDP {}:{};branch={};rport", host, port, branch)
-        .map_err(|_| SipError::Capacity)?;
-    Header::new("Via", &value)
-}
-
 fn build_from(uri: &str, tag: &str) -> Result<Header> {
     let mut value = String::new();
     write!(value, "{};tag={}", uri, tag).map_err(|_| SipError::Capacity)?;
@@ -178,12 +168,6 @@ fn build_to(uri: &str, tag: &str) -> Result<Header> {
     Header::new("To", &value)
 }
 
-fn format_cseq(seq: u32, method: &str) -> Result<String> {
-    let mut buf = String::new();
-    write!(buf, "{} {}", seq, method).map_err(|_| SipError::Capacity)?;
-    Ok(buf)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -191,6 +175,14 @@ mod tests {
         Method, Response
     };
 
+    fn local() -> LocalEndpoint {
+        LocalEndpoint {
+            host: "192.0.2.1".into(),
+            port: 5060,
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn registration_flow() {
         let mut reg = RegistrationTransaction::default();
@@ -198,8 +190,7 @@ mod tests {
             .build_register(
                 "sip:user@example.com",
                 "sip:user@example.com",
-                "192.0.2.1",
-                5060,
+                &local(),
                 120,
                 None,
             )
@@ -218,8 +209,7 @@ mod tests {
             .build_register(
                 "sip:user@example.com",
                 "sip:user@example.com",
-                "192.0.2.1",
-                5060,
+                &local(),
                 120,
                 None,
             )
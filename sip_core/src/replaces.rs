@@ -0,0 +1,180 @@
+//! The Replaces header (RFC 3891): an INVITE that takes the place of an
+//! existing dialog, as sent by the transferee in an attended transfer or
+//! by a phone picking up a call that rings elsewhere.
+
+use core::fmt::{self, Write};
+
+use crate::{
+    dialog::SipDialogId,
+    uri::SipUri,
+    Result, SipError,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Replaces {
+    pub call_id: String,
+    /// Tag of the UA receiving the Replaces.
+    pub to_tag: String,
+    /// Tag of the other end of the dialog being replaced.
+    pub from_tag: String,
+    /// Only an early dialog may be replaced, not an answered one.
+    pub early_only: bool,
+}
+
+impl Replaces {
+    pub(crate) fn parse(value: &str) -> Result<Self> {
+        let mut parts = value.split(';');
+        let call_id = parts.next().unwrap_or("").trim();
+        if call_id.is_empty() {
+            return Err(SipError::Invalid("Replaces call-id"));
+        }
+
+        let (mut to_tag, mut from_tag, mut early_only) = (None, None, false);
+        for param in parts {
+            match param.trim().split_once('=') {
+                Some((name, tag)) if name.trim().eq_ignore_ascii_case("to-tag") => {
+                    to_tag = Some(tag.trim().to_string());
+                }
+                Some((name, tag)) if name.trim().eq_ignore_ascii_case("from-tag") => {
+                    from_tag = Some(tag.trim().to_string());
+                }
+                None if param.trim().eq_ignore_ascii_case("early-only") => early_only = true,
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            call_id: call_id.to_string(),
+            to_tag: to_tag.ok_or(SipError::Invalid("Replaces to-tag"))?,
+            from_tag: from_tag.ok_or(SipError::Invalid("Replaces from-tag"))?,
+            early_only,
+        })
+    }
+
+    /// Replaces naming dialog `id` (our view of it) for our peer in it,
+    /// whose tag is therefore the to-tag.
+    pub(crate) fn for_peer_of(id: &SipDialogId) -> Self {
+        Self {
+            call_id: id.call_id.clone(),
+            to_tag: id.remote_tag.clone(),
+            from_tag: id.local_tag.clone(),
+            early_only: false,
+        }
+    }
+
+    /// Whether it names dialog `id`, as we see it.
+    pub(crate) fn matches(&self, id: &SipDialogId) -> bool {
+        self.call_id == id.call_id && self.to_tag == id.local_tag && self.from_tag == id.remote_tag
+    }
+}
+
+impl fmt::Display for Replaces {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{};to-tag={};from-tag={}", self.call_id, self.to_tag, self.from_tag)?;
+        if self.early_only {
+            f.write_str(";early-only")?;
+        }
+        Ok(())
+    }
+}
+
+/// `uri` with a `?Replaces=` header component (RFC 3261 §19.1.1), for the
+/// Refer-To of an attended transfer.
+pub(crate) fn uri_with_replaces(uri: &str, replaces: &Replaces) -> Result<String> {
+    let mut uri = SipUri::parse(uri)?.without_headers();
+    let mut escaped = String::new();
+    for b in replaces.to_string().bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.!~*'()".contains(&b) {
+            escaped.push(char::from(b));
+        } else {
+            write!(escaped, "%{:02X}", b).map_err(|_| SipError::Capacity)?;
+        }
+    }
+    uri.headers.push(("Replaces".to_string(), escaped));
+    Ok(uri.to_string())
+}
+
+/// Split a target URI into the Request-URI and the Replaces value carried
+/// in its headers, as a REFER for an attended transfer hands it over.
+/// Other URI headers are dropped; a URI we can't parse is left alone.
+pub(crate) fn take_replaces(target: &str) -> (String, Option<String>) {
+    let Ok(uri) = SipUri::parse(target) else {
+        return (target.to_string(), None);
+    };
+    if uri.headers.is_empty() {
+        return (target.to_string(), None);
+    }
+    let replaces = uri
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Replaces"))
+        .map(|(_, value)| unescape(value));
+    (uri.without_headers().to_string(), replaces)
+}
+
+fn unescape(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| core::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_a_refer_to_uri() {
+        let id = SipDialogId {
+            call_id: "abc123@192.0.2.50".to_string(),
+            local_tag: "ours".to_string(),
+            remote_tag: "theirs".to_string(),
+        };
+        let replaces = Replaces::for_peer_of(&id);
+        let uri = uri_with_replaces("sip:carol@192.0.2.77:5060;transport=tcp", &replaces).unwrap();
+        assert_eq!(
+            uri,
+            "sip:carol@192.0.2.77:5060;transport=tcp\
+             ?Replaces=abc123%40192.0.2.50%3Bto-tag%3Dtheirs%3Bfrom-tag%3Dours"
+        );
+
+        let (target, value) = take_replaces(&uri);
+        assert_eq!(target, "sip:carol@192.0.2.77:5060;transport=tcp");
+        let parsed = Replaces::parse(&value.unwrap()).unwrap();
+        assert_eq!(parsed, replaces);
+
+        // At the far end the tags swap sides.
+        let theirs = SipDialogId {
+            call_id: id.call_id.clone(),
+            local_tag: "theirs".to_string(),
+            remote_tag: "ours".to_string(),
+        };
+        assert!(parsed.matches(&theirs));
+        assert!(!parsed.matches(&id));
+    }
+
+    #[test]
+    fn parses_early_only_and_requires_both_tags() {
+        let parsed = Replaces::parse("xyz;from-tag=a; to-tag=b ;early-only").unwrap();
+        assert_eq!((parsed.to_tag.as_str(), parsed.from_tag.as_str()), ("b", "a"));
+        assert!(parsed.early_only);
+        assert!(Replaces::parse("xyz;to-tag=b").is_err());
+        assert_eq!(take_replaces("sip:bob@example.com"), ("sip:bob@example.com".to_string(), None));
+    }
+}
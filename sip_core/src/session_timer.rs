@@ -0,0 +1,173 @@
+//! Session timers (RFC 4028): the session is refreshed with re-INVITE or
+//! UPDATE every so often, and a dialog whose refreshes stop is torn down
+//! instead of staying up forever after a lost BYE.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    dialog::has_option_tag,
+    message::{header_value, HeaderList, Request, Response},
+};
+
+/// Session interval we ask for, in seconds (RFC 4028 §4 recommends 1800).
+pub(crate) const SESSION_EXPIRES: u32 = 1800;
+/// Smallest session interval we accept, in seconds (RFC 4028 §5).
+pub(crate) const MIN_SE: u32 = 90;
+
+/// What `SessionTimer::poll` wants done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SessionTimerDue {
+    /// Half the interval is gone and we are the refresher.
+    Refresh,
+    /// No refresh came in time: the session is over (RFC 4028 §10).
+    Expired,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SessionTimer {
+    /// Negotiated session interval; `None` when the session never expires.
+    interval: Option<Duration>,
+    /// Whether we send the refreshes.
+    we_refresh: bool,
+    /// Start of the current interval, set on the first poll after a
+    /// refresh.
+    refreshed_at: Option<Instant>,
+    refresh_sent: bool,
+}
+
+impl SessionTimer {
+    /// Session-Expires for a re-INVITE or UPDATE we send: the running
+    /// interval with the current refresher, or our preference before any
+    /// was negotiated. The refresher role is seen from the request's sender.
+    pub(crate) fn request_header(&self) -> String {
+        match self.interval {
+            Some(interval) => {
+                let refresher = if self.we_refresh { "uac" } else { "uas" };
+                format!("{};refresher={}", interval.as_secs(), refresher)
+            }
+            None => SESSION_EXPIRES.to_string(),
+        }
+    }
+
+    /// Settle the timer for a 2xx we send to the peer's INVITE or UPDATE
+    /// and restart the interval. Returns the Session-Expires value for the
+    /// response and whether it must carry `Require: timer`.
+    pub(crate) fn answer(&mut self, req: &Request) -> (String, bool) {
+        let peer_supports = has_option_tag(&req.headers, "Supported", "timer")
+            || has_option_tag(&req.headers, "Require", "timer");
+        let (secs, refresher) = match session_expires(&req.headers) {
+            Some((secs, refresher)) => (secs, refresher),
+            None => (SESSION_EXPIRES, None),
+        };
+        // A peer without the extension can't refresh; we do it for it.
+        let peer_refreshes = peer_supports && refresher.unwrap_or("uac") == "uac";
+
+        self.interval = Some(Duration::from_secs(u64::from(secs.max(MIN_SE))));
+        self.we_refresh = !peer_refreshes;
+        self.refreshed();
+
+        let refresher = if peer_refreshes { "uac" } else { "uas" };
+        (format!("{};refresher={}", secs.max(MIN_SE), refresher), peer_supports)
+    }
+
+    /// A 2xx to our INVITE or UPDATE: the interval the peer settled on,
+    /// or no expiry if it left Session-Expires out.
+    pub(crate) fn on_response(&mut self, resp: &Response) {
+        match session_expires(&resp.headers) {
+            Some((secs, refresher)) => {
+                self.interval = Some(Duration::from_secs(u64::from(secs.max(MIN_SE))));
+                self.we_refresh = refresher.unwrap_or("uac") == "uac";
+            }
+            None => self.interval = None,
+        }
+        self.refreshed();
+    }
+
+    pub(crate) fn poll(&mut self, now: Instant) -> Option<SessionTimerDue> {
+        let interval = self.interval?;
+        let since = *self.refreshed_at.get_or_insert(now);
+
+        if self.we_refresh && !self.refresh_sent && now >= since + interval / 2 {
+            self.refresh_sent = true;
+            return Some(SessionTimerDue::Refresh);
+        }
+        // BYE a little before the peer's own timer fires.
+        let margin = Duration::from_secs(32).min(interval / 3);
+        if now >= since + interval - margin {
+            self.interval = None;
+            return Some(SessionTimerDue::Expired);
+        }
+        None
+    }
+
+    fn refreshed(&mut self) {
+        self.refreshed_at = None;
+        self.refresh_sent = false;
+    }
+}
+
+/// Session-Expires too short for us, answered with 422 (RFC 4028 §8.1).
+pub(crate) fn interval_too_small(req: &Request) -> bool {
+    session_expires(&req.headers).is_some_and(|(secs, _)| secs < MIN_SE)
+}
+
+/// Session-Expires delta-seconds and refresher parameter.
+fn session_expires(headers: &HeaderList) -> Option<(u32, Option<&str>)> {
+    let value = header_value(headers, "Session-Expires")?;
+    let mut parts = value.split(';');
+    let secs = parts.next()?.trim().parse().ok()?;
+    let refresher = parts
+        .filter_map(|p| p.trim().split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("refresher"))
+        .map(|(_, v)| v.trim());
+    Some((secs, refresher))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Header, Method};
+
+    fn invite(headers: &[(&str, &str)]) -> Request {
+        let mut req = Request::new(Method::Invite, "sip:me@192.0.2.50").unwrap();
+        for (name, value) in headers {
+            req.add_header(Header::new(name, value).unwrap()).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn answering_picks_the_refresher_and_expires_without_refresh() {
+        let mut timer = SessionTimer::default();
+        let req = invite(&[("Supported", "100rel, timer"), ("Session-Expires", "600")]);
+        assert_eq!(timer.answer(&req), ("600;refresher=uac".to_string(), true));
+
+        let start = Instant::now();
+        assert_eq!(timer.poll(start), None);
+        assert_eq!(timer.poll(start + Duration::from_secs(400)), None, "peer refreshes");
+        assert_eq!(timer.poll(start + Duration::from_secs(568)), Some(SessionTimerDue::Expired));
+
+        // A peer without timer support leaves the refreshing to us.
+        let req = invite(&[("Session-Expires", "600")]);
+        assert_eq!(timer.answer(&req), ("600;refresher=uas".to_string(), false));
+        assert_eq!(timer.poll(start), None);
+        assert_eq!(timer.poll(start + Duration::from_secs(300)), Some(SessionTimerDue::Refresh));
+        assert_eq!(timer.poll(start + Duration::from_secs(301)), None, "one refresh per interval");
+        assert_eq!(timer.request_header(), "600;refresher=uac");
+    }
+
+    #[test]
+    fn response_sets_interval_or_turns_timer_off() {
+        let mut timer = SessionTimer::default();
+        assert_eq!(timer.request_header(), SESSION_EXPIRES.to_string());
+
+        let mut ok = Response::new(200, "OK").unwrap();
+        ok.add_header(Header::new("Session-Expires", "1200;refresher=uas").unwrap());
+        timer.on_response(&ok);
+        assert_eq!(timer.request_header(), "1200;refresher=uas");
+
+        timer.on_response(&Response::new(200, "OK").unwrap());
+        assert_eq!(timer.poll(Instant::now()), None);
+        assert!(interval_too_small(&invite(&[("Session-Expires", "30")])));
+    }
+}
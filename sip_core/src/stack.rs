@@ -1,14 +1,27 @@
-use crate::Result;
+use crate::{Result, SipError};
+use crate::admission::{parse_ranges, IpRange};
 use crate::auth::DigestChallenge;
-use crate::dialog::{Dialog, DialogState};
-use crate::message::{Header, Message, Method, Request, Response, header_value};
+use crate::dialog::{has_sdp, parse_tag_param, DialogRole, DialogState, SUPPORTED_HEADER_VALUE};
+use crate::dialog_manager::{DialogHandle, DialogManager};
+use crate::identity::CallerIdentity;
+use crate::message::{header_value, header_values, Header, Message, Method, Request, Response};
+use crate::qualify::{Qualify, Reachability};
+use crate::refer::is_refer_event;
+use crate::replaces::Replaces;
 use crate::registration::{RegistrationResult, RegistrationState, RegistrationTransaction};
-use crate::transaction::InviteServerTransactionManager;
-use std::net::SocketAddr;
-use std::time::Instant;
+use crate::session_timer::{interval_too_small, SessionTimerDue, MIN_SE};
+use crate::transaction::{
+    InviteClientEvent, InviteClientTransactionManager, InviteResponseAction,
+    InviteServerTransactionManager,
+};
+use crate::transport::{TransportAddr, TransportKind};
+use crate::uri::{NameAddr, SipUri};
+use crate::via::{response_target, stamp_received};
+use std::time::{Duration, Instant};
 
-const ALLOW_HEADER_VALUE: &str = "INVITE, ACK, CANCEL, BYE, OPTIONS";
-const ACCEPT_HEADER_VALUE: &str = "application/sdp";
+const ALLOW_HEADER_VALUE: &str =
+    "INVITE, ACK, CANCEL, BYE, OPTIONS, PRACK, UPDATE, REFER, NOTIFY";
+const ACCEPT_HEADER_VALUE: &str = "application/sdp, message/sipfrag";
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CoreRegistrationEvent {
@@ -20,52 +33,236 @@ pub enum CoreRegistrationEvent {
 pub enum InviteKind {
     Initial,
     Reinvite,
-    InitialWhileBusy,
+    /// A new call taking the place of dialog `.0` (RFC 3891): attended
+    /// transfer or call pickup. Once it is answered the old dialog ends.
+    Replacing(DialogHandle),
 }
 
+/// Dialog events name the dialog they concern by its handle.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CoreDialogEvent {
     IncomingInvite {
+        handle: DialogHandle,
         kind: InviteKind,
         request: Request,
+        /// Who is calling, from the INVITE's identity headers.
+        caller: CallerIdentity,
+        /// Where the INVITE arrived from; responses are routed from its Via.
+        source: TransportAddr,
+    },
+    /// A response from another fork of our INVITE started dialog `handle`
+    /// next to `original` (RFC 3261 §12.1.2). Both belong to the same call
+    /// until one of them is answered.
+    Forked {
+        original: DialogHandle,
+        handle: DialogHandle,
+    },
+    /// A response to an INVITE we sent, reported before any state change
+    /// it causes so the application can pick up the SDP.
+    InviteResponse {
+        handle: DialogHandle,
+        response: Response,
+    },
+    /// An UPDATE in an early or confirmed dialog (RFC 3311), possibly with
+    /// an SDP offer. The application answers it with a 2xx.
+    IncomingUpdate {
+        handle: DialogHandle,
+        request: Request,
+        source: TransportAddr,
+    },
+    /// Final response to an UPDATE we sent. A 491 is not reported: the
+    /// stack sends the UPDATE again after a random wait.
+    UpdateResponse {
+        handle: DialogHandle,
+        response: Response,
+    },
+    /// The peer asks us to call `refer_to` (RFC 3515), already accepted
+    /// with 202. The application reports on that call with
+    /// `SipStack::build_refer_notify`.
+    IncomingRefer {
+        handle: DialogHandle,
+        refer_to: String,
+    },
+    /// How the transfer we asked for with REFER goes: the status of the
+    /// transferee's call from each NOTIFY, or the final response of a
+    /// refused REFER. A 2xx means the transfer target answered.
+    TransferProgress {
+        handle: DialogHandle,
+        status_code: u16,
+    },
+    /// Our INVITE was redirected (3xx), or failed at a target an earlier
+    /// 3xx gave us, and goes on to `target` (RFC 3261 §8.1.3.4). The call
+    /// keeps its handle; the response is not reported otherwise.
+    Redirected {
+        handle: DialogHandle,
+        status_code: u16,
+        target: String,
+    },
+    /// The SDP answer to an offer we made in a 2xx, taken from the ACK.
+    /// Reported before the state change the ACK causes.
+    IncomingAnswer {
+        handle: DialogHandle,
+        sdp: String,
+    },
+    DialogStateChanged {
+        handle: DialogHandle,
+        state: DialogState,
     },
-    DialogStateChanged(DialogState),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CoreEvent {
     Registration(CoreRegistrationEvent),
     Dialog(CoreDialogEvent),
-    SendResponse(Response),
-    SendResponseTo {
+    /// A response and the address it goes to, worked out from its top Via
+    /// (RFC 3261 §18.2.2, RFC 3581).
+    SendResponse {
         response: Response,
-        target: SocketAddr,
+        target: TransportAddr,
+    },
+    /// A request the stack generated on its own (e.g. ACK) and its next hop
+    /// (top Route or Request-URI), for the application to resolve.
+    SendRequest {
+        request: Request,
+        target: SipUri,
+    },
+    /// The qualify probes found the server reachable or unreachable. `rtt`
+    /// is the round trip of the probe that proved it reachable.
+    Reachability {
+        state: Reachability,
+        rtt: Option<Duration>,
     },
 }
 
+/// Where we can be reached. Used for Via and Contact on requests the stack
+/// builds itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalEndpoint {
+    pub transport: TransportKind,
+    pub host: String,
+    pub port: u16,
+    pub contact_uri: String,
+}
+
 /// High-level SIP stack that wires registration + dialog together,
 /// and converts incoming messages into events for the application.
 #[derive(Debug, Default)]
 pub struct SipStack {
     pub registration: RegistrationTransaction,
-    pub dialog: Dialog,
+    pub dialogs: DialogManager,
+    pub local: LocalEndpoint,
+    pub qualify: Qualify,
     invite_transactions: InviteServerTransactionManager,
+    invite_clients: InviteClientTransactionManager,
     last_reg_state: RegistrationState,
+    /// xorshift state for 491 backoff; seeded by the application.
+    rng: u32,
+    /// Place calls without revealing who we are (RFC 3323).
+    anonymous: bool,
+    /// Who we take calls for besides our Contact: AOR and aliases, as
+    /// users or URIs.
+    local_identities: Vec<String>,
+    /// Our trust domain (RFC 3325): the flow to our registrar/proxy and any
+    /// other hosts whose P-Asserted-Identity we believe.
+    proxy_flow: Option<TransportAddr>,
+    trusted_sources: Vec<IpRange>,
 }
 
 impl SipStack {
+    pub fn set_local_endpoint(
+        &mut self,
+        transport: TransportKind,
+        host: &str,
+        port: u16,
+        contact_uri: &str,
+    ) {
+        self.local = LocalEndpoint {
+            transport,
+            host: host.to_string(),
+            port,
+            contact_uri: contact_uri.to_string(),
+        };
+    }
+
+    /// Seed the random waits before retrying after 491 Request Pending, so
+    /// two phones of the same make don't collide again.
+    pub fn set_random_seed(&mut self, seed: u32) {
+        self.rng = seed;
+    }
+
+    /// Withhold our identity on the calls we place from now on: an
+    /// anonymous From with `Privacy: id`, our AOR only in
+    /// P-Preferred-Identity for the proxy.
+    pub fn set_anonymous(&mut self, anonymous: bool) {
+        self.anonymous = anonymous;
+    }
+
+    /// Take calls only for our Contact, `aor` and `aliases` (users or
+    /// URIs); INVITEs for anyone else get 404 (RFC 3261 §8.2.2.1).
+    pub fn set_local_identities(&mut self, aor: &str, aliases: &[&str]) {
+        self.local_identities = core::iter::once(aor)
+            .chain(aliases.iter().copied())
+            .map(str::trim)
+            .filter(|identity| !identity.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+
+    /// The flow we registered over, if any: its requests may assert who is
+    /// calling.
+    pub fn set_proxy_flow(&mut self, flow: Option<TransportAddr>) {
+        self.proxy_flow = flow;
+    }
+
+    /// Other hosts, comma-separated addresses or CIDR ranges, whose
+    /// P-Asserted-Identity and Remote-Party-ID we believe.
+    pub fn set_trusted_sources(&mut self, sources: &str) -> Result<()> {
+        self.trusted_sources = parse_ranges(sources)?;
+        Ok(())
+    }
+
+    /// Whether requests from `source` come from our trust domain.
+    fn is_trusted(&self, source: TransportAddr) -> bool {
+        self.proxy_flow == Some(source)
+            || self.trusted_sources.iter().any(|range| range.contains(source.addr.ip()))
+    }
+
+    /// Whether `req` belongs to a dialog or INVITE transaction we already
+    /// have: an in-dialog request, a retransmission, or its ACK or CANCEL.
+    pub fn is_known_request(&self, req: &Request) -> bool {
+        self.dialogs.find_for_request(req).is_some()
+            || self.dialogs.find_for_cancel(req).is_some()
+            || self.invite_transactions.is_retransmission(req)
+    }
+
+    /// Ping `target` with OPTIONS every `interval` (`None` disables it).
+    /// Probes are sent from `poll_timers`.
+    pub fn set_qualify(
+        &mut self,
+        target: &str,
+        from_uri: &str,
+        interval: Option<Duration>,
+        now: Instant,
+    ) {
+        self.qualify.configure(target, from_uri, interval, now);
+    }
+
     /// Build a REGISTER request. Application is responsible for sending it.
+    /// Via is taken from the local endpoint.
     pub fn build_register(
         &mut self,
         registrar_uri: &str,
         contact_uri: &str,
-        via_host: &str,
-        via_port: u16,
         expires: u32,
         auth_header: Option<crate::message::Header>,
     ) -> Result<Request> {
-        self.registration
-            .build_register(registrar_uri, contact_uri, via_host, via_port, expires, auth_header)
+        self.registration.build_register(
+            registrar_uri,
+            contact_uri,
+            &self.local,
+            expires,
+            auth_header,
+        )
     }
 
     /// Handle a REGISTER response and emit registration events.
@@ -92,7 +289,7 @@ impl SipStack {
     /// This does *not* perform any I/O. The caller is responsible for:
     /// - Parsing text into `Message` (via `parse_message`).
     /// - Sending any `Request`/`Response` objects the application chooses to build.
-    pub fn on_message(&mut self, msg: Message, remote_addr: SocketAddr, now: Instant) -> Vec<CoreEvent> {
+    pub fn on_message(&mut self, msg: Message, remote_addr: TransportAddr, now: Instant) -> Vec<CoreEvent> {
         let mut events: Vec<CoreEvent> = Vec::new();
 
         match msg {
@@ -111,19 +308,46 @@ impl SipStack {
                     ));
 
                     return events;
+                } else if self.qualify.matches(&resp) {
+                    if let Some(state) = self.qualify.handle_response(&resp, now) {
+                        events.push(CoreEvent::Reachability {
+                            state,
+                            rtt: self.qualify.last_rtt(),
+                        });
+                    }
+                } else if cseq_method_is(&resp, "REFER") {
+                    self.handle_refer_response(&resp, &mut events);
+                } else if cseq_method_is(&resp, "UPDATE") {
+                    self.handle_update_response(&resp, now, &mut events);
+                } else if cseq_method_is(&resp, "CANCEL") {
+                    // The INVITE's own final response (normally 487) ends the call.
+                    self.invite_clients.on_cancel_response(&resp);
+                } else if cseq_method_is(&resp, "INVITE") {
+                    match self.invite_clients.on_response(&resp, now) {
+                        InviteResponseAction::Deliver => self.handle_invite_response(&resp, now, &mut events),
+                        InviteResponseAction::Reack(ack) => events.extend(send_request_event(ack)),
+                    }
                 } else {
-                    // Non-REGISTER responses (e.g. INVITE/ACK/BYE flows) are
-                    // not handled yet.
-                    log::warn!("on_message: unhandled non-REGISTER response: {}", resp.status_code);
+                    // BYE and other non-INVITE responses need no action.
+                    log::debug!("on_message: ignoring response {}", resp.status_code);
                 }
             }
-            Message::Request(req) => {
+            Message::Request(mut req) => {
+                if let Err(e) = stamp_received(&mut req, remote_addr) {
+                    log::warn!("on_message: dropping {} with bad Via: {:?}", req.method, e);
+                    return events;
+                }
+
                 match req.method {
-                    Method::Invite => self.handle_incoming_invite(req, remote_addr, &mut events),
+                    Method::Invite => self.handle_incoming_invite(req, remote_addr, now, &mut events),
                     Method::Cancel => self.handle_incoming_cancel(req, remote_addr, now, &mut events),
                     Method::Ack    => self.handle_incoming_ack(req, now, &mut events),
-                    Method::Bye    => self.handle_incoming_bye(req, &mut events),
-                    Method::Options => self.handle_incoming_options(req, &mut events),
+                    Method::Bye    => self.handle_incoming_bye(req, remote_addr, now, &mut events),
+                    Method::Options => self.handle_incoming_options(req, remote_addr, &mut events),
+                    Method::Prack  => self.handle_incoming_prack(req, remote_addr, now, &mut events),
+                    Method::Update => self.handle_incoming_update(req, remote_addr, now, &mut events),
+                    Method::Refer  => self.handle_incoming_refer(req, remote_addr, now, &mut events),
+                    Method::Notify => self.handle_incoming_notify(req, remote_addr, now, &mut events),
                     m => { log::warn!("on_message: unhandled request: {}", m); },
                 }
             }
@@ -135,58 +359,451 @@ impl SipStack {
     pub fn poll_timers(&mut self, now: Instant) -> Vec<CoreEvent> {
         let mut events = Vec::new();
         for (resp, target) in self.invite_transactions.poll(now) {
-            let _ = events.push(CoreEvent::SendResponseTo { response: resp, target });
+            let _ = events.push(CoreEvent::SendResponse { response: resp, target });
+        }
+        for (call_id, cseq, target) in self.invite_transactions.expired_provisionals(now) {
+            self.reject_unpracked(&call_id, cseq, target, now, &mut events);
+        }
+
+        for ev in self.invite_clients.poll(now) {
+            match ev {
+                InviteClientEvent::Send(invite) => events.extend(send_request_event(invite)),
+                InviteClientEvent::TimedOut { call_id, cseq } => {
+                    log::info!("poll_timers: INVITE {} {} timed out", call_id, cseq);
+                    for dialog in self
+                        .dialogs
+                        .iter_mut()
+                        .filter(|d| d.has_pending_invite(&call_id, cseq))
+                    {
+                        if dialog.invite_timed_out() {
+                            events.push(dialog.state_event());
+                        }
+                    }
+                }
+            }
+        }
+
+        let reliable = self.local.transport.is_reliable();
+        for dialog in self.dialogs.iter_mut() {
+            events.extend(dialog.poll_update_retry(now, &self.local).and_then(send_request_event));
+
+            if let Some((due, req)) = dialog.poll_session_timer(now, &self.local) {
+                if req.method == Method::Invite {
+                    self.invite_clients.on_invite_sent(&req, reliable, now);
+                }
+                events.extend(send_request_event(req));
+                if due == SessionTimerDue::Expired {
+                    events.push(dialog.state_event());
+                }
+            }
         }
+
+        let qualify = self.qualify.poll(now, &self.local, reliable);
+        if let Some(state) = qualify.changed {
+            events.push(CoreEvent::Reachability { state, rtt: None });
+        }
+        events.extend(qualify.send.and_then(send_request_event));
         events
     }
 
+    /// Start an outgoing call in a new dialog. The application sends the
+    /// returned INVITE.
+    pub fn start_call(
+        &mut self,
+        target: &str,
+        from_uri: &str,
+        call_id: &str,
+        body: Option<(&str, &str)>,
+        now: Instant,
+    ) -> Result<(DialogHandle, Request)> {
+        let handle = self.dialogs.create()?;
+        let dialog = self.dialogs.get_mut(handle).expect("handle just created");
+        let started = dialog
+            .start_outgoing(target, from_uri, call_id, &self.local, body)
+            .and_then(|req| if self.anonymous { dialog.make_anonymous(from_uri) } else { Ok(req) });
+        match started {
+            Ok(req) => {
+                let reliable = self.local.transport.is_reliable();
+                self.invite_clients.on_invite_sent(&req, reliable, now);
+                Ok((handle, req))
+            }
+            Err(e) => {
+                self.dialogs.remove(handle);
+                Err(e)
+            }
+        }
+    }
+
+    /// Build an UPDATE for dialog `handle`; `body` is normally an SDP offer.
+    pub fn build_update(&mut self, handle: DialogHandle, body: Option<(&str, &str)>) -> Result<Request> {
+        self.dialogs
+            .get_mut(handle)
+            .ok_or(SipError::InvalidState("no such dialog"))?
+            .build_update(&self.local, body)
+    }
+
+    /// Build a re-INVITE for dialog `handle` and track its transaction.
+    /// `body` is normally a new SDP offer.
+    pub fn build_reinvite(
+        &mut self,
+        handle: DialogHandle,
+        body: Option<(&str, &str)>,
+        now: Instant,
+    ) -> Result<Request> {
+        let req = self
+            .dialogs
+            .get_mut(handle)
+            .ok_or(SipError::InvalidState("no such dialog"))?
+            .build_reinvite(&self.local, body)?;
+        let reliable = self.local.transport.is_reliable();
+        self.invite_clients.on_invite_sent(&req, reliable, now);
+        Ok(req)
+    }
+
+    /// Build a REFER transferring the peer of dialog `handle` to
+    /// `refer_to`.
+    pub fn build_refer(&mut self, handle: DialogHandle, refer_to: &str) -> Result<Request> {
+        self.dialogs
+            .get_mut(handle)
+            .ok_or(SipError::InvalidState("no such dialog"))?
+            .build_refer(&self.local, refer_to)
+    }
+
+    /// Build a REFER for an attended transfer: the peer of dialog `handle`
+    /// is asked to call the peer of `consultation` with Replaces, taking
+    /// over our call with it.
+    pub fn build_attended_refer(
+        &mut self,
+        handle: DialogHandle,
+        consultation: DialogHandle,
+    ) -> Result<Request> {
+        let refer_to = self
+            .dialogs
+            .get(consultation)
+            .ok_or(SipError::InvalidState("no such dialog"))?
+            .replaces_target()?;
+        self.build_refer(handle, &refer_to)
+    }
+
+    /// Build the NOTIFY telling the peer of dialog `handle` how the call
+    /// its REFER asked for goes.
+    pub fn build_refer_notify(
+        &mut self,
+        handle: DialogHandle,
+        status: u16,
+        reason: &str,
+    ) -> Result<Request> {
+        self.dialogs
+            .get_mut(handle)
+            .ok_or(SipError::InvalidState("no such dialog"))?
+            .build_refer_notify(&self.local, status, reason)
+    }
+
+    /// Abandon our call `handle` before it is answered (RFC 3261 §9.1).
+    /// Returns the CANCEL to send, or `None` if none may go before a
+    /// provisional response arrives; `poll_timers` sends it then. The
+    /// dialog, and those of other forks, end with the INVITE's 487, or
+    /// with a BYE if a 2xx crosses the CANCEL.
+    pub fn cancel_call(&mut self, handle: DialogHandle, now: Instant) -> Result<Option<Request>> {
+        let (call_id, cseq) = self
+            .dialogs
+            .get(handle)
+            .ok_or(SipError::InvalidState("no such dialog"))?
+            .cancellable_invite()
+            .ok_or(SipError::InvalidState("call already answered"))?;
+        let cancel = self.invite_clients.cancel(&call_id, cseq, now)?;
+        for dialog in self
+            .dialogs
+            .iter_mut()
+            .filter(|d| d.has_pending_invite(&call_id, cseq))
+        {
+            dialog.mark_cancelled();
+        }
+        Ok(cancel)
+    }
+
+    /// Build a BYE for dialog `handle`, routed through its route set.
+    pub fn build_bye(&mut self, handle: DialogHandle) -> Result<Request> {
+        self.dialogs
+            .get_mut(handle)
+            .ok_or(SipError::InvalidState("no such dialog"))?
+            .build_bye(&self.local)
+    }
+
     /// Record an outgoing response so the stack can handle retransmissions.
-    pub fn record_outgoing_response(&mut self, resp: &Response, target: SocketAddr, now: Instant) {
+    pub fn record_outgoing_response(&mut self, resp: &Response, target: TransportAddr, now: Instant) {
         self.invite_transactions.on_outgoing_response(resp, target, now);
     }
 
+    /// Route a response to our INVITE to its dialog, forking a new early
+    /// dialog for a To tag we haven't seen (RFC 3261 §13.2.2.4).
+    fn handle_invite_response(&mut self, resp: &Response, now: Instant, events: &mut Vec<CoreEvent>) {
+        let Some(mut handle) = self.dialogs.find_for_response(resp) else {
+            log::debug!("handle_invite_response: response for no dialog");
+            return;
+        };
+
+        let to_tag = header_value(&resp.headers, "To").and_then(parse_tag_param);
+        let dialog = self.dialogs.get(handle).expect("handle just found");
+        let from_other_fork = resp.status_code > 100
+            && to_tag.is_some()
+            && dialog.state != DialogState::Inviting
+            && dialog.remote_tag() != to_tag;
+
+        let mut forked_from = None;
+        if from_other_fork {
+            match self.dialogs.fork(handle) {
+                Ok(fork) => {
+                    log::debug!("handle_invite_response: {} forked from {}", fork, handle);
+                    forked_from = Some(handle);
+                    handle = fork;
+                }
+                Err(e) => {
+                    log::warn!("handle_invite_response: cannot track fork: {:?}", e);
+                    return;
+                }
+            }
+        }
+
+        // Only one fork can win: every later 2xx is ACKed and then hung up.
+        let is_2xx = (200..300).contains(&resp.status_code);
+        let answered_elsewhere = is_2xx
+            && self.dialogs.iter().any(|d| {
+                d.handle() != handle
+                    && d.matches_response(resp)
+                    && matches!(d.state, DialogState::Established { .. })
+            });
+
+        let dialog = self.dialogs.get_mut(handle).expect("handle just found");
+        let dialog_events = dialog.handle_invite_response(resp, &self.local);
+
+        // A redirect sends the INVITE on to a new target.
+        let reliable = self.local.transport.is_reliable();
+        for ev in &dialog_events {
+            if let CoreEvent::SendRequest { request, .. } = ev {
+                if request.method == Method::Invite {
+                    self.invite_clients.on_invite_sent(request, reliable, now);
+                }
+            }
+        }
+
+        if answered_elsewhere {
+            log::info!("handle_invite_response: extra 2xx from another fork; sending BYE");
+            events.extend(dialog_events.into_iter().filter(|ev| !matches!(ev, CoreEvent::Dialog(_))));
+            match dialog.build_bye(&self.local) {
+                Ok(bye) => events.extend(send_request_event(bye)),
+                Err(e) => log::warn!("handle_invite_response: failed to build BYE: {:?}", e),
+            }
+            return;
+        }
+
+        if let Some(original) = forked_from {
+            events.push(CoreEvent::Dialog(CoreDialogEvent::Forked { original, handle }));
+        }
+        events.extend(dialog_events);
+
+        // A final response settles the INVITE: early dialogs of the other
+        // forks end with it.
+        if resp.status_code >= 200 {
+            for sibling in self
+                .dialogs
+                .iter_mut()
+                .filter(|d| d.handle() != handle && d.is_early_uac() && d.matches_response(resp))
+            {
+                sibling.terminate_local();
+                events.push(sibling.state_event());
+            }
+        }
+    }
+
     fn handle_incoming_invite(
         &mut self,
         req: Request,
-        remote_addr: SocketAddr,
+        remote_addr: TransportAddr,
+        now: Instant,
         events: &mut Vec<CoreEvent>,
     ) {
-        if let Some(resp) = self.invite_transactions.on_invite(&req, remote_addr) {
-            let _ = events.push(CoreEvent::SendResponseTo { response: resp, target: remote_addr });
+        if self.invite_transactions.is_retransmission(&req) {
+            if let Some(resp) = self.invite_transactions.on_invite(&req, remote_addr) {
+                events.extend(send_response_event(resp, remote_addr));
+            }
+            return;
         }
+        self.invite_transactions.on_invite(&req, remote_addr);
 
-        let dialog_events = self.dialog.handle_incoming_invite(req);
-        events.extend(dialog_events);
+        if interval_too_small(&req) {
+            self.reject_interval_too_small(&req, remote_addr, now, events);
+            return;
+        }
+
+        // A To tag means the peer thinks this INVITE is in an existing dialog.
+        let in_dialog = header_value(&req.headers, "To").and_then(parse_tag_param).is_some();
+        if in_dialog {
+            let Some(handle) = self.dialogs.find_for_request(&req) else {
+                log::debug!("handle_incoming_invite: re-INVITE for unknown dialog");
+                self.reject(&req, 481, "Call/Transaction Does Not Exist", remote_addr, now, events);
+                return;
+            };
+            let dialog = self.dialogs.get_mut(handle).expect("handle just found");
+            if has_sdp(&req.headers, &req.body) && dialog.offer_pending() {
+                log::info!("handle_incoming_invite: re-INVITE offer collides with ours");
+                self.reject(&req, 491, "Request Pending", remote_addr, now, events);
+                return;
+            }
+            dialog.handle_target_refresh(&req);
+            events.push(CoreEvent::Dialog(CoreDialogEvent::IncomingInvite {
+                handle,
+                kind: InviteKind::Reinvite,
+                caller: CallerIdentity::from_request(&req, self.is_trusted(remote_addr)),
+                request: req,
+                source: remote_addr,
+            }));
+            return;
+        }
+
+        if let Err((status, reason)) = self.check_request_uri(&req) {
+            log::info!("handle_incoming_invite: {} is not for us", req.uri);
+            self.reject(&req, status, reason, remote_addr, now, events);
+            return;
+        }
+
+        let kind = match header_value(&req.headers, "Replaces") {
+            Some(value) => match self.replaced_dialog(value, &req, remote_addr) {
+                Ok(replaced) => InviteKind::Replacing(replaced),
+                Err((status, reason)) => {
+                    log::info!("handle_incoming_invite: Replaces refused with {}", status);
+                    self.reject(&req, status, reason, remote_addr, now, events);
+                    return;
+                }
+            },
+            None => InviteKind::Initial,
+        };
+
+        let handle = match self.dialogs.create() {
+            Ok(handle) => handle,
+            Err(e) => {
+                log::info!("handle_incoming_invite: no room for another dialog: {:?}", e);
+                self.reject(&req, 486, "Busy Here", remote_addr, now, events);
+                return;
+            }
+        };
+        self.dialogs.get_mut(handle).expect("handle just created").handle_initial_invite(&req);
+        events.push(CoreEvent::Dialog(CoreDialogEvent::IncomingInvite {
+            handle,
+            kind,
+            caller: CallerIdentity::from_request(&req, self.is_trusted(remote_addr)),
+            request: req,
+            source: remote_addr,
+        }));
+    }
+
+    /// Whether a new call's Request-URI is one of ours, or the response
+    /// refusing it. Ours is one of our users (Contact, AOR, aliases) at
+    /// our address or at the domain of our AOR or an alias; a URI without
+    /// a user only at our address.
+    fn check_request_uri(&self, req: &Request) -> core::result::Result<(), (u16, &'static str)> {
+        let uri = SipUri::parse(&req.uri).map_err(|_| (416, "Unsupported URI Scheme"))?;
+        // (user, host) of our Contact first, then of our AOR and aliases.
+        let identities: Vec<(Option<String>, Option<String>)> =
+            core::iter::once(self.local.contact_uri.as_str())
+                .chain(self.local_identities.iter().map(String::as_str))
+                .map(|identity| match SipUri::parse(identity) {
+                    Ok(identity) => (identity.user, Some(identity.host)),
+                    Err(_) => (Some(identity.to_string()), None),
+                })
+                .collect();
+        let at_our_address = uri.host.eq_ignore_ascii_case(&self.local.host)
+            || identities
+                .first()
+                .and_then(|(_, host)| host.as_deref())
+                .is_some_and(|host| host.eq_ignore_ascii_case(&uri.host));
+        let at_our_domain = at_our_address
+            || identities
+                .iter()
+                .filter_map(|(_, host)| host.as_deref())
+                .any(|host| host.eq_ignore_ascii_case(&uri.host));
+
+        let ours = match &uri.user {
+            None => at_our_address,
+            Some(user) => {
+                at_our_domain && identities.iter().any(|(ours, _)| ours.as_ref() == Some(user))
+            }
+        };
+        if ours {
+            Ok(())
+        } else {
+            Err((404, "Not Found"))
+        }
+    }
+
+    /// The dialog a Replaces header names, or the response refusing it
+    /// (RFC 3891 §3): only answered calls and calls we are placing can be
+    /// taken over. Only our proxy or trusted sources, or the replaced
+    /// call's own peer, may take a call over (RFC 3891 §6).
+    fn replaced_dialog(
+        &self,
+        value: &str,
+        req: &Request,
+        source: TransportAddr,
+    ) -> core::result::Result<DialogHandle, (u16, &'static str)> {
+        let replaces = Replaces::parse(value).map_err(|_| (400, "Bad Request"))?;
+        let dialog = self
+            .dialogs
+            .iter()
+            .find(|d| d.is_replaced_by(&replaces))
+            .ok_or((481, "Call/Transaction Does Not Exist"))?;
+        let trusted = self.is_trusted(source);
+        let from_peer = dialog
+            .remote_uri()
+            .is_some_and(|peer| CallerIdentity::from_request(req, trusted).matches(&peer));
+        if !trusted && !from_peer {
+            return Err((403, "Forbidden"));
+        }
+        match dialog.state {
+            DialogState::Established { .. } if replaces.early_only => Err((486, "Busy Here")),
+            DialogState::Established { .. } => Ok(dialog.handle()),
+            DialogState::Ringing { role: DialogRole::Uac, .. } => Ok(dialog.handle()),
+            _ => Err((481, "Call/Transaction Does Not Exist")),
+        }
     }
 
     fn handle_incoming_cancel(
         &mut self,
         req: Request,
-        remote_addr: SocketAddr,
+        remote_addr: TransportAddr,
         now: Instant,
         events: &mut Vec<CoreEvent>,
     ) {
-        match self.dialog.handle_incoming_cancel(&req) {
+        let Some(dialog) = self
+            .dialogs
+            .find_for_cancel(&req)
+            .and_then(|handle| self.dialogs.get_mut(handle))
+        else {
+            self.reject(&req, 481, "Call/Transaction Does Not Exist", remote_addr, now, events);
+            return;
+        };
+
+        match dialog.handle_incoming_cancel(&req) {
             Ok(cancel_res) => {
-                // Emit the responses we must send
-                self.invite_transactions.on_outgoing_response(&cancel_res.cancel_ok, remote_addr, now);
-                let _ = events.push(
-                    CoreEvent::SendResponse(cancel_res.cancel_ok),
-                );
-                if let Some(resp_487) = cancel_res.maybe_invite_487 {
-                    self.invite_transactions.on_outgoing_response(&resp_487, remote_addr, now);
-                    let _ = events.push(
-                        CoreEvent::SendResponse(resp_487),
-                    );
+                // Let the app know the dialog died once the responses are out.
+                let state_event = dialog.state_event();
+
+                let responses = core::iter::once(cancel_res.cancel_ok)
+                    .chain(cancel_res.maybe_invite_487);
+                for response in responses {
+                    match response_target(&response, remote_addr) {
+                        Ok(target) => {
+                            self.invite_transactions.on_outgoing_response(&response, target, now);
+                            events.push(CoreEvent::SendResponse { response, target });
+                        }
+                        Err(e) => log::warn!("handle_incoming_cancel: {:?}", e),
+                    }
                 }
 
-                // And let the app know the dialog died
-                let _ = events.push(CoreEvent::Dialog(
-                    CoreDialogEvent::DialogStateChanged(self.dialog.state.clone()),
-                ));
+                events.push(state_event);
             }
-            Err(_e) => {
-                // log::warn!("handle_incoming_cancel: {:?}", e);
+            Err(e) => {
+                log::debug!("handle_incoming_cancel: {:?}", e);
             }
         }
     }
@@ -199,29 +816,53 @@ impl SipStack {
     ) {
         self.invite_transactions.on_ack(&req, now);
 
-        if let Err(_e) = self.dialog.handle_incoming_ack(&req) {
-            // log::warn!("handle_incoming_ack: {:?}", e);
+        // ACKs for our own non-2xx rejections match no dialog; that's fine.
+        let Some(dialog) = self
+            .dialogs
+            .find_for_request(&req)
+            .and_then(|handle| self.dialogs.get_mut(handle))
+        else {
             return;
+        };
+
+        match dialog.handle_incoming_ack(&req) {
+            Ok(Some(sdp)) => events.push(CoreEvent::Dialog(CoreDialogEvent::IncomingAnswer {
+                handle: dialog.handle(),
+                sdp,
+            })),
+            Ok(None) => {}
+            Err(_e) => {
+                // log::warn!("handle_incoming_ack: {:?}", e);
+                return;
+            }
         }
 
-        let _ = events.push(CoreEvent::Dialog(
-            CoreDialogEvent::DialogStateChanged(self.dialog.state.clone())
-        ));
+        events.push(dialog.state_event());
     }
 
     fn handle_incoming_bye (
         &mut self,
         req: Request,
+        remote_addr: TransportAddr,
+        now: Instant,
         events: &mut Vec<CoreEvent>,
     ) {
-        match self.dialog.handle_incoming_bye(&req) {
+        let Some(dialog) = self
+            .dialogs
+            .find_for_request(&req)
+            .and_then(|handle| self.dialogs.get_mut(handle))
+        else {
+            // RFC 3261 §15.1.2: a BYE for no dialog gets 481.
+            self.reject(&req, 481, "Call/Transaction Does Not Exist", remote_addr, now, events);
+            return;
+        };
+
+        match dialog.handle_incoming_bye(&req) {
             Ok(resp) => {
                 // send 200 OK for BYE
-                let _ = events.push(CoreEvent::SendResponse(resp));
+                events.extend(send_response_event(resp, remote_addr));
                 // dialog is already moved to Terminated by the dialog helper
-                let _ = events.push(CoreEvent::Dialog(
-                    CoreDialogEvent::DialogStateChanged(self.dialog.state.clone())
-                ));
+                events.push(dialog.state_event());
             }
             Err(_e) => {
                 // log::warn!("handle_incoming_bye: {:?}", e);
@@ -232,9 +873,11 @@ impl SipStack {
     fn handle_incoming_options(
         &mut self,
         req: Request,
+        remote_addr: TransportAddr,
         events: &mut Vec<CoreEvent>,
     ) {
-        match self.dialog.build_response_for_request(&req, 200, "OK", None) {
+        let handle = self.dialogs.find_for_request(&req);
+        match self.dialogs.build_response(handle, &req, 200, "OK", None) {
             Ok(mut resp) => {
                 if let Ok(allow) = Header::new("Allow", ALLOW_HEADER_VALUE) {
                     resp.add_header(allow);
@@ -242,7 +885,10 @@ impl SipStack {
                 if let Ok(accept) = Header::new("Accept", ACCEPT_HEADER_VALUE) {
                     resp.add_header(accept);
                 }
-                let _ = events.push(CoreEvent::SendResponse(resp));
+                if let Ok(supported) = Header::new("Supported", SUPPORTED_HEADER_VALUE) {
+                    resp.add_header(supported);
+                }
+                events.extend(send_response_event(resp, remote_addr));
             }
             Err(e) => {
                 log::warn!("handle_incoming_options: {:?}", e);
@@ -250,6 +896,280 @@ impl SipStack {
         }
     }
 
+    fn handle_incoming_update(
+        &mut self,
+        req: Request,
+        remote_addr: TransportAddr,
+        now: Instant,
+        events: &mut Vec<CoreEvent>,
+    ) {
+        let Some(dialog) = self
+            .dialogs
+            .find_for_request(&req)
+            .and_then(|handle| self.dialogs.get_mut(handle))
+        else {
+            self.reject(&req, 481, "Call/Transaction Does Not Exist", remote_addr, now, events);
+            return;
+        };
+
+        if interval_too_small(&req) {
+            self.reject_interval_too_small(&req, remote_addr, now, events);
+            return;
+        }
+
+        // RFC 3311 §5.2: an offer while ours is unanswered is glare.
+        if has_sdp(&req.headers, &req.body) && dialog.offer_pending() {
+            log::info!("handle_incoming_update: offer collides with ours");
+            self.reject(&req, 491, "Request Pending", remote_addr, now, events);
+            return;
+        }
+
+        dialog.handle_target_refresh(&req);
+        events.push(CoreEvent::Dialog(CoreDialogEvent::IncomingUpdate {
+            handle: dialog.handle(),
+            request: req,
+            source: remote_addr,
+        }));
+    }
+
+    fn handle_update_response(&mut self, resp: &Response, now: Instant, events: &mut Vec<CoreEvent>) {
+        let Some((handle, owner)) = self
+            .dialogs
+            .iter()
+            .find(|d| d.matches_update_response(resp))
+            .map(|d| (d.handle(), d.owns_call_id()))
+        else {
+            log::debug!("handle_update_response: no pending UPDATE for {}", resp.status_code);
+            return;
+        };
+        if resp.status_code < 200 {
+            return;
+        }
+
+        let retry_after = self.glare_backoff(owner);
+        if let Some(dialog) = self.dialogs.get_mut(handle) {
+            dialog.handle_update_response(resp, retry_after, now);
+        }
+        if resp.status_code == 491 {
+            log::info!("handle_update_response: 491, retrying in {:?}", retry_after);
+            return;
+        }
+        events.push(CoreEvent::Dialog(CoreDialogEvent::UpdateResponse {
+            handle,
+            response: resp.clone(),
+        }));
+    }
+
+    /// REFER inside a dialog (RFC 3515 §2.4): accepted with 202, reported
+    /// to the application, and followed at once by a first NOTIFY.
+    fn handle_incoming_refer(
+        &mut self,
+        req: Request,
+        remote_addr: TransportAddr,
+        now: Instant,
+        events: &mut Vec<CoreEvent>,
+    ) {
+        let Some(handle) = self.dialogs.find_for_request(&req) else {
+            self.reject(&req, 481, "Call/Transaction Does Not Exist", remote_addr, now, events);
+            return;
+        };
+
+        // Exactly one Refer-To (RFC 3515 §2.4.1).
+        let refer_to = match header_values(&req.headers, "Refer-To").as_slice() {
+            [value] => NameAddr::parse(value).ok().map(|name_addr| name_addr.uri),
+            _ => None,
+        };
+        let Some(refer_to) = refer_to else {
+            self.reject(&req, 400, "Bad Request", remote_addr, now, events);
+            return;
+        };
+
+        match self.dialogs.build_response(Some(handle), &req, 202, "Accepted", None) {
+            Ok(resp) => events.extend(send_response_event(resp, remote_addr)),
+            Err(e) => {
+                log::warn!("handle_incoming_refer: {:?}", e);
+                return;
+            }
+        }
+
+        let dialog = self.dialogs.get_mut(handle).expect("handle just found");
+        dialog.accept_refer();
+        match dialog.build_refer_notify(&self.local, 100, "Trying") {
+            Ok(notify) => events.extend(send_request_event(notify)),
+            Err(e) => log::warn!("handle_incoming_refer: failed to build NOTIFY: {:?}", e),
+        }
+        events.push(CoreEvent::Dialog(CoreDialogEvent::IncomingRefer { handle, refer_to }));
+    }
+
+    fn handle_refer_response(&mut self, resp: &Response, events: &mut Vec<CoreEvent>) {
+        let Some(dialog) = self.dialogs.iter_mut().find(|d| d.matches_refer_response(resp)) else {
+            log::debug!("handle_refer_response: no pending REFER for {}", resp.status_code);
+            return;
+        };
+        if let Some(status_code) = dialog.handle_refer_response(resp) {
+            events.push(CoreEvent::Dialog(CoreDialogEvent::TransferProgress {
+                handle: dialog.handle(),
+                status_code,
+            }));
+        }
+    }
+
+    /// NOTIFY of the implicit subscription our REFER created.
+    fn handle_incoming_notify(
+        &mut self,
+        req: Request,
+        remote_addr: TransportAddr,
+        now: Instant,
+        events: &mut Vec<CoreEvent>,
+    ) {
+        if !is_refer_event(&req.headers) {
+            self.reject(&req, 489, "Bad Event", remote_addr, now, events);
+            return;
+        }
+        let progress = self
+            .dialogs
+            .find_for_request(&req)
+            .and_then(|handle| self.dialogs.get_mut(handle))
+            .and_then(|dialog| Some((dialog.handle(), dialog.handle_incoming_notify(&req).ok()?)));
+        let Some((handle, status)) = progress else {
+            self.reject(&req, 481, "Subscription Does Not Exist", remote_addr, now, events);
+            return;
+        };
+
+        match self.dialogs.build_response(Some(handle), &req, 200, "OK", None) {
+            Ok(resp) => events.extend(send_response_event(resp, remote_addr)),
+            Err(e) => log::warn!("handle_incoming_notify: {:?}", e),
+        }
+        if let Some(status_code) = status {
+            events.push(CoreEvent::Dialog(CoreDialogEvent::TransferProgress { handle, status_code }));
+        }
+    }
+
+    /// How long to wait before retrying after 491 (RFC 3261 §14.1): 2.1 to
+    /// 4 s if we own the Call-ID, up to 2 s otherwise, in 10 ms steps.
+    fn glare_backoff(&mut self, owner: bool) -> Duration {
+        // xorshift32; zero is its fixed point.
+        let mut x = if self.rng == 0 { 0x9e37_79b9 } else { self.rng };
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+
+        let steps = if owner { 210 + x % 191 } else { x % 201 };
+        Duration::from_millis(u64::from(steps) * 10)
+    }
+
+    /// PRACK for one of our reliable provisional responses (RFC 3262 §3).
+    fn handle_incoming_prack(
+        &mut self,
+        req: Request,
+        remote_addr: TransportAddr,
+        now: Instant,
+        events: &mut Vec<CoreEvent>,
+    ) {
+        let handle = self.dialogs.find_for_request(&req);
+        if handle.is_none() || !self.invite_transactions.on_prack(&req) {
+            self.reject(&req, 481, "Call/Transaction Does Not Exist", remote_addr, now, events);
+            return;
+        }
+
+        match self.dialogs.build_response(handle, &req, 200, "OK", None) {
+            Ok(resp) => events.extend(send_response_event(resp, remote_addr)),
+            Err(e) => log::warn!("handle_incoming_prack: {:?}", e),
+        }
+    }
+
+    /// Our reliable provisional went unacknowledged: give up on the call
+    /// with a 500 and end its dialog.
+    fn reject_unpracked(
+        &mut self,
+        call_id: &str,
+        cseq: u32,
+        target: TransportAddr,
+        now: Instant,
+        events: &mut Vec<CoreEvent>,
+    ) {
+        let Some((handle, invite)) = self
+            .dialogs
+            .iter()
+            .find_map(|d| Some((d.handle(), d.ringing_invite(call_id, cseq)?.clone())))
+        else {
+            return;
+        };
+        log::info!("reject_unpracked: no PRACK for {}", handle);
+
+        match self.dialogs.build_response(Some(handle), &invite, 500, "Server Internal Error", None) {
+            Ok(response) => {
+                self.invite_transactions.on_outgoing_response(&response, target, now);
+                events.push(CoreEvent::SendResponse { response, target });
+            }
+            Err(e) => log::warn!("reject_unpracked: {:?}", e),
+        }
+        if let Some(dialog) = self.dialogs.get_mut(handle) {
+            dialog.terminate_local();
+            events.push(dialog.state_event());
+        }
+    }
+
+    /// 422 with our Min-SE for a session interval below it (RFC 4028 §8.1).
+    fn reject_interval_too_small(
+        &mut self,
+        req: &Request,
+        remote_addr: TransportAddr,
+        now: Instant,
+        events: &mut Vec<CoreEvent>,
+    ) {
+        let Some(mut response) = self.standalone_response(req, 422, "Session Interval Too Small") else {
+            return;
+        };
+        if let Ok(min_se) = Header::new("Min-SE", &MIN_SE.to_string()) {
+            response.add_header(min_se);
+        }
+        self.send_final_response(response, remote_addr, now, events);
+    }
+
+    /// Answer `req` outside any dialog with a final response. Responses to
+    /// INVITE are tracked so they are retransmitted until ACKed.
+    fn reject(
+        &mut self,
+        req: &Request,
+        status: u16,
+        reason: &str,
+        remote_addr: TransportAddr,
+        now: Instant,
+        events: &mut Vec<CoreEvent>,
+    ) {
+        if let Some(response) = self.standalone_response(req, status, reason) {
+            self.send_final_response(response, remote_addr, now, events);
+        }
+    }
+
+    fn standalone_response(&mut self, req: &Request, status: u16, reason: &str) -> Option<Response> {
+        match self.dialogs.build_response(None, req, status, reason, None) {
+            Ok(resp) => Some(resp),
+            Err(e) => {
+                log::warn!("failed to build {} response: {:?}", status, e);
+                None
+            }
+        }
+    }
+
+    fn send_final_response(
+        &mut self,
+        response: Response,
+        remote_addr: TransportAddr,
+        now: Instant,
+        events: &mut Vec<CoreEvent>,
+    ) {
+        match response_target(&response, remote_addr) {
+            Ok(target) => {
+                self.invite_transactions.on_outgoing_response(&response, target, now);
+                events.push(CoreEvent::SendResponse { response, target });
+            }
+            Err(e) => log::warn!("no target for {} response: {:?}", response.status_code, e),
+        }
+    }
+
     pub fn registration_state(&self) -> RegistrationState {
         self.registration.state()
     }
@@ -264,11 +1184,161 @@ impl SipStack {
     }
 }
 
+/// Event for sending a request to its next hop.
+pub(crate) fn send_request_event(request: Request) -> Option<CoreEvent> {
+    match request.next_hop() {
+        Ok(target) => Some(CoreEvent::SendRequest { request, target }),
+        Err(e) => {
+            log::warn!("no next hop for {}: {:?}", request.method, e);
+            None
+        }
+    }
+}
+
+/// Event for sending a response to a request that arrived from `source`.
+fn send_response_event(response: Response, source: TransportAddr) -> Option<CoreEvent> {
+    match response_target(&response, source) {
+        Ok(target) => Some(CoreEvent::SendResponse { response, target }),
+        Err(e) => {
+            log::warn!("no target for {} response: {:?}", response.status_code, e);
+            None
+        }
+    }
+}
+
 /// Heuristic: treat any response whose CSeq ends in "REGISTER" as a REGISTER response.
 fn is_register_response(resp: &Response) -> bool {
+    cseq_method_is(resp, "REGISTER")
+}
+
+fn cseq_method_is(resp: &Response, method: &str) -> bool {
     if let Some(cseq) = header_value(&resp.headers, "CSeq") {
-        cseq.trim().ends_with("REGISTER")
+        cseq.trim().ends_with(method)
     } else {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::parse_message;
+
+    fn invite(request_uri: &str) -> Message {
+        let text = format!(
+            "INVITE {} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 203.0.113.9:5060;branch=z9hG4bK-{}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:caller@203.0.113.9>;tag=c1\r\n\
+             To: <{}>\r\n\
+             Call-ID: call-{}@203.0.113.9\r\n\
+             CSeq: 1 INVITE\r\n\
+             Contact: <sip:caller@203.0.113.9>\r\n\
+             Content-Length: 0\r\n\r\n",
+            request_uri, request_uri.len(), request_uri, request_uri.len(),
+        );
+        parse_message(&text).unwrap()
+    }
+
+    fn answer(stack: &mut SipStack, request_uri: &str) -> Option<u16> {
+        let source = TransportAddr::udp("203.0.113.9:5060".parse().unwrap());
+        stack
+            .on_message(invite(request_uri), source, Instant::now())
+            .into_iter()
+            .find_map(|ev| match ev {
+                CoreEvent::SendResponse { response, .. } => Some(response.status_code),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn calls_for_other_users_get_404() {
+        let mut stack = SipStack::default();
+        stack.set_local_endpoint(TransportKind::Udp, "192.0.2.50", 5060, "sip:me@192.0.2.50:5060");
+        stack.set_local_identities("sip:100@pbx.example.com", &["door"]);
+
+        assert_eq!(answer(&mut stack, "sip:me@192.0.2.50:5060"), None);
+        assert_eq!(answer(&mut stack, "sip:100@pbx.example.com"), None);
+        assert_eq!(answer(&mut stack, "sip:door@192.0.2.50"), None);
+        assert_eq!(answer(&mut stack, "sip:9011442079460000@192.0.2.50"), Some(404));
+        assert_eq!(answer(&mut stack, "tel:+15551234567"), Some(416));
+    }
+
+    #[test]
+    fn our_users_at_other_hosts_get_404() {
+        let mut stack = SipStack::default();
+        stack.set_local_endpoint(TransportKind::Udp, "192.0.2.50", 5060, "sip:me@192.0.2.50:5060");
+        stack.set_local_identities("sip:100@pbx.example.com", &[]);
+
+        assert_eq!(answer(&mut stack, "sip:100@198.51.100.1"), Some(404));
+        assert_eq!(answer(&mut stack, "sip:me@evil.example.net"), Some(404));
+
+        // Without an AOR only our Contact is ours.
+        stack.set_local_identities("", &[]);
+        assert_eq!(answer(&mut stack, "sip:me@192.0.2.50"), None);
+        assert_eq!(answer(&mut stack, "sip:100@192.0.2.50"), Some(404));
+    }
+
+    #[test]
+    fn user_less_uris_are_ours_only_at_our_address() {
+        let mut stack = SipStack::default();
+        stack.set_local_endpoint(TransportKind::Udp, "192.0.2.50", 5060, "sip:me@192.0.2.50:5060");
+        stack.set_local_identities("sip:100@pbx.example.com", &[]);
+
+        assert_eq!(answer(&mut stack, "sip:192.0.2.50"), None);
+        assert_eq!(answer(&mut stack, "sip:198.51.100.1"), Some(404));
+        assert_eq!(answer(&mut stack, "sip:pbx.example.com"), Some(404));
+    }
+
+    #[test]
+    fn only_our_proxy_or_the_peer_may_replace_a_call() {
+        let mut stack = SipStack::default();
+        stack.set_local_endpoint(TransportKind::Udp, "192.0.2.50", 5060, "sip:me@192.0.2.50:5060");
+        stack.set_trusted_sources("198.51.100.1").unwrap();
+        let now = Instant::now();
+        let (_, invite) = stack
+            .start_call("sip:bob@198.51.100.7", "sip:me@192.0.2.50", "xfer@192.0.2.50", None, now)
+            .unwrap();
+        let header = |name| header_value(&invite.headers, name).unwrap();
+        let ringing = format!(
+            "SIP/2.0 180 Ringing\r\nVia: {}\r\nFrom: {}\r\nTo: {};tag=b1\r\n\
+             Call-ID: {}\r\nCSeq: {}\r\nContact: <sip:bob@198.51.100.7>\r\n\
+             Content-Length: 0\r\n\r\n",
+            header("Via"), header("From"), header("To"), header("Call-ID"), header("CSeq"),
+        );
+        let peer = TransportAddr::udp("198.51.100.7:5060".parse().unwrap());
+        stack.on_message(parse_message(&ringing).unwrap(), peer, now);
+        let our_tag = parse_tag_param(header("From")).unwrap();
+        let replaces = format!("xfer@192.0.2.50;to-tag={};from-tag=b1", our_tag);
+
+        let mut replace = |from: &str, source: &str| {
+            let text = format!(
+                "INVITE sip:me@192.0.2.50:5060 SIP/2.0\r\n\
+                 Via: SIP/2.0/UDP {};branch=z9hG4bK-{}\r\n\
+                 Max-Forwards: 70\r\n\
+                 From: <{}>;tag=r1\r\n\
+                 To: <sip:me@192.0.2.50:5060>\r\n\
+                 Call-ID: replace-{}\r\n\
+                 CSeq: 1 INVITE\r\n\
+                 Replaces: {}\r\n\
+                 Content-Length: 0\r\n\r\n",
+                source, source, from, source, replaces,
+            );
+            let source = TransportAddr::udp(source.parse().unwrap());
+            stack.on_message(parse_message(&text).unwrap(), source, now).into_iter().find_map(
+                |ev| match ev {
+                    CoreEvent::SendResponse { response, .. } => Some(response.status_code),
+                    CoreEvent::Dialog(CoreDialogEvent::IncomingInvite {
+                        kind: InviteKind::Replacing(_),
+                        ..
+                    }) => Some(200),
+                    _ => None,
+                },
+            )
+        };
+
+        assert_eq!(replace("sip:mallory@203.0.113.9", "203.0.113.9:5060"), Some(403));
+        assert_eq!(replace("sip:bob@198.51.100.7", "198.51.100.7:5062"), Some(200));
+        assert_eq!(replace("sip:carol@198.51.100.1", "198.51.100.1:5060"), Some(200));
+    }
+}
@@ -1,13 +1,18 @@
-use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
-use crate::{header_value, Request, Response};
+use crate::transport::TransportAddr;
+use crate::{header_value, Header, Method, Request, Response, Result, SipError};
 
 // Timer values from RFC 3261 (assuming UDP/unreliable transport)
 const T1: Duration = Duration::from_millis(500);
 const T2: Duration = Duration::from_secs(4);
 const TIMER_H: Duration = Duration::from_millis(500 * 64); // 64 * T1
 const TIMER_I: Duration = Duration::from_secs(5); // Time to keep transaction after ACK
+const TIMER_B: Duration = Duration::from_millis(500 * 64); // 64 * T1
+const TIMER_C: Duration = Duration::from_secs(180); // > 3 minutes in RFC 3261 §16.6
+const TIMER_D: Duration = Duration::from_secs(32);
+const TIMER_M: Duration = Duration::from_millis(500 * 64); // 64 * T1, RFC 6026
+const PRACK_TIMEOUT: Duration = Duration::from_millis(500 * 64); // 64 * T1, RFC 3262 §3
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum InviteServerTxState {
@@ -16,21 +21,36 @@ enum InviteServerTxState {
     Confirmed,
 }
 
+/// A reliable provisional response (RFC 3262 §3), retransmitted on any
+/// transport until PRACKed.
+#[derive(Debug, Clone)]
+struct ReliableProvisional {
+    response: Response,
+    rseq: u32,
+    interval: Duration,
+    next: Instant,
+    deadline: Instant,
+}
+
 #[derive(Debug, Clone)]
 struct InviteServerTransaction {
     call_id: String,
     cseq: u32,
-    remote: SocketAddr,
+    remote: TransportAddr,
     last_response: Option<Response>,
     state: InviteServerTxState,
     timer_g_interval: Duration,
     next_timer_g: Option<Instant>,
     deadline_h: Option<Instant>,
     deadline_i: Option<Instant>,
+    reliable_provisional: Option<ReliableProvisional>,
+    /// RSeq of the last PRACKed provisional, so a retransmitted PRACK
+    /// still gets its 200.
+    pracked_rseq: Option<u32>,
 }
 
 impl InviteServerTransaction {
-    fn new(call_id: &str, cseq: u32, remote: SocketAddr) -> Self {
+    fn new(call_id: &str, cseq: u32, remote: TransportAddr) -> Self {
         Self {
             call_id: call_id.to_string(),
             cseq,
@@ -41,6 +61,8 @@ impl InviteServerTransaction {
             next_timer_g: None,
             deadline_h: None,
             deadline_i: None,
+            reliable_provisional: None,
+            pracked_rseq: None,
         }
     }
 
@@ -52,15 +74,34 @@ impl InviteServerTransaction {
         let status = resp.status_code;
         self.last_response = Some(resp.clone());
 
-        // Provisional response -> stay in Proceeding, no timers.
+        // Provisional response -> stay in Proceeding; only a reliable one
+        // is retransmitted.
         if status < 200 {
+            let rseq = header_value(&resp.headers, "RSeq").and_then(|v| v.trim().parse().ok());
+            if let Some(rseq) = rseq {
+                self.reliable_provisional = Some(ReliableProvisional {
+                    response: resp.clone(),
+                    rseq,
+                    interval: T1,
+                    next: now + T1,
+                    deadline: now + PRACK_TIMEOUT,
+                });
+            }
             return;
         }
 
-        // Final response -> start retransmission timers
+        // A final response ends any wait for PRACK.
+        self.reliable_provisional = None;
+
+        // Final response -> start retransmission timers. Timer G only
+        // applies to unreliable transports (RFC 3261 §17.2.1).
         self.state = InviteServerTxState::Completed;
         self.timer_g_interval = T1;
-        self.next_timer_g = Some(now + self.timer_g_interval);
+        self.next_timer_g = if self.remote.transport.is_reliable() {
+            None
+        } else {
+            Some(now + self.timer_g_interval)
+        };
         self.deadline_h = Some(now + TIMER_H);
         self.deadline_i = None;
     }
@@ -69,7 +110,30 @@ impl InviteServerTransaction {
         // ACK stops retransmissions; keep transaction briefly (Timer I)
         self.state = InviteServerTxState::Confirmed;
         self.next_timer_g = None;
-        self.deadline_i = Some(now + TIMER_I);
+        self.deadline_i = if self.remote.transport.is_reliable() {
+            Some(now)
+        } else {
+            Some(now + TIMER_I)
+        };
+    }
+
+    fn on_prack(&mut self, rseq: u32) -> bool {
+        if self.reliable_provisional.as_ref().is_some_and(|p| p.rseq == rseq) {
+            self.reliable_provisional = None;
+            self.pracked_rseq = Some(rseq);
+        }
+        self.pracked_rseq == Some(rseq)
+    }
+
+    /// Reliable provisional retransmissions double without the T2 cap.
+    fn maybe_retransmit_provisional(&mut self, now: Instant) -> Option<Response> {
+        let provisional = self.reliable_provisional.as_mut()?;
+        if now >= provisional.deadline || now < provisional.next {
+            return None;
+        }
+        provisional.interval *= 2;
+        provisional.next = now + provisional.interval;
+        Some(provisional.response.clone())
     }
 
     fn maybe_retransmit(&mut self, now: Instant) -> Option<Response> {
@@ -134,12 +198,23 @@ impl InviteServerTransactionManager {
         }
     }
 
+    /// Whether `req` repeats an INVITE we already have a transaction for.
+    pub fn is_retransmission(&self, req: &Request) -> bool {
+        let Some(call_id) = header_value(&req.headers, "Call-ID") else {
+            return false;
+        };
+        let Some(cseq) = header_value(&req.headers, "CSeq").and_then(parse_cseq_number) else {
+            return false;
+        };
+        self.transactions.iter().any(|t| t.matches(call_id, cseq))
+    }
+
     /// Handle an incoming INVITE request. If it is a retransmission,
     /// return the last response to be resent.
     pub fn on_invite(
         &mut self,
         req: &Request,
-        remote: SocketAddr,
+        remote: TransportAddr,
     ) -> Option<Response> {
         let call_id = header_value(&req.headers, "Call-ID")?;
         let cseq = parse_cseq_number(header_value(&req.headers, "CSeq")?)?;
@@ -163,7 +238,7 @@ impl InviteServerTransactionManager {
     pub fn on_outgoing_response(
         &mut self,
         resp: &Response,
-        remote: SocketAddr,
+        remote: TransportAddr,
         now: Instant,
     ) {
         // Only track responses to INVITE
@@ -188,7 +263,10 @@ impl InviteServerTransactionManager {
             .find(|t| t.matches(call_id, cseq_num));
 
         match tx {
-            Some(t) => t.update_with_response(resp, now),
+            Some(t) => {
+                t.remote = remote;
+                t.update_with_response(resp, now);
+            }
             None => {
                 // If we somehow send a response without seeing the INVITE first,
                 // start tracking now.
@@ -199,6 +277,45 @@ impl InviteServerTransactionManager {
         }
     }
 
+    /// Match a PRACK to the reliable provisional it acknowledges by its
+    /// RAck header. Returns whether it matched one (retransmitted PRACKs
+    /// included), which stops that response's retransmissions.
+    pub fn on_prack(&mut self, prack: &Request) -> bool {
+        let Some(call_id) = header_value(&prack.headers, "Call-ID") else {
+            return false;
+        };
+        let Some(rack) = header_value(&prack.headers, "RAck") else {
+            return false;
+        };
+        let mut parts = rack.split_whitespace();
+        let rseq = parts.next().and_then(|n| n.parse::<u32>().ok());
+        let cseq = parts.next().and_then(|n| n.parse::<u32>().ok());
+        let (Some(rseq), Some(cseq)) = (rseq, cseq) else {
+            return false;
+        };
+        if !parts.next().is_some_and(|m| m.eq_ignore_ascii_case("INVITE")) {
+            return false;
+        }
+
+        self.transactions
+            .iter_mut()
+            .find(|t| t.matches(call_id, cseq))
+            .is_some_and(|t| t.on_prack(rseq))
+    }
+
+    /// Reliable provisional responses nobody PRACKed within 64*T1, as
+    /// (Call-ID, CSeq, target). The INVITE should be rejected with a 5xx.
+    pub fn expired_provisionals(&mut self, now: Instant) -> Vec<(String, u32, TransportAddr)> {
+        let mut out = Vec::new();
+        for tx in &mut self.transactions {
+            if tx.reliable_provisional.as_ref().is_some_and(|p| now >= p.deadline) {
+                tx.reliable_provisional = None;
+                out.push((tx.call_id.clone(), tx.cseq, tx.remote));
+            }
+        }
+        out
+    }
+
     pub fn on_ack(&mut self, ack: &Request, now: Instant) {
         let call_id = match header_value(&ack.headers, "Call-ID") {
             Some(v) => v,
@@ -221,11 +338,14 @@ impl InviteServerTransactionManager {
     }
 
     /// Advance timers and produce any retransmissions that should be sent now.
-    pub fn poll(&mut self, now: Instant) -> Vec<(Response, SocketAddr)> {
+    pub fn poll(&mut self, now: Instant) -> Vec<(Response, TransportAddr)> {
         let mut out = Vec::new();
 
         for tx in &mut self.transactions {
-            if let Some(resp) = tx.maybe_retransmit(now) {
+            let retransmit = tx
+                .maybe_retransmit_provisional(now)
+                .or_else(|| tx.maybe_retransmit(now));
+            if let Some(resp) = retransmit {
                 out.push((resp, tx.remote));
             }
         }
@@ -237,6 +357,327 @@ impl InviteServerTransactionManager {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InviteClientTxState {
+    Calling,
+    Proceeding,
+    /// A 2xx arrived; further 2xx from other forks are still passed up
+    /// until Timer M fires (RFC 6026 §7.2).
+    Accepted,
+    /// A non-2xx final arrived and was ACKed; retransmissions of it are
+    /// re-ACKed until Timer D fires.
+    Completed,
+}
+
+/// CANCEL of an INVITE client transaction (RFC 3261 §9.1).
+#[derive(Debug, Clone)]
+enum CancelState {
+    Idle,
+    /// Asked for before any provisional response; a CANCEL may only be
+    /// sent once one arrived.
+    Deferred(Request),
+    /// Sent. Over UDP it is retransmitted like any non-INVITE request
+    /// (Timer E) until it or the INVITE gets a final response.
+    Sent {
+        cancel: Request,
+        interval: Duration,
+        next: Option<Instant>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct InviteClientTransaction {
+    request: Request,
+    call_id: String,
+    cseq: u32,
+    reliable: bool,
+    state: InviteClientTxState,
+    timer_a_interval: Duration,
+    next_timer_a: Option<Instant>,
+    deadline_b: Option<Instant>,
+    deadline_c: Option<Instant>,
+    deadline_end: Option<Instant>,
+    ack: Option<Request>,
+    cancel: CancelState,
+}
+
+impl InviteClientTransaction {
+    fn new(request: &Request, call_id: &str, cseq: u32, reliable: bool, now: Instant) -> Self {
+        Self {
+            request: request.clone(),
+            call_id: call_id.to_string(),
+            cseq,
+            reliable,
+            state: InviteClientTxState::Calling,
+            timer_a_interval: T1,
+            // Timer A only applies to unreliable transports (RFC 3261 §17.1.1.2).
+            next_timer_a: if reliable { None } else { Some(now + T1) },
+            deadline_b: Some(now + TIMER_B),
+            deadline_c: Some(now + TIMER_C),
+            deadline_end: None,
+            ack: None,
+            cancel: CancelState::Idle,
+        }
+    }
+
+    fn matches(&self, call_id: &str, cseq: u32) -> bool {
+        self.call_id == call_id && self.cseq == cseq
+    }
+
+    fn on_response(&mut self, resp: &Response, now: Instant) -> InviteResponseAction {
+        let status = resp.status_code;
+        match self.state {
+            InviteClientTxState::Calling | InviteClientTxState::Proceeding => {
+                self.next_timer_a = None;
+                // Once a CANCEL is out, Timer B bounds the wait for the
+                // INVITE's final response again (RFC 3261 §9.1).
+                if !matches!(self.cancel, CancelState::Sent { .. }) {
+                    self.deadline_b = None;
+                }
+                if status < 200 {
+                    self.state = InviteClientTxState::Proceeding;
+                    // Every provisional restarts Timer C.
+                    self.deadline_c = Some(now + TIMER_C);
+                    if let CancelState::Deferred(cancel) = &self.cancel {
+                        // Now it may go; poll sends it.
+                        self.cancel = CancelState::Sent {
+                            cancel: cancel.clone(),
+                            interval: T1,
+                            next: Some(now),
+                        };
+                        self.deadline_b = Some(now + TIMER_B);
+                    }
+                    return InviteResponseAction::Deliver;
+                }
+                self.stop_cancel();
+                if status < 300 {
+                    self.state = InviteClientTxState::Accepted;
+                    self.deadline_c = None;
+                    self.deadline_end = Some(now + TIMER_M);
+                } else {
+                    self.state = InviteClientTxState::Completed;
+                    self.deadline_c = None;
+                    self.ack = ack_for_non_2xx(&self.request, resp).ok();
+                    self.deadline_end = Some(if self.reliable { now } else { now + TIMER_D });
+                }
+                InviteResponseAction::Deliver
+            }
+            InviteClientTxState::Accepted => InviteResponseAction::Deliver,
+            InviteClientTxState::Completed if status >= 300 => match &self.ack {
+                Some(ack) => InviteResponseAction::Reack(ack.clone()),
+                None => InviteResponseAction::Deliver,
+            },
+            InviteClientTxState::Completed => InviteResponseAction::Deliver,
+        }
+    }
+
+    fn poll(&mut self, now: Instant, out: &mut Vec<InviteClientEvent>) {
+        let timed_out = self.deadline_b.is_some_and(|b| now >= b)
+            || self.deadline_c.is_some_and(|c| now >= c);
+        if timed_out {
+            self.next_timer_a = None;
+            self.deadline_b = None;
+            self.deadline_c = None;
+            self.deadline_end = Some(now);
+            out.push(InviteClientEvent::TimedOut {
+                call_id: self.call_id.clone(),
+                cseq: self.cseq,
+            });
+            return;
+        }
+
+        if self.next_timer_a.is_some_and(|a| now >= a) {
+            self.timer_a_interval *= 2;
+            self.next_timer_a = Some(now + self.timer_a_interval);
+            out.push(InviteClientEvent::Send(self.request.clone()));
+        }
+
+        if let CancelState::Sent { cancel, interval, next } = &mut self.cancel {
+            if next.is_some_and(|n| now >= n) {
+                *next = (!self.reliable).then(|| now + *interval);
+                *interval = (*interval * 2).min(T2);
+                out.push(InviteClientEvent::Send(cancel.clone()));
+            }
+        }
+    }
+
+    /// CANCEL this INVITE. Returns the CANCEL if it can go now, `None` if
+    /// it has to wait for a provisional response.
+    fn cancel(&mut self, now: Instant) -> Result<Option<Request>> {
+        if !matches!(self.state, InviteClientTxState::Calling | InviteClientTxState::Proceeding) {
+            return Err(SipError::InvalidState("INVITE already has a final response"));
+        }
+        if !matches!(self.cancel, CancelState::Idle) {
+            return Ok(None);
+        }
+        let cancel = cancel_for(&self.request)?;
+        if self.state == InviteClientTxState::Calling {
+            self.cancel = CancelState::Deferred(cancel);
+            return Ok(None);
+        }
+        self.cancel = CancelState::Sent {
+            cancel: cancel.clone(),
+            interval: T1 * 2,
+            next: (!self.reliable).then(|| now + T1),
+        };
+        self.deadline_b = Some(now + TIMER_B);
+        Ok(Some(cancel))
+    }
+
+    fn stop_cancel(&mut self) {
+        if let CancelState::Sent { next, .. } = &mut self.cancel {
+            *next = None;
+        }
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        self.deadline_end.is_some_and(|end| now >= end)
+    }
+}
+
+/// What to do with a response to an INVITE we sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InviteResponseAction {
+    /// Pass it to the dialog layer.
+    Deliver,
+    /// A retransmitted non-2xx final: send this ACK again, nothing else.
+    Reack(Request),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InviteClientEvent {
+    /// Retransmit the INVITE (Timer A), or send or retransmit its CANCEL.
+    Send(Request),
+    /// No response (Timer B), no final response after the last
+    /// provisional (Timer C) or after a CANCEL: the INVITE failed.
+    TimedOut { call_id: String, cseq: u32 },
+}
+
+/// INVITE client transactions (RFC 3261 §17.1.1, as updated by RFC 6026).
+#[derive(Debug, Default)]
+pub struct InviteClientTransactionManager {
+    transactions: Vec<InviteClientTransaction>,
+}
+
+impl InviteClientTransactionManager {
+    /// Start a transaction for an INVITE we are about to send.
+    pub fn on_invite_sent(&mut self, req: &Request, reliable: bool, now: Instant) {
+        let Some(call_id) = header_value(&req.headers, "Call-ID") else {
+            return;
+        };
+        let Some(cseq) = header_value(&req.headers, "CSeq").and_then(parse_cseq_number) else {
+            return;
+        };
+        self.transactions.retain(|t| !t.matches(call_id, cseq));
+        self.transactions
+            .push(InviteClientTransaction::new(req, call_id, cseq, reliable, now));
+    }
+
+    /// Responses that match no transaction (e.g. a 2xx retransmitted after
+    /// Timer M) are delivered too; the dialog re-ACKs or ignores them.
+    pub fn on_response(&mut self, resp: &Response, now: Instant) -> InviteResponseAction {
+        let call_id = header_value(&resp.headers, "Call-ID").unwrap_or("");
+        let cseq = header_value(&resp.headers, "CSeq").and_then(parse_cseq_number);
+        match self
+            .transactions
+            .iter_mut()
+            .find(|t| Some(t.cseq) == cseq && t.call_id == call_id)
+        {
+            Some(tx) => tx.on_response(resp, now),
+            None => InviteResponseAction::Deliver,
+        }
+    }
+
+    /// CANCEL our INVITE `call_id`/`cseq` (RFC 3261 §9.1). Returns the
+    /// CANCEL to send now, or `None` when it waits for a provisional
+    /// response and `poll` sends it then. The INVITE's final response,
+    /// normally 487, still arrives through `on_response`.
+    pub fn cancel(&mut self, call_id: &str, cseq: u32, now: Instant) -> Result<Option<Request>> {
+        self.transactions
+            .iter_mut()
+            .find(|t| t.matches(call_id, cseq))
+            .ok_or(SipError::InvalidState("no such INVITE transaction"))?
+            .cancel(now)
+    }
+
+    /// A response to one of our CANCELs: stop retransmitting it.
+    pub fn on_cancel_response(&mut self, resp: &Response) {
+        let call_id = header_value(&resp.headers, "Call-ID").unwrap_or("");
+        let cseq = header_value(&resp.headers, "CSeq").and_then(parse_cseq_number);
+        if let Some(tx) = self
+            .transactions
+            .iter_mut()
+            .find(|t| Some(t.cseq) == cseq && t.call_id == call_id)
+        {
+            tx.stop_cancel();
+        }
+    }
+
+    /// Advance timers: INVITE and CANCEL retransmissions and timeouts.
+    pub fn poll(&mut self, now: Instant) -> Vec<InviteClientEvent> {
+        let mut out = Vec::new();
+        for tx in &mut self.transactions {
+            tx.poll(now, &mut out);
+        }
+        self.transactions.retain(|tx| !tx.expired(now));
+        out
+    }
+}
+
+/// ACK for a non-2xx final response to our INVITE (RFC 3261 §17.1.1.3).
+///
+/// This ACK belongs to the INVITE client transaction: it reuses the
+/// INVITE's Request-URI, top Via (same branch), Route set, From, Call-ID
+/// and CSeq number, and takes To from the response.
+pub fn ack_for_non_2xx(invite: &Request, resp: &Response) -> Result<Request> {
+    let mut ack = Request::new(Method::Ack, &invite.uri)?;
+
+    let via = header_value(&invite.headers, "Via").ok_or(SipError::Invalid("missing Via"))?;
+    ack.add_header(Header::new("Via", via)?)?;
+    ack.add_header(Header::new("Max-Forwards", "70")?)?;
+    for route in invite.headers.iter().filter(|h| h.name.eq_ignore_ascii_case("Route")) {
+        ack.add_header(route.clone())?;
+    }
+    for name in ["From", "Call-ID"] {
+        let value = header_value(&invite.headers, name).ok_or(SipError::Invalid("missing header"))?;
+        ack.add_header(Header::new(name, value)?)?;
+    }
+    let to = header_value(&resp.headers, "To").ok_or(SipError::Invalid("missing To"))?;
+    ack.add_header(Header::new("To", to)?)?;
+
+    let cseq = header_value(&invite.headers, "CSeq")
+        .and_then(parse_cseq_number)
+        .ok_or(SipError::Invalid("missing CSeq"))?;
+    ack.add_header(Header::new("CSeq", &format!("{} ACK", cseq))?)?;
+    ack.add_header(Header::new("Content-Length", "0")?)?;
+    Ok(ack)
+}
+
+/// CANCEL for our INVITE (RFC 3261 §9.1): the INVITE's Request-URI, top
+/// Via (same branch, so it matches the same server transaction), Route
+/// set, From, To, Call-ID and CSeq number.
+pub fn cancel_for(invite: &Request) -> Result<Request> {
+    let mut cancel = Request::new(Method::Cancel, &invite.uri)?;
+
+    let via = header_value(&invite.headers, "Via").ok_or(SipError::Invalid("missing Via"))?;
+    cancel.add_header(Header::new("Via", via)?)?;
+    cancel.add_header(Header::new("Max-Forwards", "70")?)?;
+    for route in invite.headers.iter().filter(|h| h.name.eq_ignore_ascii_case("Route")) {
+        cancel.add_header(route.clone())?;
+    }
+    for name in ["From", "To", "Call-ID"] {
+        let value = header_value(&invite.headers, name).ok_or(SipError::Invalid("missing header"))?;
+        cancel.add_header(Header::new(name, value)?)?;
+    }
+
+    let cseq = header_value(&invite.headers, "CSeq")
+        .and_then(parse_cseq_number)
+        .ok_or(SipError::Invalid("missing CSeq"))?;
+    cancel.add_header(Header::new("CSeq", &format!("{} CANCEL", cseq))?)?;
+    cancel.add_header(Header::new("Content-Length", "0")?)?;
+    Ok(cancel)
+}
+
 fn parse_cseq_number(cseq: &str) -> Option<u32> {
     cseq.split_whitespace()
         .next()
@@ -251,6 +692,7 @@ fn parse_cseq_method(cseq: &str) -> Option<&str> {
 mod tests {
     use super::*;
     use crate::{Header, Method};
+    use std::net::SocketAddr;
     use std::str::FromStr;
 
     fn sample_invite() -> Request {
@@ -290,7 +732,7 @@ mod tests {
     fn retransmits_final_response_until_ack() {
         let mut mgr = InviteServerTransactionManager::new();
         let base = Instant::now();
-        let remote = SocketAddr::from_str("192.0.2.10:5060").unwrap();
+        let remote = TransportAddr::udp(SocketAddr::from_str("192.0.2.10:5060").unwrap());
         let invite = sample_invite();
 
         // First INVITE starts transaction
@@ -313,10 +755,150 @@ mod tests {
         assert!(mgr.poll(base + Duration::from_secs(2)).is_empty());
     }
 
+    #[test]
+    fn client_retransmits_invite_until_provisional_then_times_out_on_c() {
+        let mut mgr = InviteClientTransactionManager::default();
+        let base = Instant::now();
+        mgr.on_invite_sent(&sample_invite(), false, base);
+
+        assert_eq!(mgr.poll(base + T1), vec![InviteClientEvent::Send(sample_invite())]);
+        assert!(mgr.poll(base + T1 + T1).is_empty());
+
+        let ringing = base + Duration::from_secs(1);
+        assert_eq!(mgr.on_response(&sample_response(180), ringing), InviteResponseAction::Deliver);
+        assert!(mgr.poll(base + TIMER_B).is_empty(), "provisional stops Timer A and B");
+
+        assert_eq!(
+            mgr.poll(ringing + TIMER_C),
+            vec![InviteClientEvent::TimedOut { call_id: "call123".into(), cseq: 1 }]
+        );
+    }
+
+    #[test]
+    fn cancel_waits_for_a_provisional_and_retransmits_until_answered() {
+        let mut mgr = InviteClientTransactionManager::default();
+        let base = Instant::now();
+        mgr.on_invite_sent(&sample_invite(), false, base);
+
+        assert!(
+            matches!(mgr.cancel("call123", 1, base), Ok(None)),
+            "no CANCEL before a provisional"
+        );
+        mgr.on_response(&sample_response(180), base + T1);
+        let sent = mgr.poll(base + T1);
+        let Some(InviteClientEvent::Send(cancel)) = sent.last() else {
+            panic!("expected the CANCEL, got {:?}", sent);
+        };
+        assert_eq!(cancel.method, Method::Cancel);
+        assert_eq!(cancel.uri, "sip:alice@example.com");
+        assert_eq!(
+            header_value(&cancel.headers, "Via"),
+            Some("SIP/2.0/UDP 192.0.2.10:5060;branch=z9hG4bK1")
+        );
+        assert_eq!(header_value(&cancel.headers, "To"), Some("<sip:alice@example.com>"));
+        assert_eq!(header_value(&cancel.headers, "CSeq"), Some("1 CANCEL"));
+        assert_eq!(mgr.poll(base + T1 * 2), vec![InviteClientEvent::Send(cancel.clone())]);
+
+        let mut cancel_ok = sample_response(200);
+        cancel_ok.headers.retain(|h| h.name != "CSeq");
+        cancel_ok.add_header(Header::new("CSeq", "1 CANCEL").unwrap());
+        mgr.on_cancel_response(&cancel_ok);
+        assert!(mgr.poll(base + T1 * 8).is_empty());
+
+        assert_eq!(mgr.on_response(&sample_response(487), base + T1 * 8), InviteResponseAction::Deliver);
+        assert!(mgr.cancel("call123", 1, base + T1 * 8).is_err());
+    }
+
+    #[test]
+    fn cancelled_invite_without_final_response_times_out() {
+        let mut mgr = InviteClientTransactionManager::default();
+        let base = Instant::now();
+        mgr.on_invite_sent(&sample_invite(), true, base);
+        mgr.on_response(&sample_response(180), base);
+
+        let cancel = mgr.cancel("call123", 1, base).unwrap().expect("sent at once");
+        assert_eq!(cancel.method, Method::Cancel);
+        assert!(mgr.poll(base + T1).is_empty(), "no retransmissions over TCP");
+        mgr.on_response(&sample_response(180), base + T1);
+        assert_eq!(
+            mgr.poll(base + TIMER_B),
+            vec![InviteClientEvent::TimedOut { call_id: "call123".into(), cseq: 1 }]
+        );
+    }
+
+    #[test]
+    fn client_delivers_forked_2xx_and_reacks_retransmitted_errors() {
+        let mut mgr = InviteClientTransactionManager::default();
+        let base = Instant::now();
+        mgr.on_invite_sent(&sample_invite(), false, base);
+
+        let ok = sample_response(200);
+        let mut forked_ok = sample_response(200);
+        forked_ok.headers.retain(|h| h.name != "To");
+        forked_ok.add_header(Header::new("To", "<sip:alice@example.com>;tag=to2").unwrap());
+        assert_eq!(mgr.on_response(&ok, base), InviteResponseAction::Deliver);
+        assert_eq!(mgr.on_response(&forked_ok, base), InviteResponseAction::Deliver);
+
+        let mut second = sample_invite();
+        second.headers.retain(|h| h.name != "CSeq");
+        second.add_header(Header::new("CSeq", "2 INVITE").unwrap()).unwrap();
+        mgr.on_invite_sent(&second, false, base);
+        let mut busy = sample_response(486);
+        busy.headers.retain(|h| h.name != "CSeq");
+        busy.add_header(Header::new("CSeq", "2 INVITE").unwrap());
+
+        assert_eq!(mgr.on_response(&busy, base), InviteResponseAction::Deliver);
+        match mgr.on_response(&busy, base + T1) {
+            InviteResponseAction::Reack(ack) => {
+                assert_eq!(ack.method, Method::Ack);
+                assert_eq!(header_value(&ack.headers, "CSeq"), Some("2 ACK"));
+            }
+            other => panic!("expected re-ACK, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reliable_provisional_retransmits_until_pracked() {
+        let mut mgr = InviteServerTransactionManager::default();
+        let base = Instant::now();
+        let remote = TransportAddr::udp(SocketAddr::from_str("192.0.2.10:5060").unwrap());
+        assert!(mgr.on_invite(&sample_invite(), remote).is_none());
+
+        let mut progress = sample_response(183);
+        progress.add_header(Header::new("Require", "100rel").unwrap());
+        progress.add_header(Header::new("RSeq", "1").unwrap());
+        mgr.on_outgoing_response(&progress, remote, base);
+
+        assert_eq!(mgr.poll(base + T1), vec![(progress.clone(), remote)]);
+        assert!(mgr.poll(base + T1 + T1).is_empty(), "interval doubles");
+
+        let mut prack = Request::new(Method::Prack, "sip:alice@example.com").unwrap();
+        prack.add_header(Header::new("Call-ID", "call123").unwrap()).unwrap();
+        prack.add_header(Header::new("RAck", "1 1 INVITE").unwrap()).unwrap();
+        assert!(mgr.on_prack(&prack));
+        assert!(mgr.on_prack(&prack), "retransmitted PRACK still matches");
+        assert!(mgr.poll(base + Duration::from_secs(10)).is_empty());
+        assert!(mgr.expired_provisionals(base + PRACK_TIMEOUT).is_empty());
+
+        prack.headers.retain(|h| h.name != "RAck");
+        prack.add_header(Header::new("RAck", "2 1 INVITE").unwrap()).unwrap();
+        assert!(!mgr.on_prack(&prack));
+
+        // Another one nobody PRACKs.
+        let mut second = progress.clone();
+        second.headers.retain(|h| h.name != "RSeq");
+        second.add_header(Header::new("RSeq", "2").unwrap());
+        mgr.on_outgoing_response(&second, remote, base);
+        assert_eq!(
+            mgr.expired_provisionals(base + PRACK_TIMEOUT),
+            vec![("call123".to_string(), 1, remote)]
+        );
+    }
+
     #[test]
     fn responds_to_retransmitted_invite_with_last_response() {
         let mut mgr = InviteServerTransactionManager::new();
-        let remote = SocketAddr::from_str("192.0.2.10:5060").unwrap();
+        let remote = TransportAddr::udp(SocketAddr::from_str("192.0.2.10:5060").unwrap());
         let invite = sample_invite();
         assert!(mgr.on_invite(&invite, remote).is_none());
 
@@ -0,0 +1,313 @@
+//! Sans-IO transport layer: transport kinds, the `Transport` trait the
+//! application implements over real sockets, and stream framing for
+//! connection-oriented transports.
+
+use std::net::SocketAddr;
+
+use crate::message::{header_value, Header, Request};
+use crate::uri::SipUri;
+use crate::{Result, SipError};
+
+/// Requests larger than this go over a congestion-controlled transport
+/// when the path MTU is unknown (RFC 3261 §18.1.1).
+pub const UDP_MAX_MESSAGE: usize = 1300;
+
+/// Upper bound for a single framed message, so a peer can't make us buffer
+/// without limit.
+const MAX_STREAM_MESSAGE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TransportKind {
+    #[default]
+    Udp,
+    Tcp,
+    Tls,
+}
+
+impl TransportKind {
+    /// Token used in the Via sent-protocol (`SIP/2.0/UDP`).
+    pub fn via_token(self) -> &'static str {
+        match self {
+            TransportKind::Udp => "UDP",
+            TransportKind::Tcp => "TCP",
+            TransportKind::Tls => "TLS",
+        }
+    }
+
+    /// Value for the `transport=` URI parameter, if one is needed.
+    pub fn uri_param(self) -> Option<&'static str> {
+        match self {
+            TransportKind::Udp => None,
+            TransportKind::Tcp => Some("tcp"),
+            TransportKind::Tls => Some("tls"),
+        }
+    }
+
+    pub fn is_reliable(self) -> bool {
+        !matches!(self, TransportKind::Udp)
+    }
+
+    pub fn is_secure(self) -> bool {
+        matches!(self, TransportKind::Tls)
+    }
+
+    /// Port to use when a URI doesn't give one (RFC 3263 §4.2).
+    pub fn default_port(self) -> u16 {
+        if self.is_secure() {
+            5061
+        } else {
+            5060
+        }
+    }
+
+    /// Transport implied by a URI: `sips:` always means TLS, otherwise the
+    /// `transport=` parameter if it names one we support.
+    pub fn for_uri(uri: &SipUri) -> Option<Self> {
+        if uri.is_sips() {
+            return Some(TransportKind::Tls);
+        }
+        uri.param("transport").and_then(Self::parse)
+    }
+
+    /// Parse a Via token or `transport=` parameter value.
+    pub fn parse(input: &str) -> Option<Self> {
+        if input.eq_ignore_ascii_case("udp") {
+            Some(TransportKind::Udp)
+        } else if input.eq_ignore_ascii_case("tcp") {
+            Some(TransportKind::Tcp)
+        } else if input.eq_ignore_ascii_case("tls") {
+            Some(TransportKind::Tls)
+        } else {
+            None
+        }
+    }
+}
+
+impl core::fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.via_token())
+    }
+}
+
+/// A transport-level peer: where a message came from or where it goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransportAddr {
+    pub transport: TransportKind,
+    pub addr: SocketAddr,
+}
+
+impl TransportAddr {
+    pub fn new(transport: TransportKind, addr: SocketAddr) -> Self {
+        Self { transport, addr }
+    }
+
+    pub fn udp(addr: SocketAddr) -> Self {
+        Self::new(TransportKind::Udp, addr)
+    }
+}
+
+impl core::fmt::Display for TransportAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.transport)
+    }
+}
+
+/// A socket-owning transport. The stack never calls this itself; the
+/// application drives it and hands whole messages to `SipStack::on_message`.
+pub trait Transport {
+    fn kind(&self) -> TransportKind;
+
+    /// Send one complete message. Connection-oriented transports reuse an
+    /// open connection to `target` or open a new one.
+    fn send(&mut self, target: SocketAddr, payload: &[u8]) -> std::io::Result<()>;
+
+    /// Send a request to `target`, an address of `host`. A TLS transport
+    /// that has to connect checks the server certificate against `host`;
+    /// other transports ignore it.
+    fn send_to_host(
+        &mut self,
+        target: SocketAddr,
+        _host: &str,
+        payload: &[u8],
+    ) -> std::io::Result<()> {
+        self.send(target, payload)
+    }
+
+    /// Return the next complete message, if one is ready. Never blocks.
+    fn poll_recv(&mut self) -> Option<(Vec<u8>, SocketAddr)>;
+
+    /// Peers whose connection was lost since the last call. Lets the
+    /// application re-register so the registrar can reach us again.
+    fn take_closed(&mut self) -> Vec<SocketAddr> {
+        Vec::new()
+    }
+}
+
+/// Splits a byte stream into SIP messages using Content-Length
+/// (RFC 3261 §18.3). CRLF keep-alives between messages are skipped.
+#[derive(Debug, Default)]
+pub struct StreamFramer {
+    buf: Vec<u8>,
+}
+
+impl StreamFramer {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Pop the next complete message. `Ok(None)` means more data is needed;
+    /// an error means the stream is unusable and should be closed.
+    pub fn next_message(&mut self) -> Result<Option<Vec<u8>>> {
+        let skip = self
+            .buf
+            .iter()
+            .take_while(|b| **b == b'\r' || **b == b'\n')
+            .count();
+        self.buf.drain(..skip);
+
+        let Some(header_end) = find(&self.buf, b"\r\n\r\n") else {
+            if self.buf.len() > MAX_STREAM_MESSAGE {
+                return Err(SipError::Invalid("stream header too long"));
+            }
+            return Ok(None);
+        };
+        let body_start = header_end + 4;
+
+        let head = core::str::from_utf8(&self.buf[..header_end])
+            .map_err(|_| SipError::Invalid("stream header not UTF-8"))?;
+        let content_length = content_length(head)?;
+
+        let total = body_start + content_length;
+        if total > MAX_STREAM_MESSAGE {
+            return Err(SipError::Invalid("stream message too long"));
+        }
+        if self.buf.len() < total {
+            return Ok(None);
+        }
+
+        Ok(Some(self.buf.drain(..total).collect()))
+    }
+}
+
+/// Choose the transport for an outgoing request and make its top Via
+/// match. A request that would be too large for UDP switches to TCP
+/// (RFC 3261 §18.1.1).
+pub fn select_transport(req: &mut Request, preferred: TransportKind) -> Result<TransportKind> {
+    let transport = if preferred == TransportKind::Udp && req.render()?.len() > UDP_MAX_MESSAGE {
+        TransportKind::Tcp
+    } else {
+        preferred
+    };
+
+    if let Some(via) = req.headers.iter_mut().find(|h| h.name.eq_ignore_ascii_case("Via")) {
+        *via = Header::new("Via", &set_via_transport(&via.value, transport))?;
+    }
+
+    Ok(transport)
+}
+
+/// Transport and port to use for a next-hop URI. `preferred` applies when
+/// the URI doesn't force one; a `sips:` URI is never downgraded.
+pub fn hop_transport(hop: &SipUri, preferred: TransportKind) -> (TransportKind, u16) {
+    let transport = TransportKind::for_uri(hop).unwrap_or(preferred);
+    (transport, hop.port.unwrap_or(transport.default_port()))
+}
+
+/// Transport named in a Via sent-protocol, e.g. `SIP/2.0/TCP host`.
+pub fn via_transport(via: &str) -> Option<TransportKind> {
+    let protocol = via.split_whitespace().next()?;
+    TransportKind::parse(protocol.rsplit('/').next()?)
+}
+
+fn set_via_transport(via: &str, transport: TransportKind) -> String {
+    match via.split_once(char::is_whitespace) {
+        Some((_, rest)) => format!("SIP/2.0/{} {}", transport.via_token(), rest.trim_start()),
+        None => via.to_string(),
+    }
+}
+
+fn content_length(head: &str) -> Result<usize> {
+    let headers = head
+        .split("\r\n")
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| Header {
+            name: name.trim().to_string(),
+            value: value.trim().to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    header_value(&headers, "Content-Length")
+        .or_else(|| header_value(&headers, "l"))
+        .ok_or(SipError::Invalid("missing Content-Length on stream transport"))?
+        .parse()
+        .map_err(|_| SipError::Invalid("Content-Length"))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Method;
+
+    #[test]
+    fn frames_messages_split_across_reads() {
+        let mut framer = StreamFramer::new();
+        framer.push(b"\r\n\r\nOPTIONS sip:a SIP/2.0\r\nContent-Length: 4\r\n\r\nab");
+        assert!(framer.next_message().unwrap().is_none());
+
+        framer.push(b"cdBYE sip:a SIP/2.0\r\nl: 0\r\n\r\n");
+        let first = framer.next_message().unwrap().unwrap();
+        assert!(first.starts_with(b"OPTIONS") && first.ends_with(b"abcd"));
+        let second = framer.next_message().unwrap().unwrap();
+        assert!(second.starts_with(b"BYE"));
+        assert!(framer.next_message().unwrap().is_none());
+        assert_eq!(framer.buffered(), 0);
+    }
+
+    #[test]
+    fn rejects_stream_message_without_content_length() {
+        let mut framer = StreamFramer::new();
+        framer.push(b"OPTIONS sip:a SIP/2.0\r\nVia: SIP/2.0/TCP h\r\n\r\n");
+        assert!(framer.next_message().is_err());
+    }
+
+    #[test]
+    fn sips_uris_use_tls_and_port_5061() {
+        let hop = SipUri::parse("sips:proxy.example.com").unwrap();
+        assert_eq!(hop_transport(&hop, TransportKind::Udp), (TransportKind::Tls, 5061));
+
+        let hop = SipUri::parse("sip:proxy.example.com;transport=tls").unwrap();
+        assert_eq!(hop_transport(&hop, TransportKind::Udp), (TransportKind::Tls, 5061));
+
+        let hop = SipUri::parse("sip:proxy.example.com:5080").unwrap();
+        assert_eq!(hop_transport(&hop, TransportKind::Tcp), (TransportKind::Tcp, 5080));
+        assert_eq!(via_transport("SIP/2.0/TLS 192.0.2.1:5061"), Some(TransportKind::Tls));
+    }
+
+    #[test]
+    fn large_requests_switch_to_tcp() {
+        let mut req = Request::new(Method::Invite, "sip:bob@example.com").unwrap();
+        req.add_header(Header::new("Via", "SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK1").unwrap())
+            .unwrap();
+        assert_eq!(select_transport(&mut req, TransportKind::Udp).unwrap(), TransportKind::Udp);
+
+        req.set_body(&"a".repeat(UDP_MAX_MESSAGE)).unwrap();
+        assert_eq!(select_transport(&mut req, TransportKind::Udp).unwrap(), TransportKind::Tcp);
+        assert_eq!(
+            header_value(&req.headers, "Via"),
+            Some("SIP/2.0/TCP 192.0.2.1:5060;branch=z9hG4bK1")
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! Small helpers for SIP URIs and name-addr header values
+//! (`"Bob" <sip:bob@example.com;lr>;tag=abc`).
+
+use core::fmt::Write;
+
+use crate::{Result, SipError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SipUri {
+    pub scheme: String,
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub params: Vec<(String, Option<String>)>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl SipUri {
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        let (scheme, rest) = input
+            .split_once(':')
+            .ok_or(SipError::Invalid("uri scheme"))?;
+        if !scheme.eq_ignore_ascii_case("sip") && !scheme.eq_ignore_ascii_case("sips") {
+            return Err(SipError::Invalid("uri scheme"));
+        }
+
+        let (rest, raw_headers) = match rest.split_once('?') {
+            Some((r, h)) => (r, Some(h)),
+            None => (rest, None),
+        };
+
+        // The user part may itself contain ';' (user parameters), so split
+        // on the last '@' before looking at URI parameters.
+        let (user, host_part) = match rest.rfind('@') {
+            Some(idx) => (Some(rest[..idx].to_string()), &rest[idx + 1..]),
+            None => (None, rest),
+        };
+
+        let mut parts = host_part.split(';');
+        let hostport = parts.next().unwrap_or("");
+        let (host, port) = split_host_port(hostport)?;
+        if host.is_empty() {
+            return Err(SipError::Invalid("uri host"));
+        }
+
+        let params = parts.filter(|p| !p.is_empty()).map(split_param).collect();
+
+        let headers = raw_headers
+            .map(|h| {
+                h.split('&')
+                    .filter(|p| !p.is_empty())
+                    .map(|p| match p.split_once('=') {
+                        Some((k, v)) => (k.to_string(), v.to_string()),
+                        None => (p.to_string(), String::new()),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            scheme: scheme.to_ascii_lowercase(),
+            user,
+            host: host.to_string(),
+            port,
+            params,
+            headers,
+        })
+    }
+
+    /// Look up a URI parameter. Flag parameters such as `lr` return `Some("")`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        find_param(&self.params, name)
+    }
+
+    pub fn has_param(&self, name: &str) -> bool {
+        self.param(name).is_some()
+    }
+
+    pub fn is_sips(&self) -> bool {
+        self.scheme == "sips"
+    }
+
+    /// Render the URI with its `?headers` component removed, e.g. for use
+    /// as a Request-URI.
+    pub fn without_headers(&self) -> SipUri {
+        let mut out = self.clone();
+        out.headers.clear();
+        out
+    }
+}
+
+impl core::fmt::Display for SipUri {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}:", self.scheme)?;
+        if let Some(user) = &self.user {
+            write!(f, "{}@", user)?;
+        }
+        f.write_str(&self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        write_params(f, &self.params)?;
+        for (i, (k, v)) in self.headers.iter().enumerate() {
+            let sep = if i == 0 { '?' } else { '&' };
+            write!(f, "{}{}={}", sep, k, v)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `name-addr` / `addr-spec` header value as used by From, To, Contact,
+/// Route and Record-Route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameAddr {
+    pub display_name: Option<String>,
+    pub uri: String,
+    pub params: Vec<(String, Option<String>)>,
+}
+
+impl NameAddr {
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+
+        if let Some(open) = find_unquoted(input, '<') {
+            let close = input[open..]
+                .find('>')
+                .map(|i| i + open)
+                .ok_or(SipError::Invalid("unterminated name-addr"))?;
+
+            let display = input[..open].trim().trim_matches('"').trim();
+            let display_name = if display.is_empty() {
+                None
+            } else {
+                Some(display.to_string())
+            };
+
+            let params = input[close + 1..]
+                .split(';')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(split_param)
+                .collect();
+
+            Ok(Self {
+                display_name,
+                uri: input[open + 1..close].trim().to_string(),
+                params,
+            })
+        } else {
+            // Bare addr-spec: anything after the first ';' is a header
+            // parameter, not a URI parameter (RFC 3261 §20).
+            let mut parts = input.split(';');
+            let uri = parts.next().unwrap_or("").trim();
+            if uri.is_empty() {
+                return Err(SipError::Invalid("empty name-addr"));
+            }
+            let params = parts
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(split_param)
+                .collect();
+
+            Ok(Self {
+                display_name: None,
+                uri: uri.to_string(),
+                params,
+            })
+        }
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        find_param(&self.params, name)
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.param("tag")
+    }
+
+    pub fn sip_uri(&self) -> Result<SipUri> {
+        SipUri::parse(&self.uri)
+    }
+
+    /// Same value with the `tag` parameter removed.
+    pub fn without_tag(&self) -> NameAddr {
+        let mut out = self.clone();
+        out.params.retain(|(k, _)| !k.eq_ignore_ascii_case("tag"));
+        out
+    }
+}
+
+impl core::fmt::Display for NameAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if let Some(name) = &self.display_name {
+            write!(f, "\"{}\" ", name)?;
+        }
+        write!(f, "<{}>", self.uri)?;
+        write_params(f, &self.params)
+    }
+}
+
+/// Split a header value that may carry several comma-separated entries
+/// (e.g. `Record-Route: <sip:p1;lr>, <sip:p2;lr>`), ignoring commas inside
+/// quotes or angle brackets.
+pub fn split_header_values(value: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut in_quotes = false;
+    let mut in_brackets = false;
+    let mut start = 0;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '<' if !in_quotes => in_brackets = true,
+            '>' if !in_quotes => in_brackets = false,
+            ',' if !in_quotes && !in_brackets => {
+                let part = value[start..i].trim();
+                if !part.is_empty() {
+                    out.push(part);
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    let last = value[start..].trim();
+    if !last.is_empty() {
+        out.push(last);
+    }
+    out
+}
+
+pub(crate) fn split_host_port(hostport: &str) -> Result<(&str, Option<u16>)> {
+    let (host, port) = if hostport.starts_with('[') {
+        let end = hostport
+            .find(']')
+            .ok_or(SipError::Invalid("uri ipv6 host"))?;
+        let host = &hostport[..=end];
+        let port = hostport[end + 1..].strip_prefix(':');
+        (host, port)
+    } else {
+        match hostport.split_once(':') {
+            Some((h, p)) => (h, Some(p)),
+            None => (hostport, None),
+        }
+    };
+
+    let port = match port {
+        Some(p) => Some(p.parse().map_err(|_| SipError::Invalid("uri port"))?),
+        None => None,
+    };
+    Ok((host, port))
+}
+
+pub(crate) fn split_param(param: &str) -> (String, Option<String>) {
+    match param.split_once('=') {
+        Some((k, v)) => (k.trim().to_string(), Some(v.trim().to_string())),
+        None => (param.trim().to_string(), None),
+    }
+}
+
+fn find_param<'a>(params: &'a [(String, Option<String>)], name: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_deref().unwrap_or(""))
+}
+
+fn find_unquoted(input: &str, needle: char) -> Option<usize> {
+    let mut in_quotes = false;
+    for (i, c) in input.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c == needle && !in_quotes => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+pub(crate) fn write_params(
+    f: &mut core::fmt::Formatter<'_>,
+    params: &[(String, Option<String>)],
+) -> core::fmt::Result {
+    for (k, v) in params {
+        match v {
+            Some(v) => write!(f, ";{}={}", k, v)?,
+            None => write!(f, ";{}", k)?,
+        }
+    }
+    Ok(())
+}
+
+/// Render a URI in angle brackets, as used for Route and Contact values.
+pub(crate) fn bracketed(uri: &str) -> Result<String> {
+    let mut out = String::new();
+    write!(out, "<{}>", uri).map_err(|_| SipError::Capacity)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sip_uri_with_params() {
+        let uri = SipUri::parse("sip:alice@192.0.2.1:5070;transport=tcp;lr?Subject=hi").unwrap();
+        assert_eq!(uri.user.as_deref(), Some("alice"));
+        assert_eq!(uri.host, "192.0.2.1");
+        assert_eq!(uri.port, Some(5070));
+        assert_eq!(uri.param("transport"), Some("tcp"));
+        assert!(uri.has_param("lr"));
+        assert_eq!(uri.headers.len(), 1);
+        assert_eq!(
+            uri.without_headers().to_string(),
+            "sip:alice@192.0.2.1:5070;transport=tcp;lr"
+        );
+    }
+
+    #[test]
+    fn parses_name_addr_and_bare_addr_spec() {
+        let na = NameAddr::parse("\"Bob\" <sip:bob@example.com;lr>;tag=abc").unwrap();
+        assert_eq!(na.display_name.as_deref(), Some("Bob"));
+        assert_eq!(na.uri, "sip:bob@example.com;lr");
+        assert_eq!(na.tag(), Some("abc"));
+        assert_eq!(na.without_tag().to_string(), "\"Bob\" <sip:bob@example.com;lr>");
+
+        let bare = NameAddr::parse("sip:bob@example.com;tag=xyz").unwrap();
+        assert_eq!(bare.uri, "sip:bob@example.com");
+        assert_eq!(bare.tag(), Some("xyz"));
+    }
+
+    #[test]
+    fn splits_comma_separated_values() {
+        let values = split_header_values("<sip:p1.example.com;lr>, \"A, B\" <sip:p2;lr>");
+        assert_eq!(values, vec!["<sip:p1.example.com;lr>", "\"A, B\" <sip:p2;lr>"]);
+    }
+}
@@ -0,0 +1,218 @@
+//! Via header handling for server transactions: stamping `received` /
+//! `rport` on incoming requests and working out where responses go
+//! (RFC 3261 §18.2, RFC 3581).
+
+use std::net::{IpAddr, SocketAddr};
+
+use crate::message::{Header, HeaderList, Request, Response};
+use crate::transport::{TransportAddr, TransportKind};
+use crate::uri::{split_header_values, split_host_port, split_param, write_params};
+use crate::{Result, SipError};
+
+/// One via-parm: `SIP/2.0/UDP host:port;branch=z9hG4bK..;rport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Via {
+    pub protocol: String,
+    pub host: String,
+    pub port: Option<u16>,
+    pub params: Vec<(String, Option<String>)>,
+}
+
+impl Via {
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        let (protocol, rest) = input
+            .split_once(char::is_whitespace)
+            .ok_or(SipError::Invalid("via sent-protocol"))?;
+
+        let mut parts = rest.trim_start().split(';');
+        let (host, port) = split_host_port(parts.next().unwrap_or("").trim())?;
+        if host.is_empty() {
+            return Err(SipError::Invalid("via sent-by"));
+        }
+
+        Ok(Self {
+            protocol: protocol.to_string(),
+            host: host.to_string(),
+            port,
+            params: parts
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(split_param)
+                .collect(),
+        })
+    }
+
+    pub fn transport(&self) -> Option<TransportKind> {
+        TransportKind::parse(self.protocol.rsplit('/').next()?)
+    }
+
+    /// Look up a parameter. Flag parameters such as `rport` return `Some("")`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_deref().unwrap_or(""))
+    }
+
+    pub fn set_param(&mut self, name: &str, value: &str) {
+        match self
+            .params
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        {
+            Some((_, v)) => *v = Some(value.to_string()),
+            None => self.params.push((name.to_string(), Some(value.to_string()))),
+        }
+    }
+}
+
+impl core::fmt::Display for Via {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} {}", self.protocol, self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        write_params(f, &self.params)
+    }
+}
+
+/// The top Via of a message.
+pub fn top_via(headers: &HeaderList) -> Result<Via> {
+    let header = headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case("Via"))
+        .ok_or(SipError::Invalid("missing Via"))?;
+    let first = split_header_values(&header.value)
+        .into_iter()
+        .next()
+        .ok_or(SipError::Invalid("empty Via"))?;
+    Via::parse(first)
+}
+
+/// Record where a request really came from in its top Via: `received`
+/// when the sent-by host isn't the source address (RFC 3261 §18.2.1), and
+/// `received` plus the source port when the client asked with an empty
+/// `rport` (RFC 3581 §4).
+pub fn stamp_received(req: &mut Request, source: TransportAddr) -> Result<()> {
+    let header = req
+        .headers
+        .iter_mut()
+        .find(|h| h.name.eq_ignore_ascii_case("Via"))
+        .ok_or(SipError::Invalid("missing Via"))?;
+
+    let mut values = split_header_values(&header.value);
+    let first = values.first().ok_or(SipError::Invalid("empty Via"))?;
+    let mut via = Via::parse(first)?;
+
+    let source_ip = source.addr.ip();
+    let wants_rport = via.param("rport") == Some("");
+    if wants_rport || parse_ip(&via.host) != Some(source_ip) {
+        via.set_param("received", &source_ip.to_string());
+    }
+    if wants_rport {
+        via.set_param("rport", &source.addr.port().to_string());
+    }
+
+    let stamped = via.to_string();
+    values[0] = &stamped;
+    *header = Header::new("Via", &values.join(", "))?;
+    Ok(())
+}
+
+/// Where to send a response, from its top Via (RFC 3261 §18.2.2,
+/// RFC 3581 §4). `source` is where the request arrived from; responses on
+/// a reliable transport go back over that same connection.
+pub fn response_target(resp: &Response, source: TransportAddr) -> Result<TransportAddr> {
+    let via = top_via(&resp.headers)?;
+    let transport = via.transport().unwrap_or(source.transport);
+
+    if transport.is_reliable() && transport == source.transport {
+        return Ok(source);
+    }
+
+    let port = via
+        .param("rport")
+        .and_then(|p| p.parse().ok())
+        .or(via.port)
+        .unwrap_or(transport.default_port());
+
+    let ip = via
+        .param("maddr")
+        .and_then(parse_ip)
+        .or_else(|| via.param("received").and_then(parse_ip))
+        .or_else(|| parse_ip(&via.host))
+        .ok_or(SipError::Invalid("via sent-by not an address"))?;
+
+    Ok(TransportAddr::new(transport, SocketAddr::new(ip, port)))
+}
+
+fn parse_ip(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[').trim_end_matches(']').parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Method;
+
+    fn request_with_via(via: &str) -> Request {
+        let mut req = Request::new(Method::Options, "sip:alice@example.com").unwrap();
+        req.add_header(Header::new("Via", via).unwrap()).unwrap();
+        req
+    }
+
+    fn response_from(req: &Request) -> Response {
+        let mut resp = Response::new(200, "OK").unwrap();
+        for via in req.headers.iter().filter(|h| h.name == "Via") {
+            resp.add_header(via.clone());
+        }
+        resp
+    }
+
+    #[test]
+    fn rport_sends_response_to_source_port() {
+        let source = TransportAddr::udp("198.51.100.7:40000".parse().unwrap());
+        let mut req = request_with_via("SIP/2.0/UDP 10.0.0.5:5060;branch=z9hG4bK1;rport");
+        stamp_received(&mut req, source).unwrap();
+
+        let via = top_via(&req.headers).unwrap();
+        assert_eq!(via.param("received"), Some("198.51.100.7"));
+        assert_eq!(via.param("rport"), Some("40000"));
+        assert_eq!(response_target(&response_from(&req), source).unwrap(), source);
+    }
+
+    #[test]
+    fn received_without_rport_uses_sent_by_port() {
+        let source = TransportAddr::udp("198.51.100.7:40000".parse().unwrap());
+        let mut req = request_with_via("SIP/2.0/UDP pbx.example.com;branch=z9hG4bK1");
+        stamp_received(&mut req, source).unwrap();
+
+        let target = response_target(&response_from(&req), source).unwrap();
+        assert_eq!(target.addr, "198.51.100.7:5060".parse().unwrap());
+    }
+
+    #[test]
+    fn only_top_via_is_stamped_and_maddr_wins() {
+        let source = TransportAddr::udp("192.0.2.1:5060".parse().unwrap());
+        let mut req = request_with_via(
+            "SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK1;maddr=239.0.0.9, SIP/2.0/UDP 10.0.0.1",
+        );
+        stamp_received(&mut req, source).unwrap();
+        assert_eq!(
+            req.headers[0].value,
+            "SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK1;maddr=239.0.0.9, SIP/2.0/UDP 10.0.0.1"
+        );
+
+        let target = response_target(&response_from(&req), source).unwrap();
+        assert_eq!(target.addr, "239.0.0.9:5060".parse().unwrap());
+    }
+
+    #[test]
+    fn reliable_responses_reuse_the_connection() {
+        let source = TransportAddr::new(TransportKind::Tcp, "198.51.100.7:51000".parse().unwrap());
+        let mut req = request_with_via("SIP/2.0/TCP 10.0.0.5:5060;branch=z9hG4bK1");
+        stamp_received(&mut req, source).unwrap();
+        assert_eq!(response_target(&response_from(&req), source).unwrap(), source);
+    }
+}